@@ -0,0 +1,805 @@
+use crate::latency::LatencyModel;
+use crate::md;
+use crate::observer::BookObserver;
+use crate::queue_sim::{HypotheticalOrder, SimulatedOrder};
+use crate::schedule;
+use crate::snapshot_builder::{Book, SnapshotBuilder};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+/// how long liquidity taken by strategy orders stays gone from the book,
+/// the real book never sees strategy orders so this is all the impact there is
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ImpactPersistence {
+    /// the displayed liquidity is back with the next event of the instrument
+    Transient,
+    /// what was taken comes back exponentially with this half-life in clock units
+    Decay(i64),
+    /// gone until the level leaves the book
+    Permanent,
+}
+
+impl ImpactPersistence {
+    /// "transient", "permanent" or "decay=500" with the half-life in milliseconds
+    pub fn parse(s: &str) -> Result<ImpactPersistence, Box<dyn Error>> {
+        return match s {
+            "transient" => Ok(ImpactPersistence::Transient),
+            "permanent" => Ok(ImpactPersistence::Permanent),
+            _ => match s.strip_prefix("decay=") {
+                Some(millis) => {
+                    let millis = millis.parse::<i64>()?;
+                    if millis <= 0 {
+                        return Err(format!("half-life must be positive in '{}'", s).into());
+                    }
+                    Ok(ImpactPersistence::Decay(millis * 1000))
+                }
+                None => Err(format!("unknown impact persistence '{}'", s).into()),
+            },
+        };
+    }
+}
+
+/// how a strategy order meets the book
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StrategyOrderType {
+    /// takes what it crosses, the rest queues at its price
+    Limit,
+    /// takes what it can at any price, the rest is dropped
+    Market,
+}
+
+/// an order of the strategy, prices in book units
+#[derive(Debug, Copy, Clone)]
+pub struct StrategyOrder {
+    /// SecurityID of the instrument
+    pub inst_id: i32,
+    /// Bid to buy, Ask to sell
+    pub side: md::Side,
+    /// ignored for market orders
+    pub price: i64,
+    /// shares to trade
+    pub quantity: i64,
+    /// limit or market
+    pub order_type: StrategyOrderType,
+}
+
+/// an execution of a strategy order, price in book units
+#[derive(Debug, Copy, Clone)]
+pub struct StrategyFill {
+    /// the id given by Context::submit
+    pub id: u64,
+    /// SecurityID of the instrument
+    pub inst_id: i32,
+    /// side of the strategy order
+    pub side: md::Side,
+    /// clock of the event that filled it
+    pub timestamp: i64,
+    /// execution price
+    pub price: i64,
+    /// shares executed
+    pub quantity: i64,
+    /// took displayed liquidity rather than waiting in a queue
+    pub aggressive: bool,
+}
+
+// a decision of the strategy on its way to the exchange
+enum Request {
+    Submit(u64, StrategyOrder),
+    Cancel(u64),
+}
+
+/// what a strategy sees of the engine and how it trades
+pub struct Context {
+    now: i64,
+    latency: LatencyModel,
+    next_id: u64,
+    // (arrival clock, request) in the order they were made
+    requests: Vec<(i64, Request)>,
+    // key: SecurityID
+    positions: BTreeMap<i32, i64>,
+    // book units, negative after buying
+    cash: i64,
+}
+
+impl Context {
+    fn new(latency: LatencyModel) -> Context {
+        Context {
+            now: 0,
+            latency,
+            next_id: 1,
+            requests: Vec::new(),
+            positions: BTreeMap::new(),
+            cash: 0,
+        }
+    }
+
+    /// clock of the event or snapshot the strategy is called for
+    pub fn now(&self) -> i64 {
+        return self.now;
+    }
+
+    // clock a request made now reaches the book
+    fn arrival(&mut self) -> i64 {
+        let millis = md::ExchangeTime::from_clock(self.now).millis;
+        return self.now + self.latency.sample(millis);
+    }
+
+    /// the order reaches the book after the latency, returns its id
+    pub fn submit(&mut self, order: StrategyOrder) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let arrival = self.arrival();
+        self.requests.push((arrival, Request::Submit(id, order)));
+        return id;
+    }
+
+    /// whatever is left of the order when the cancel arrives is dropped
+    pub fn cancel(&mut self, id: u64) {
+        let arrival = self.arrival();
+        self.requests.push((arrival, Request::Cancel(id)));
+    }
+
+    /// shares held, negative when short
+    pub fn position(&self, inst_id: i32) -> i64 {
+        return self.positions.get(&inst_id).copied().unwrap_or(0);
+    }
+
+    /// in book units, what was received for selling minus what was paid for buying
+    pub fn cash(&self) -> i64 {
+        return self.cash;
+    }
+
+    fn apply(&mut self, fill: &StrategyFill) {
+        let signed = match fill.side {
+            md::Side::Bid => fill.quantity,
+            _ => -fill.quantity,
+        };
+        *self.positions.entry(fill.inst_id).or_insert(0) += signed;
+        self.cash -= signed * fill.price;
+    }
+}
+
+/// decides what to trade, every callback may submit and cancel orders
+pub trait Strategy {
+    /// after every event of the replay, with the book it changed
+    fn on_event(&mut self, _context: &mut Context, _book: &Book) {}
+
+    /// at every snapshot time, once per book
+    fn on_snapshot(&mut self, _context: &mut Context, _book: &Book) {}
+
+    /// whenever a strategy order is executed, fully or partly
+    fn on_fill(&mut self, _context: &mut Context, _fill: &StrategyFill) {}
+}
+
+// executions and cancels of the real book, passive strategy orders queue among them
+#[derive(Default)]
+struct Recorder {
+    cancels: Vec<(md::Order, i64)>,
+    trades: Vec<md::Trade>,
+}
+
+impl BookObserver for Recorder {
+    fn on_order_cancelled(&mut self, _book: &Book, order: &md::Order, quantity: i64) {
+        self.cancels.push((order.clone(), quantity));
+    }
+
+    fn on_order_executed(&mut self, _book: &Book, trade: &md::Trade) {
+        self.trades.push(trade.clone());
+    }
+}
+
+// displayed liquidity taken by strategy orders
+struct Taken {
+    quantity: f64,
+    // clock it was last updated
+    timestamp: i64,
+}
+
+/// runs a strategy against the replay of a SnapshotBuilder, strategy orders
+/// match against the rebuilt books but never change them
+pub struct Backtester<S: Strategy> {
+    builder: SnapshotBuilder,
+    strategy: S,
+    context: Context,
+    impact: ImpactPersistence,
+    snapshot_times: Vec<i64>,
+    recorder: Arc<Mutex<Recorder>>,
+    // key: (SecurityID, side, price)
+    taken_: HashMap<(i32, bool, i64), Taken>,
+    // key: (arrival clock, order id), the time priority among strategy orders
+    resting_: BTreeMap<(i64, u64), SimulatedOrder>,
+    fills_: Vec<StrategyFill>,
+}
+
+impl<S: Strategy> Backtester<S> {
+    /// runs strategy over the events of builder with no latency and transient impact
+    pub fn new(mut builder: SnapshotBuilder, strategy: S) -> Backtester<S> {
+        let recorder = Arc::new(Mutex::new(Recorder::default()));
+        builder.add_observer(Box::new(Arc::clone(&recorder)));
+        Backtester {
+            builder,
+            strategy,
+            context: Context::new(LatencyModel::constant(0)),
+            impact: ImpactPersistence::Transient,
+            snapshot_times: Vec::new(),
+            recorder,
+            taken_: HashMap::new(),
+            resting_: BTreeMap::new(),
+            fills_: Vec::new(),
+        }
+    }
+
+    /// clock units between a decision and its arrival at the book
+    pub fn set_latency(&mut self, latency: i64) {
+        self.context.latency = LatencyModel::constant(latency);
+    }
+
+    /// latencies between a decision and its arrival drawn from the model
+    pub fn set_latency_model(&mut self, model: LatencyModel) {
+        self.context.latency = model;
+    }
+
+    /// how long taken liquidity stays gone, Transient by default
+    pub fn set_impact_persistence(&mut self, impact: ImpactPersistence) {
+        self.impact = impact;
+    }
+
+    /// clocks to call Strategy::on_snapshot at, e.g. Schedule::timestamps
+    pub fn set_snapshot_times(&mut self, mut timestamps: Vec<i64>) {
+        timestamps.sort_unstable();
+        self.snapshot_times = timestamps;
+    }
+
+    /// the strategy, e.g. to read its state after the run
+    pub fn strategy(&self) -> &S {
+        return &self.strategy;
+    }
+
+    /// orders, position and cash of the strategy
+    pub fn context(&self) -> &Context {
+        return &self.context;
+    }
+
+    /// every fill of the strategy in the order it happened
+    pub fn fills(&self) -> &[StrategyFill] {
+        return &self.fills_;
+    }
+
+    /// replay every event, delivering strategy orders as they arrive
+    pub fn run(&mut self) {
+        let mut snapshot_idx = 0;
+        loop {
+            let next = self.builder.next_event_time().unwrap_or(i64::MAX);
+            let snapshot = self.snapshot_times.get(snapshot_idx).copied();
+            // a snapshot at the same clock as an event is taken before it
+            let until = snapshot.map_or(next, |snapshot| snapshot.min(next));
+            self.deliver_until(until);
+
+            if let Some(snapshot) = snapshot.filter(|snapshot| *snapshot <= next) {
+                snapshot_idx += 1;
+                self.context.now = snapshot;
+                for book in self.builder.books() {
+                    self.strategy.on_snapshot(&mut self.context, book);
+                }
+                continue;
+            }
+
+            let inst_id = match self.builder.step() {
+                Some(Some(inst_id)) => inst_id,
+                // an exchange snapshot that left no book
+                Some(None) => continue,
+                None => break,
+            };
+            self.context.now = next;
+            if self.impact == ImpactPersistence::Transient {
+                self.taken_.retain(|(inst, _, _), _| *inst != inst_id);
+            }
+            self.update_resting(inst_id, next);
+            if let Some(book) = self.builder.book(inst_id) {
+                self.strategy.on_event(&mut self.context, book);
+            }
+        }
+        // requests made after the last event
+        self.deliver_until(i64::MAX);
+    }
+
+    // requests arriving before the clock, in the order they arrive
+    fn deliver_until(&mut self, until: i64) {
+        while let Some(idx) = self.next_request(until) {
+            let (arrival, request) = self.context.requests.remove(idx);
+            match request {
+                Request::Submit(id, order) => self.handle_submit(id, order, arrival),
+                Request::Cancel(id) => self.handle_cancel(id),
+            }
+        }
+    }
+
+    fn next_request(&self, until: i64) -> Option<usize> {
+        let requests = &self.context.requests;
+        let (idx, (arrival, _)) = requests
+            .iter()
+            .enumerate()
+            .min_by_key(|(idx, (arrival, _))| (*arrival, *idx))?;
+        if *arrival >= until && until != i64::MAX {
+            return None;
+        }
+        return Some(idx);
+    }
+
+    // displayed liquidity at the level less what strategy orders took of it
+    fn available(
+        &mut self,
+        inst_id: i32,
+        side: md::Side,
+        price: i64,
+        displayed: i64,
+        now: i64,
+    ) -> i64 {
+        let key = (inst_id, matches!(side, md::Side::Bid), price);
+        let taken = match self.taken_.get_mut(&key) {
+            Some(taken) => taken,
+            None => return displayed,
+        };
+        if let ImpactPersistence::Decay(half_life) = self.impact {
+            let elapsed = (now - taken.timestamp).max(0) as f64;
+            taken.quantity *= 0.5f64.powf(elapsed / half_life as f64);
+            taken.timestamp = now;
+        }
+        return (displayed - taken.quantity.round() as i64).max(0);
+    }
+
+    fn take(&mut self, inst_id: i32, side: md::Side, price: i64, quantity: i64, now: i64) {
+        let key = (inst_id, matches!(side, md::Side::Bid), price);
+        let taken = self.taken_.entry(key).or_insert(Taken {
+            quantity: 0.0,
+            timestamp: now,
+        });
+        taken.quantity += quantity as f64;
+        taken.timestamp = now;
+    }
+
+    fn handle_submit(&mut self, id: u64, order: StrategyOrder, arrival: i64) {
+        let book = match self.builder.book(order.inst_id) {
+            Some(book) => book,
+            // nothing to trade against yet, the order is dropped
+            None => return,
+        };
+        let time = md::ExchangeTime::from_clock(arrival);
+        let opposite = order.side.opposite();
+        let levels: Vec<(i64, i64)> = book.depth(opposite).collect();
+        let matching = schedule::is_matching(time.millis);
+
+        // walk the opposite side like the exchange would
+        let mut remaining = order.quantity;
+        for (price, displayed) in levels {
+            let crosses = match (order.order_type, order.side) {
+                (StrategyOrderType::Market, _) => true,
+                (_, md::Side::Bid) => price <= order.price,
+                _ => price >= order.price,
+            };
+            if !matching || !crosses || remaining == 0 {
+                break;
+            }
+            let available = self.available(order.inst_id, opposite, price, displayed, arrival);
+            let quantity = available.min(remaining);
+            if quantity == 0 {
+                continue;
+            }
+            self.take(order.inst_id, opposite, price, quantity, arrival);
+            remaining -= quantity;
+            self.fill(StrategyFill {
+                id,
+                inst_id: order.inst_id,
+                side: order.side,
+                timestamp: arrival,
+                price,
+                quantity,
+                aggressive: true,
+            });
+        }
+        if remaining == 0 || order.order_type == StrategyOrderType::Market {
+            return;
+        }
+
+        let mut resting = SimulatedOrder::new(HypotheticalOrder {
+            inst_id: order.inst_id,
+            side: order.side,
+            price: order.price,
+            quantity: remaining,
+            millis: time.millis,
+            cancel_millis: None,
+        });
+        match self.builder.book(order.inst_id) {
+            Some(book) => resting.join(book, time),
+            None => return,
+        }
+        // earlier strategy orders at the price are ahead of it too
+        let ahead: i64 = self
+            .resting_
+            .values()
+            .filter(|other| same_queue(other, &resting))
+            .map(|other| other.remaining_quantity())
+            .sum();
+        resting.entry_queue_ahead += ahead;
+        resting.queue_ahead += ahead;
+        self.resting_.insert((arrival, id), resting);
+    }
+
+    fn handle_cancel(&mut self, id: u64) {
+        let key = match self.resting_.keys().find(|(_, other)| *other == id) {
+            Some(key) => *key,
+            None => return,
+        };
+        let cancelled = self.resting_.remove(&key).unwrap();
+        // strategy orders that arrived after it at the price move up
+        for (_, other) in self.resting_.range_mut(key..) {
+            if same_queue(other, &cancelled) {
+                other.queue_ahead = (other.queue_ahead - cancelled.remaining_quantity()).max(0);
+            }
+        }
+    }
+
+    // passive orders of the instrument see the cancels and executions of the event
+    fn update_resting(&mut self, inst_id: i32, now: i64) {
+        let (cancels, trades) = {
+            let mut recorder = self.recorder.lock().unwrap();
+            (
+                std::mem::take(&mut recorder.cancels),
+                std::mem::take(&mut recorder.trades),
+            )
+        };
+        if self.impact == ImpactPersistence::Permanent {
+            // a level that is gone takes what was taken of it along,
+            // and so does a book that is gone
+            let book = self.builder.book(inst_id);
+            self.taken_.retain(|(inst, bid, price), _| {
+                let side = if *bid { md::Side::Bid } else { md::Side::Ask };
+                *inst != inst_id || book.is_some_and(|book| book.depth_at(side, *price) > 0)
+            });
+        }
+
+        let mut fills = Vec::new();
+        for ((_, id), resting) in self.resting_.iter_mut() {
+            if resting.order.inst_id != inst_id {
+                continue;
+            }
+            let before = resting.fills.len();
+            for (order, quantity) in cancels.iter() {
+                resting.on_cancel(order, *quantity);
+            }
+            for trade in trades.iter() {
+                resting.on_trade(trade);
+            }
+            for fill in resting.fills[before..].iter() {
+                fills.push(StrategyFill {
+                    id: *id,
+                    inst_id,
+                    side: resting.order.side,
+                    timestamp: now,
+                    price: fill.price,
+                    quantity: fill.quantity,
+                    aggressive: false,
+                });
+            }
+        }
+        self.resting_
+            .retain(|_, resting| resting.remaining_quantity() > 0);
+        for fill in fills {
+            self.fill(fill);
+        }
+    }
+
+    fn fill(&mut self, fill: StrategyFill) {
+        self.context.apply(&fill);
+        self.fills_.push(fill);
+        self.strategy.on_fill(&mut self.context, &fill);
+    }
+}
+
+// resting at the same price of the same book
+fn same_queue(a: &SimulatedOrder, b: &SimulatedOrder) -> bool {
+    return a.order.inst_id == b.order.inst_id
+        && matches!(
+            (a.order.side, b.order.side),
+            (md::Side::Bid, md::Side::Bid) | (md::Side::Ask, md::Side::Ask)
+        )
+        && a.order.price == b.order.price;
+}
+
+/// splits a parent order into equal child orders over a time window,
+/// each taking liquidity at once or joining the touch
+pub struct TwapStrategy {
+    inst_id: i32,
+    side: md::Side,
+    quantity: i64,
+    // clocks the child orders are sent at
+    times: Vec<i64>,
+    passive: bool,
+    sent: usize,
+}
+
+impl TwapStrategy {
+    /// children are rounded to lots of 100 shares, the last one takes the rest
+    pub fn new(
+        inst_id: i32,
+        side: md::Side,
+        quantity: i64,
+        times: Vec<i64>,
+        passive: bool,
+    ) -> TwapStrategy {
+        TwapStrategy {
+            inst_id,
+            side,
+            quantity,
+            times,
+            passive,
+            sent: 0,
+        }
+    }
+
+    /// "2290,buy,10000,09:35:00,10:35:00,12" sends 12 children from 09:35 to 10:35
+    /// of the date, the first at the start
+    pub fn parse(s: &str, date: i32, passive: bool) -> Result<TwapStrategy, Box<dyn Error>> {
+        let fields: Vec<&str> = s.split(',').map(|field| field.trim()).collect();
+        if fields.len() != 6 {
+            return Err(format!("invalid twap '{}'", s).into());
+        }
+        let side = match fields[1] {
+            "bid" | "buy" => md::Side::Bid,
+            "ask" | "sell" => md::Side::Ask,
+            side => return Err(format!("unknown side '{}' in '{}'", side, s).into()),
+        };
+        let start = md::ExchangeTime::parse(date, fields[3])?.to_clock();
+        let end = md::ExchangeTime::parse(date, fields[4])?.to_clock();
+        let slices = fields[5].parse::<i64>()?;
+        if slices <= 0 || end < start {
+            return Err(format!("invalid twap window or slices in '{}'", s).into());
+        }
+        let step = (end - start) / slices;
+        let times = (0..slices).map(|slice| start + slice * step).collect();
+        return Ok(TwapStrategy::new(
+            fields[0].parse::<i32>()?,
+            side,
+            fields[2].parse::<i64>()?,
+            times,
+            passive,
+        ));
+    }
+
+    fn child_quantity(&self) -> i64 {
+        let slices = self.times.len() as i64;
+        let lot = self.quantity / slices / 100 * 100;
+        if self.sent as i64 == slices - 1 {
+            return self.quantity - lot * (slices - 1);
+        }
+        return lot;
+    }
+}
+
+impl Strategy for TwapStrategy {
+    fn on_event(&mut self, context: &mut Context, book: &Book) {
+        if book.inst_id() != self.inst_id {
+            return;
+        }
+        while self.sent < self.times.len() && context.now() >= self.times[self.sent] {
+            let touch = match (self.side, self.passive) {
+                (md::Side::Bid, true) | (md::Side::Ask, false) => book.best_bid(),
+                _ => book.best_ask(),
+            };
+            let quantity = self.child_quantity();
+            self.sent += 1;
+            let (price, order_type) = match touch {
+                Some((price, _)) => (price, StrategyOrderType::Limit),
+                None if !self.passive => (0, StrategyOrderType::Market),
+                None => continue,
+            };
+            if quantity > 0 {
+                context.submit(StrategyOrder {
+                    inst_id: self.inst_id,
+                    side: self.side,
+                    price,
+                    quantity,
+                    order_type,
+                });
+            }
+        }
+    }
+
+    // on a schedule, slices go out on time even when the instrument is quiet
+    fn on_snapshot(&mut self, context: &mut Context, book: &Book) {
+        self.on_event(context, book);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md::testing::{clock, order, trade};
+    use crate::md::Side;
+    use crate::snapshot_builder::ClockType;
+
+    enum Step {
+        Submit(StrategyOrder),
+        Cancel(u64),
+    }
+
+    // requests to make on the nth event, with their latency
+    struct Script {
+        steps: Vec<(usize, i64, Step)>,
+        events: usize,
+        // clocks of on_snapshot
+        snapshots: Vec<i64>,
+    }
+
+    impl Strategy for Script {
+        fn on_event(&mut self, context: &mut Context, _book: &Book) {
+            for (event, latency, step) in self.steps.iter() {
+                if *event != self.events {
+                    continue;
+                }
+                context.latency = LatencyModel::constant(*latency);
+                match step {
+                    Step::Submit(order) => {
+                        context.submit(*order);
+                    }
+                    Step::Cancel(id) => context.cancel(*id),
+                }
+            }
+            self.events += 1;
+        }
+
+        fn on_snapshot(&mut self, context: &mut Context, _book: &Book) {
+            self.snapshots.push(context.now());
+        }
+    }
+
+    fn buy(price: i64, quantity: i64, order_type: StrategyOrderType) -> Step {
+        return Step::Submit(StrategyOrder {
+            inst_id: 2290,
+            side: Side::Bid,
+            price,
+            quantity,
+            order_type,
+        });
+    }
+
+    fn backtester(
+        orders: Vec<md::Order>,
+        trades: Vec<md::Trade>,
+        steps: Vec<(usize, i64, Step)>,
+    ) -> Backtester<Script> {
+        let orders = orders.into_iter().map(Arc::new).collect();
+        let trades = trades.into_iter().map(Arc::new).collect();
+        let mut builder = SnapshotBuilder::new(orders, trades);
+        builder.set_clock_type(ClockType::Exchange);
+        let script = Script {
+            steps,
+            events: 0,
+            snapshots: Vec::new(),
+        };
+        return Backtester::new(builder, script);
+    }
+
+    fn filled(backtester: &Backtester<Script>, id: u64) -> i64 {
+        return backtester
+            .fills()
+            .iter()
+            .filter(|fill| fill.id == id)
+            .map(|fill| fill.quantity)
+            .sum();
+    }
+
+    // two market buys of 200 against an ask of 300, one event apart
+    fn sweep(impact: ImpactPersistence) -> Backtester<Script> {
+        let orders = vec![
+            order(2290, 1, "10:00:00.000", Side::Ask, 51000, 300),
+            order(2290, 2, "10:00:01.000", Side::Bid, 50000, 100),
+        ];
+        let steps = vec![
+            (0, 0, buy(0, 200, StrategyOrderType::Market)),
+            (1, 0, buy(0, 200, StrategyOrderType::Market)),
+        ];
+        let mut backtester = backtester(orders, Vec::new(), steps);
+        backtester.set_impact_persistence(impact);
+        backtester.run();
+        return backtester;
+    }
+
+    #[test]
+    fn empty_exchange_snapshots_do_not_stop_the_run() {
+        // all zeros before the open, so 2385 never gets a book
+        let mut empty = Book::new(2385);
+        empty.timestamp = clock("08:24:03.000");
+        empty.exchange_time = md::ExchangeTime::from_clock(empty.timestamp);
+        let orders = vec![order(2290, 1, "10:00:00.000", Side::Ask, 51000, 300)];
+        let mut for_2385 = buy(0, 100, StrategyOrderType::Market);
+        if let Step::Submit(order) = &mut for_2385 {
+            order.inst_id = 2385;
+        }
+        let steps = vec![
+            (0, 0, for_2385),
+            (0, 0, buy(0, 200, StrategyOrderType::Market)),
+        ];
+        let mut backtester = backtester(orders, Vec::new(), steps);
+        backtester
+            .builder
+            .set_exchange_snapshots(vec![Arc::new(empty.to_snapshot())]);
+        backtester.run();
+        // nothing to trade against for 2385, so it is dropped
+        assert_eq!(filled(&backtester, 1), 0);
+        assert_eq!(filled(&backtester, 2), 200);
+    }
+
+    #[test]
+    fn snapshot_times_reach_the_strategy() {
+        let orders = vec![
+            order(2290, 1, "10:00:00.000", Side::Ask, 51000, 300),
+            order(2290, 2, "10:00:05.000", Side::Ask, 51000, 300),
+        ];
+        let mut backtester = backtester(orders, Vec::new(), Vec::new());
+        let times = vec![clock("10:00:03.000"), clock("10:00:01.000")];
+        backtester.set_snapshot_times(times);
+        backtester.run();
+        let expected = vec![clock("10:00:01.000"), clock("10:00:03.000")];
+        assert_eq!(backtester.strategy().snapshots, expected);
+    }
+
+    #[test]
+    fn transient_impact_is_back_with_the_next_event() {
+        let backtester = sweep(ImpactPersistence::Transient);
+        assert_eq!(filled(&backtester, 1), 200);
+        assert_eq!(filled(&backtester, 2), 200);
+        assert!(backtester.fills().iter().all(|fill| fill.aggressive));
+        assert_eq!(backtester.context().position(2290), 400);
+        assert_eq!(backtester.context().cash(), -400 * 51000);
+    }
+
+    #[test]
+    fn permanent_impact_stays_until_the_level_is_gone() {
+        let backtester = sweep(ImpactPersistence::Permanent);
+        assert_eq!(filled(&backtester, 1), 200);
+        // the rest of a market order is dropped
+        assert_eq!(filled(&backtester, 2), 100);
+    }
+
+    #[test]
+    fn limit_order_rests_after_taking_what_it_crosses() {
+        let orders = vec![order(2290, 1, "10:00:00.000", Side::Ask, 51000, 100)];
+        let steps = vec![(0, 0, buy(51000, 300, StrategyOrderType::Limit))];
+        let mut backtester = backtester(orders, Vec::new(), steps);
+        backtester.run();
+        let fills = backtester.fills();
+        assert_eq!(fills.len(), 1);
+        assert!(fills[0].aggressive);
+        assert_eq!(fills[0].quantity, 100);
+        let resting: Vec<&SimulatedOrder> = backtester.resting_.values().collect();
+        assert_eq!(resting.len(), 1);
+        assert_eq!(resting[0].remaining_quantity(), 200);
+    }
+
+    #[test]
+    fn cancel_moves_up_orders_that_arrived_later() {
+        let orders = vec![
+            order(2290, 1, "10:00:00.000", Side::Bid, 50000, 100),
+            order(2290, 3, "10:00:00.500", Side::Bid, 50000, 500),
+            order(2290, 4, "10:00:01.000", Side::Ask, 50000, 150),
+        ];
+        let trades = vec![
+            trade(2290, 5, "10:00:01.000", 50000, 100, 1, 4),
+            trade(2290, 6, "10:00:01.000", 50000, 50, 3, 4),
+        ];
+        // order 2 is sent after order 1 but arrives first, then is cancelled,
+        // leaving only the real 100 ahead of order 1
+        let steps = vec![
+            (0, 2000, buy(50000, 100, StrategyOrderType::Limit)),
+            (0, 1000, buy(50000, 100, StrategyOrderType::Limit)),
+            (0, 3000, Step::Cancel(2)),
+        ];
+        let mut backtester = backtester(orders, trades, steps);
+        backtester.run();
+        assert_eq!(filled(&backtester, 1), 50);
+        assert_eq!(filled(&backtester, 2), 0);
+        assert!(backtester.fills().iter().all(|fill| !fill.aggressive));
+    }
+}
@@ -0,0 +1,330 @@
+use crate::md;
+use crate::observer::BookObserver;
+use crate::schedule::{CONTINUOUS_START_MILLIS, SESSIONS};
+use crate::snapshot_builder::Book;
+use serde::Serialize;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// when a bar is complete
+#[derive(Debug, Copy, Clone)]
+pub enum BarKind {
+    /// every N milliseconds of exchange time
+    Time(i64),
+    /// once this many shares traded
+    Volume(i64),
+    /// once this much was traded, in book units like Book::cum_amount
+    Dollar(i64),
+    /// every N trades
+    Tick(i64),
+}
+
+impl BarKind {
+    /// "time=60s", "time=5m", "time=500" (milliseconds), "volume=100000",
+    /// "dollar=1000000" (yuan) or "tick=100"
+    pub fn parse(s: &str) -> Result<BarKind, Box<dyn Error>> {
+        let (field, value) = match s.find('=') {
+            Some(pos) => (&s[..pos], &s[pos + 1..]),
+            None => return Err(format!("invalid bar kind '{}'", s).into()),
+        };
+        let kind = match field {
+            "time" => {
+                let millis = if let Some(seconds) = value.strip_suffix('s') {
+                    seconds.parse::<i64>()? * 1000
+                } else if let Some(minutes) = value.strip_suffix('m') {
+                    minutes.parse::<i64>()? * 60 * 1000
+                } else {
+                    value.parse::<i64>()?
+                };
+                BarKind::Time(millis)
+            }
+            "volume" => BarKind::Volume(value.parse::<i64>()?),
+            "dollar" => BarKind::Dollar(value.parse::<i64>()? * Book::PRICE_DIVISOR as i64),
+            "tick" => BarKind::Tick(value.parse::<i64>()?),
+            _ => return Err(format!("unknown bar kind '{}'", field).into()),
+        };
+        let size = match kind {
+            BarKind::Time(n) | BarKind::Volume(n) | BarKind::Dollar(n) | BarKind::Tick(n) => n,
+        };
+        if size <= 0 {
+            return Err(format!("bar size must be positive in '{}'", s).into());
+        }
+        return Ok(kind);
+    }
+}
+
+/// prices and amount in book units
+#[derive(Debug, Clone, Serialize)]
+pub struct Bar {
+    /// SecurityID of the instrument
+    pub inst_id: i32,
+    /// trading day as YYYYMMDD
+    pub date: i32,
+    /// exchange time of day in milliseconds, the bucket of a time bar
+    /// or the first and last trade of the others
+    pub start_millis: i64,
+    /// end of the bar, like start_millis
+    pub end_millis: i64,
+    /// first trade price
+    pub open: i64,
+    /// highest trade price
+    pub high: i64,
+    /// lowest trade price
+    pub low: i64,
+    /// last trade price
+    pub close: i64,
+    /// shares traded
+    pub volume: i64,
+    /// sum of price times quantity
+    pub amount: i64,
+    /// number of executions
+    pub num_trades: i64,
+    /// includes prints of the opening or closing call auction
+    pub auction: bool,
+}
+
+impl Bar {
+    /// volume weighted average price in book units, 0 without volume
+    pub fn vwap(&self) -> f64 {
+        if self.volume == 0 {
+            return 0.0;
+        }
+        return self.amount as f64 / self.volume as f64;
+    }
+}
+
+/// aggregates executions into bars per instrument, usable as an observer
+/// or fed directly with trades in exchange time order
+pub struct BarBuilder {
+    kind: BarKind,
+    // key: SecurityID
+    open_: BTreeMap<i32, Bar>,
+    // completed bars not yet taken
+    bars_: Vec<Bar>,
+}
+
+impl BarBuilder {
+    /// no bars until trades are added
+    pub fn new(kind: BarKind) -> BarBuilder {
+        BarBuilder {
+            kind,
+            open_: BTreeMap::new(),
+            bars_: Vec::new(),
+        }
+    }
+
+    // time bars follow the usual convention for SZSE minute bars: the opening
+    // auction goes into the first bar of continuous trading, and prints at the
+    // end of a session (11:30, the 15:00 closing auction) into the bar before,
+    // so there is never a bar in the lunch break
+    fn time_bucket(&self, millis: i64, interval: i64) -> i64 {
+        let millis = if millis < CONTINUOUS_START_MILLIS {
+            CONTINUOUS_START_MILLIS
+        } else if SESSIONS.iter().any(|(_, end)| *end == millis) {
+            millis - 1
+        } else {
+            millis
+        };
+        return millis / interval * interval;
+    }
+
+    /// adds an execution, cancels are ignored
+    pub fn add_trade(&mut self, trade: &md::Trade) {
+        match trade.ExecType {
+            md::ExecuteType::Traded => {}
+            _ => return,
+        }
+        let time = trade.exchange_time();
+        let amount = trade.TradeQty * trade.TradePrice;
+
+        let (start_millis, end_millis) = match self.kind {
+            BarKind::Time(interval) => {
+                let start = self.time_bucket(time.millis, interval);
+                (start, start + interval)
+            }
+            _ => (time.millis, time.millis),
+        };
+        // a time bar is complete once a trade falls into a later bucket
+        if let Some(bar) = self.open_.get(&trade.SecurityID) {
+            if matches!(self.kind, BarKind::Time(_))
+                && (bar.date, bar.start_millis) != (time.date, start_millis)
+            {
+                let bar = self.open_.remove(&trade.SecurityID).unwrap();
+                self.bars_.push(bar);
+            }
+        }
+
+        let bar = self.open_.entry(trade.SecurityID).or_insert(Bar {
+            inst_id: trade.SecurityID,
+            date: time.date,
+            start_millis,
+            end_millis,
+            open: trade.TradePrice,
+            high: trade.TradePrice,
+            low: trade.TradePrice,
+            close: trade.TradePrice,
+            volume: 0,
+            amount: 0,
+            num_trades: 0,
+            auction: false,
+        });
+        bar.end_millis = cmp::max(bar.end_millis, end_millis);
+        bar.high = cmp::max(bar.high, trade.TradePrice);
+        bar.low = cmp::min(bar.low, trade.TradePrice);
+        bar.close = trade.TradePrice;
+        bar.volume += trade.TradeQty;
+        bar.amount += amount;
+        bar.num_trades += 1;
+        bar.auction |= trade.aggressor() == md::Aggressor::Auction;
+
+        // the trade that reaches the threshold stays in the bar, it is not split
+        let complete = match self.kind {
+            BarKind::Time(_) => false,
+            BarKind::Volume(volume) => bar.volume >= volume,
+            BarKind::Dollar(amount) => bar.amount >= amount,
+            BarKind::Tick(trades) => bar.num_trades >= trades,
+        };
+        if complete {
+            let bar = self.open_.remove(&trade.SecurityID).unwrap();
+            self.bars_.push(bar);
+        }
+    }
+
+    /// completed bars in the order they were completed
+    pub fn take_bars(&mut self) -> Vec<Bar> {
+        return std::mem::take(&mut self.bars_);
+    }
+
+    /// completes the bars still open, e.g. at the end of the day,
+    /// and returns everything not taken yet
+    pub fn finish(&mut self) -> Vec<Bar> {
+        let open = std::mem::take(&mut self.open_);
+        self.bars_.extend(open.into_values());
+        return self.take_bars();
+    }
+}
+
+impl BookObserver for BarBuilder {
+    fn on_order_executed(&mut self, _book: &Book, trade: &md::Trade) {
+        self.add_trade(trade);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md::testing::trade;
+
+    fn millis(time: &str) -> i64 {
+        return md::ExchangeTime::parse(md::testing::DATE, time)
+            .unwrap()
+            .millis;
+    }
+
+    // an execution of 2290 where the sell came later
+    fn print(seq: i64, time: &str, price: i64, quantity: i64) -> md::Trade {
+        return trade(2290, seq, time, price, quantity, seq - 1, seq);
+    }
+
+    // (start, end, volume, num_trades, auction) of every bar
+    fn bars(kind: BarKind, trades: &[md::Trade]) -> Vec<(i64, i64, i64, i64, bool)> {
+        let mut builder = BarBuilder::new(kind);
+        for trade in trades {
+            builder.add_trade(trade);
+        }
+        return builder
+            .finish()
+            .iter()
+            .map(|bar| {
+                let (start, end) = (bar.start_millis, bar.end_millis);
+                (start, end, bar.volume, bar.num_trades, bar.auction)
+            })
+            .collect();
+    }
+
+    #[test]
+    fn session_edges_go_into_the_neighbouring_bar() {
+        let trades = vec![
+            print(2, "09:25:00.000", 51000, 100),
+            print(4, "09:30:00.000", 51100, 200),
+            print(6, "11:29:59.990", 51000, 300),
+            print(8, "11:30:00.000", 51000, 400),
+            print(10, "13:00:00.000", 51000, 500),
+            print(12, "14:59:59.000", 51000, 600),
+            print(14, "15:00:00.000", 51000, 700),
+        ];
+        let minute = 60_000;
+        let expected = vec![
+            // the opening auction starts the first bar of the day
+            (millis("09:30:00.000"), millis("09:31:00.000"), 300, 2, true),
+            (
+                millis("11:29:00.000"),
+                millis("11:30:00.000"),
+                700,
+                2,
+                false,
+            ),
+            (
+                millis("13:00:00.000"),
+                millis("13:01:00.000"),
+                500,
+                1,
+                false,
+            ),
+            // the closing auction ends the last one
+            (
+                millis("14:59:00.000"),
+                millis("15:00:00.000"),
+                1300,
+                2,
+                true,
+            ),
+        ];
+        assert_eq!(bars(BarKind::Time(minute), &trades), expected);
+
+        let mut builder = BarBuilder::new(BarKind::Time(minute));
+        builder.add_trade(&trades[0]);
+        builder.add_trade(&trades[1]);
+        let bar = &builder.finish()[0];
+        assert_eq!(
+            (bar.open, bar.high, bar.low, bar.close),
+            (51000, 51100, 51000, 51100)
+        );
+        assert_eq!(bar.amount, 100 * 51000 + 200 * 51100);
+    }
+
+    #[test]
+    fn activity_bars_complete_on_the_trade_that_reaches_the_size() {
+        let trades: Vec<md::Trade> = (1..=5)
+            .map(|idx| print(idx * 2, &format!("10:00:0{}.000", idx), 50000, idx * 100))
+            .collect();
+        let volumes = |kind| -> Vec<i64> {
+            return bars(kind, &trades).iter().map(|bar| bar.2).collect();
+        };
+        // 100 + 200 + 300 reaches 500, the rest is still open at the end
+        assert_eq!(volumes(BarKind::Volume(500)), vec![600, 900]);
+        assert_eq!(volumes(BarKind::Tick(2)), vec![300, 700, 500]);
+        // 5 yuan times 600 shares is 3000 yuan
+        let dollar = BarKind::parse("dollar=3000").unwrap();
+        assert_eq!(volumes(dollar), vec![600, 900]);
+        assert_eq!(volumes(BarKind::Dollar(3000 * 10000 + 1)), vec![1000, 500]);
+
+        // the first and last trade of the bar
+        let found = bars(BarKind::Tick(2), &trades);
+        assert_eq!(
+            (found[0].0, found[0].1),
+            (millis("10:00:01.000"), millis("10:00:02.000"))
+        );
+    }
+
+    #[test]
+    fn cancels_are_not_trades() {
+        let trades = vec![
+            print(2, "10:00:00.000", 50000, 100),
+            trade(2290, 3, "10:00:01.000", 0, 100, 1, 0),
+        ];
+        assert_eq!(bars(BarKind::Tick(2), &trades).len(), 1);
+        assert_eq!(bars(BarKind::Tick(2), &trades)[0].3, 1);
+    }
+}
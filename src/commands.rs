@@ -0,0 +1,1362 @@
+use crate::config::{Config, Format};
+use reconstruct::backtest::{Backtester, ImpactPersistence, TwapStrategy};
+use reconstruct::bars::{BarBuilder, BarKind};
+use reconstruct::features::FeatureCalculator;
+use reconstruct::latency::{LatencyAnalyzer, LatencyModel};
+use reconstruct::lifecycle::LifecycleTracker;
+use reconstruct::observer::BookObserver;
+use reconstruct::queue_sim::{HypotheticalOrder, QueueSimulator};
+use reconstruct::reference::PrevCloses;
+use reconstruct::snapshot_builder::{Book, ClockType, SnapshotBuilder};
+use reconstruct::surveillance::SurveillanceMonitor;
+use reconstruct::tape::TradeTape;
+use reconstruct::{dump, md, parallel, schedule};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+type Matches<'a> = clap::ArgMatches<'a>;
+
+// rows of every subcommand are written through this
+enum Output {
+    Csv(Box<csv::Writer<Box<dyn Write + Send>>>),
+    // one json object per line
+    Json(Box<dyn Write + Send>),
+}
+
+impl Output {
+    fn new(config: &Config) -> Result<Output, Box<dyn Error>> {
+        return Output::create(config.output.path.as_deref(), config.output.format);
+    }
+
+    // stdout if there is no file
+    fn create(filename: Option<&str>, format: Format) -> Result<Output, Box<dyn Error>> {
+        let writer: Box<dyn Write + Send> = match filename {
+            Some(filename) => Box::new(BufWriter::new(File::create(filename)?)),
+            None => Box::new(io::stdout()),
+        };
+        return match format {
+            Format::Json => Ok(Output::Json(writer)),
+            Format::Csv => Ok(Output::Csv(Box::new(csv::Writer::from_writer(writer)))),
+        };
+    }
+
+    fn write<T: Serialize>(&mut self, row: &T) -> Result<(), Box<dyn Error>> {
+        match self {
+            Output::Csv(writer) => writer.serialize(row)?,
+            Output::Json(writer) => {
+                serde_json::to_writer(&mut *writer, row)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        return Ok(());
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Output::Csv(writer) => writer.flush()?,
+            Output::Json(writer) => writer.flush()?,
+        }
+        return Ok(());
+    }
+}
+
+type Input = (Vec<Arc<md::Order>>, Vec<Arc<md::Trade>>);
+
+fn read_input(config: &Config, filter: &md::Filter) -> Result<Input, Box<dyn Error>> {
+    let (order, trade) = match (&config.input.order, &config.input.trade) {
+        (Some(order), Some(trade)) => (order, trade),
+        _ => {
+            return Err(
+                "no order or trade file, give --order and --trade or set them in the config".into(),
+            )
+        }
+    };
+    let orders = md::read_csv_filtered::<md::Order>(order, filter)?;
+    let trades = md::read_csv_filtered::<md::Trade>(trade, filter)?;
+    return Ok((orders, trades));
+}
+
+// trading day of the data
+fn date(orders: &[Arc<md::Order>], trades: &[Arc<md::Trade>]) -> Result<i32, Box<dyn Error>> {
+    if let Some(order) = orders.first() {
+        return Ok(order.exchange_time().date);
+    }
+    return match trades.first() {
+        Some(trade) => Ok(trade.exchange_time().date),
+        None => Err("no orders or trades left after filtering".into()),
+    };
+}
+
+// the last snapshot of each instrument in the --init file,
+// the time range does not apply since books start before it
+fn init_snapshots(
+    config: &Config,
+    filter: &md::Filter,
+) -> Result<Vec<md::Snapshot>, Box<dyn Error>> {
+    let filename = match &config.book.init {
+        Some(filename) => filename,
+        None => return Ok(Vec::new()),
+    };
+    let filter = md::Filter {
+        securities: filter.securities.clone(),
+        prefix: filter.prefix.clone(),
+        start_millis: None,
+        end_millis: None,
+        skip_repeated: false,
+    };
+    let mut last = BTreeMap::new();
+    for snapshot in md::read_csv_filtered::<md::Snapshot>(filename, &filter)? {
+        last.insert(snapshot.StockID, snapshot);
+    }
+    return Ok(last
+        .into_values()
+        .map(|snapshot| (*snapshot).clone())
+        .collect());
+}
+
+// on the arrival clock a snapshot would be reconciled after flow that came
+// after its exchange time, e.g. cum_volume could go backwards
+fn warm_start(
+    config: &Config,
+    filter: &md::Filter,
+    clock_type: ClockType,
+) -> Result<Vec<Arc<md::Snapshot>>, Box<dyn Error>> {
+    return match &config.book.warm_start {
+        Some(_) if clock_type != ClockType::Exchange => {
+            Err("--warm-start reconciles at exchange times, run it on the exchange clock".into())
+        }
+        Some(mdlog) => md::read_csv_filtered::<md::Snapshot>(mdlog, filter),
+        None => Ok(Vec::new()),
+    };
+}
+
+// a single threaded builder with --init and --warm-start applied
+fn builder(
+    config: &Config,
+    filter: &md::Filter,
+    orders: Vec<Arc<md::Order>>,
+    trades: Vec<Arc<md::Trade>>,
+    clock_type: ClockType,
+) -> Result<SnapshotBuilder, Box<dyn Error>> {
+    let mut builder = SnapshotBuilder::new(orders, trades);
+    builder.set_clock_type(clock_type);
+    builder.set_exchange_snapshots(warm_start(config, filter, clock_type)?);
+    let init = init_snapshots(config, filter)?;
+    if !init.is_empty() {
+        builder.init(&init);
+    }
+    return Ok(builder);
+}
+
+pub fn build(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args(matches)?;
+    let filter = config.filter()?;
+    let (orders, trades) = read_input(&config, &filter)?;
+    let date = date(&orders, &trades)?;
+
+    // snapshots "at" exchange times are taken on the exchange clock, so are
+    // checkpoints since --checkpoint-at is an exchange time
+    let checkpoint = matches.is_present("save-checkpoint") || matches.is_present("restore");
+    let clock_type = config.clock_type(if config.schedule.at.is_empty() && !checkpoint {
+        ClockType::Arrival
+    } else {
+        ClockType::Exchange
+    });
+    let schedule = config.schedule(date, &filter, clock_type)?;
+
+    if config.output.extended && config.book.threads.is_some() {
+        return Err("extended snapshots are only built on a single thread".into());
+    }
+    let snapshots = if let Some(threads) = config.book.threads {
+        let mut builder = parallel::ParallelSnapshotBuilder::new(orders, trades, threads);
+        builder.set_clock_type(clock_type);
+        match config.book.partition.as_deref() {
+            None | Some("instrument") => {}
+            Some("channel") => builder.set_partition(parallel::Partition::Channel),
+            Some(partition) => return Err(format!("unknown partition '{}'", partition).into()),
+        }
+        builder.set_exchange_snapshots(warm_start(&config, &filter, clock_type)?);
+        builder.init(&init_snapshots(&config, &filter)?);
+        builder.build_on_schedule(&schedule)
+    } else {
+        let mut builder = builder(&config, &filter, orders, trades, clock_type)?;
+        if let Some(values) = matches.values_of("dump-when") {
+            for value in values {
+                builder.add_observer(Box::new(dump::BookDumper::parse(value)?));
+            }
+        }
+        if let Some(checkpoint) = matches.value_of("restore") {
+            builder.restore_checkpoint(checkpoint)?;
+        }
+        if let Some(checkpoint) = matches.value_of("save-checkpoint") {
+            if clock_type != ClockType::Exchange {
+                return Err(
+                    "--checkpoint-at is an exchange time, save on the exchange clock".into(),
+                );
+            }
+            let at = matches.value_of("checkpoint-at").unwrap();
+            builder.process_until(md::ExchangeTime::parse(date, at)?.to_clock());
+            return builder.save_checkpoint(checkpoint);
+        }
+        if config.output.extended {
+            let mut output = Output::new(&config)?;
+            for snapshot in builder.build_extended_on_schedule(&schedule).iter() {
+                output.write(snapshot)?;
+            }
+            output.flush()?;
+            return config.save_next_to_output();
+        }
+        builder.build_on_schedule(&schedule)
+    };
+
+    let mut output = Output::new(&config)?;
+    for snapshot in snapshots.iter() {
+        output.write(snapshot)?;
+    }
+    output.flush()?;
+    return config.save_next_to_output();
+}
+
+#[derive(Serialize)]
+struct ValidationRow {
+    inst: i32,
+    snapshots: usize,
+    matched: usize,
+    // exchange time of the first snapshot that does not match
+    first_mismatch: String,
+}
+
+// prices are compared in book units so float noise does not count
+fn same_levels(expected: &md::Snapshot, actual: &md::Snapshot) -> bool {
+    let units = |levels: [(f64, i64); 5]| {
+        levels.map(|(price, quantity)| ((price * Book::PRICE_DIVISOR).round() as i64, quantity))
+    };
+    return units(expected.bids()) == units(actual.bids())
+        && units(expected.asks()) == units(actual.asks());
+}
+
+pub fn validate(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args(matches)?;
+    let filter = config.filter()?;
+    let (orders, trades) = read_input(&config, &filter)?;
+    let clock_type = config.clock_type(ClockType::Exchange);
+    let mut builder = builder(&config, &filter, orders, trades, clock_type)?;
+    let mdlog = match &config.schedule.mdlog {
+        Some(mdlog) => mdlog,
+        None => return Err("no mdLog file to validate against, give --mdlog".into()),
+    };
+
+    let mut expected = Vec::new();
+    for snapshot in md::read_csv_filtered::<md::Snapshot>(mdlog, &filter)? {
+        expected.push((snapshot.exchange_time()?, snapshot));
+    }
+    // stable, so snapshots of one time keep their file order
+    expected.sort_by_key(|(time, _)| *time);
+
+    let mut rows: BTreeMap<i32, ValidationRow> = BTreeMap::new();
+    for (time, snapshot) in expected.iter() {
+        // the exchange snapshot includes messages at its own time
+        builder.process_until(time.to_clock() + 1);
+        let matched = match builder.book(snapshot.StockID) {
+            Some(book) => same_levels(snapshot, &book.to_snapshot()),
+            None => false,
+        };
+        if !matched {
+            log::debug!(
+                target: "validate",
+                "inst={} time={} expected={:?} actual={:?}",
+                snapshot.StockID,
+                time,
+                snapshot,
+                builder.book(snapshot.StockID).map(|book| book.to_snapshot())
+            );
+        }
+
+        let row = rows.entry(snapshot.StockID).or_insert(ValidationRow {
+            inst: snapshot.StockID,
+            snapshots: 0,
+            matched: 0,
+            first_mismatch: String::new(),
+        });
+        row.snapshots += 1;
+        if matched {
+            row.matched += 1;
+        } else if row.first_mismatch.is_empty() {
+            row.first_mismatch = time.to_string();
+        }
+    }
+
+    let mut output = Output::new(&config)?;
+    for row in rows.values() {
+        output.write(row)?;
+    }
+    output.flush()?;
+    return config.save_next_to_output();
+}
+
+#[derive(Serialize)]
+struct EventRow {
+    clock: i64,
+    time: String,
+    inst: i32,
+    event: &'static str,
+    // ApplSeqNum of the order or trade, 0 for level and top of book events
+    seq: i64,
+    // the aggressor's side for executions
+    side: String,
+    // Buy, Sell or Auction for executions, empty otherwise
+    aggressor: String,
+    price: f64,
+    quantity: i64,
+}
+
+impl EventRow {
+    fn new(
+        book: &Book,
+        event: &'static str,
+        seq: i64,
+        side: md::Side,
+        price: i64,
+        quantity: i64,
+    ) -> EventRow {
+        EventRow {
+            clock: book.timestamp,
+            time: book.exchange_time.to_string(),
+            inst: book.inst_id(),
+            event,
+            seq,
+            side: format!("{:?}", side),
+            aggressor: String::new(),
+            price: price as f64 / Book::PRICE_DIVISOR,
+            quantity,
+        }
+    }
+}
+
+// writes every event through a handle shared with the command,
+// which flushes it once the replay is done
+struct EventWriter {
+    output: Arc<Mutex<Output>>,
+    // stop after the first failed write, e.g. a closed pipe
+    failed: bool,
+}
+
+impl EventWriter {
+    fn write(&mut self, row: EventRow) {
+        if self.failed {
+            return;
+        }
+        if let Err(err) = self.output.lock().unwrap().write(&row) {
+            log::error!("failed to write event: {}", err);
+            self.failed = true;
+        }
+    }
+}
+
+impl BookObserver for EventWriter {
+    fn on_order_added(&mut self, book: &Book, order: &md::Order) {
+        self.write(EventRow::new(
+            book,
+            "order_added",
+            order.ApplSeqNum,
+            order.Side,
+            order.Price,
+            order.OrderQty,
+        ));
+    }
+
+    fn on_order_cancelled(&mut self, book: &Book, order: &md::Order, quantity: i64) {
+        self.write(EventRow::new(
+            book,
+            "order_cancelled",
+            order.ApplSeqNum,
+            order.Side,
+            order.Price,
+            quantity,
+        ));
+    }
+
+    fn on_order_executed(&mut self, book: &Book, trade: &md::Trade) {
+        let aggressor = trade.aggressor();
+        let mut row = EventRow::new(
+            book,
+            "order_executed",
+            trade.ApplSeqNum,
+            aggressor.side(),
+            trade.TradePrice,
+            trade.TradeQty,
+        );
+        row.aggressor = format!("{:?}", aggressor);
+        self.write(row);
+    }
+
+    fn on_level_changed(&mut self, book: &Book, side: md::Side, price: i64, quantity: i64) {
+        self.write(EventRow::new(
+            book,
+            "level_changed",
+            0,
+            side,
+            price,
+            quantity,
+        ));
+    }
+
+    fn on_top_of_book_changed(&mut self, book: &Book) {
+        // one row per side, price and quantity 0 for an empty side
+        let (bid_price, bid_quantity) = book.best_bid().unwrap_or((0, 0));
+        let (ask_price, ask_quantity) = book.best_ask().unwrap_or((0, 0));
+        self.write(EventRow::new(
+            book,
+            "top_of_book",
+            0,
+            md::Side::Bid,
+            bid_price,
+            bid_quantity,
+        ));
+        self.write(EventRow::new(
+            book,
+            "top_of_book",
+            0,
+            md::Side::Ask,
+            ask_price,
+            ask_quantity,
+        ));
+    }
+
+    fn on_auction_uncross(&mut self, book: &Book, quantity: i64) {
+        self.write(EventRow::new(
+            book,
+            "auction_uncross",
+            0,
+            md::Side::Unknown,
+            0,
+            quantity,
+        ));
+    }
+}
+
+pub fn replay(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args(matches)?;
+    let filter = config.filter()?;
+    let (orders, trades) = read_input(&config, &filter)?;
+    let clock_type = config.clock_type(ClockType::Arrival);
+    let mut builder = builder(&config, &filter, orders, trades, clock_type)?;
+
+    let output = Arc::new(Mutex::new(Output::new(&config)?));
+    builder.add_observer(Box::new(EventWriter {
+        output: Arc::clone(&output),
+        failed: false,
+    }));
+    builder.process_until(i64::MAX);
+    drop(builder);
+
+    output.lock().unwrap().flush()?;
+    return config.save_next_to_output();
+}
+
+#[derive(Serialize, Default)]
+struct StatsRow {
+    inst: i32,
+    channel: i32,
+    orders: usize,
+    limit_orders: usize,
+    market_orders: usize,
+    best_orders: usize,
+    executions: usize,
+    cancels: usize,
+    volume: i64,
+    amount: f64,
+    // TradeQty * TradePrice, summed without rounding
+    #[serde(skip)]
+    amount_units: i64,
+    first: String,
+    last: String,
+}
+
+pub fn stats(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args(matches)?;
+    let filter = config.filter()?;
+    let (orders, trades) = read_input(&config, &filter)?;
+
+    let mut rows: BTreeMap<i32, StatsRow> = BTreeMap::new();
+    // first and last exchange time of each instrument
+    let mut times: BTreeMap<i32, (md::ExchangeTime, md::ExchangeTime)> = BTreeMap::new();
+    let mut seen = |inst: i32, time: md::ExchangeTime| {
+        let range = times.entry(inst).or_insert((time, time));
+        range.0 = range.0.min(time);
+        range.1 = range.1.max(time);
+    };
+
+    for order in orders.iter() {
+        seen(order.SecurityID, order.exchange_time());
+        let row = rows.entry(order.SecurityID).or_default();
+        row.channel = order.ChannelNo;
+        row.orders += 1;
+        match order.OrderType {
+            md::OrderType::LimitOrder => row.limit_orders += 1,
+            md::OrderType::MarketOrder => row.market_orders += 1,
+            md::OrderType::BestOrder => row.best_orders += 1,
+            md::OrderType::Unknown => {}
+        }
+    }
+    for trade in trades.iter() {
+        seen(trade.SecurityID, trade.exchange_time());
+        let row = rows.entry(trade.SecurityID).or_default();
+        row.channel = trade.ChannelNo;
+        match trade.ExecType {
+            md::ExecuteType::Traded => {
+                row.executions += 1;
+                row.volume += trade.TradeQty;
+                row.amount_units += trade.TradeQty * trade.TradePrice;
+            }
+            md::ExecuteType::Cancelled => row.cancels += 1,
+            md::ExecuteType::Unknown => {}
+        }
+    }
+
+    let mut output = Output::new(&config)?;
+    for (inst, row) in rows.iter_mut() {
+        row.inst = *inst;
+        row.amount = row.amount_units as f64 / Book::PRICE_DIVISOR;
+        let (first, last) = times[inst];
+        row.first = first.to_string();
+        row.last = last.to_string();
+        output.write(row)?;
+    }
+    output.flush()?;
+    return config.save_next_to_output();
+}
+
+#[derive(Serialize)]
+struct TapeRow {
+    clock: i64,
+    time: String,
+    inst: i32,
+    seq: i64,
+    price: f64,
+    quantity: i64,
+    amount: f64,
+    aggressor: String,
+    // 'B', 'S' or 'N' like the exchange's TradeBSFlag
+    flag: char,
+    bid_seq: i64,
+    offer_seq: i64,
+    // the book as the aggressive order found it, empty in call auctions
+    bid: Option<f64>,
+    bid_quantity: Option<i64>,
+    ask: Option<f64>,
+    ask_quantity: Option<i64>,
+    spread: Option<f64>,
+    resting_seq: Option<i64>,
+    queue_ahead: Option<i64>,
+    resting_age_millis: Option<i64>,
+    aggressor_quantity: Option<i64>,
+    aggressor_type: Option<String>,
+}
+
+pub fn tape(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args(matches)?;
+    let filter = config.filter()?;
+    let (orders, trades) = read_input(&config, &filter)?;
+    let clock_type = config.clock_type(ClockType::Arrival);
+    let mut builder = builder(&config, &filter, orders, trades, clock_type)?;
+
+    let tape = Arc::new(Mutex::new(TradeTape::new()));
+    builder.add_observer(Box::new(Arc::clone(&tape)));
+    builder.process_until(i64::MAX);
+
+    let price = |price: i64| price as f64 / Book::PRICE_DIVISOR;
+    let mut output = Output::new(&config)?;
+    for entry in tape.lock().unwrap().take_entries() {
+        output.write(&TapeRow {
+            clock: entry.timestamp,
+            time: entry.exchange_time.to_string(),
+            inst: entry.inst_id,
+            seq: entry.seq,
+            price: price(entry.price),
+            quantity: entry.quantity,
+            amount: price(entry.price * entry.quantity),
+            aggressor: format!("{:?}", entry.aggressor),
+            flag: entry.aggressor.to_flag(),
+            bid_seq: entry.bid_seq,
+            offer_seq: entry.offer_seq,
+            bid: entry.best_bid.map(|(bid, _)| price(bid)),
+            bid_quantity: entry.best_bid.map(|(_, quantity)| quantity),
+            ask: entry.best_ask.map(|(ask, _)| price(ask)),
+            ask_quantity: entry.best_ask.map(|(_, quantity)| quantity),
+            spread: entry.spread().map(price),
+            resting_seq: entry.resting_seq,
+            queue_ahead: entry.queue_ahead,
+            resting_age_millis: entry.resting_age_millis,
+            aggressor_quantity: entry.aggressor_quantity,
+            aggressor_type: entry.aggressor_type.map(|t| format!("{:?}", t)),
+        })?;
+    }
+    output.flush()?;
+    return config.save_next_to_output();
+}
+
+#[derive(Serialize)]
+struct LifecycleRow {
+    inst: i32,
+    seq: i64,
+    clock: i64,
+    time: String,
+    side: String,
+    order_type: String,
+    price: f64,
+    quantity: i64,
+    filled: i64,
+    // time quantity@price of every execution, separated by ';'
+    fills: String,
+    first_fill_millis: Option<i64>,
+    cancel_time: Option<String>,
+    cancelled: i64,
+    state: String,
+    queue_ahead: Option<i64>,
+}
+
+pub fn lifecycle(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args(matches)?;
+    let filter = config.filter()?;
+    let (orders, trades) = read_input(&config, &filter)?;
+    let clock_type = config.clock_type(ClockType::Arrival);
+    let mut builder = builder(&config, &filter, orders, trades, clock_type)?;
+
+    let tracker = Arc::new(Mutex::new(LifecycleTracker::new()));
+    builder.add_observer(Box::new(Arc::clone(&tracker)));
+    builder.process_until(i64::MAX);
+
+    let price = |price: i64| price as f64 / Book::PRICE_DIVISOR;
+    let mut output = Output::new(&config)?;
+    for order in tracker.lock().unwrap().finish() {
+        let fills: Vec<String> = order
+            .fills
+            .iter()
+            .map(|fill| {
+                format!(
+                    "{} {}@{}",
+                    fill.exchange_time,
+                    fill.quantity,
+                    price(fill.price)
+                )
+            })
+            .collect();
+        output.write(&LifecycleRow {
+            inst: order.inst_id,
+            seq: order.seq,
+            clock: order.clock_at_arrival,
+            time: order.exchange_time.to_string(),
+            side: format!("{:?}", order.side),
+            order_type: format!("{:?}", order.order_type),
+            price: price(order.price),
+            quantity: order.quantity,
+            filled: order.filled_quantity(),
+            fills: fills.join(";"),
+            first_fill_millis: order.time_to_first_fill(),
+            cancel_time: order.cancel_time.map(|time| time.to_string()),
+            cancelled: order.cancelled_quantity,
+            state: format!("{:?}", order.state()),
+            queue_ahead: order.queue_ahead,
+        })?;
+    }
+    output.flush()?;
+    return config.save_next_to_output();
+}
+
+#[derive(Serialize)]
+struct AlertRow {
+    inst: i32,
+    time: String,
+    kind: String,
+    side: String,
+    price: f64,
+    quantity: i64,
+    orders: usize,
+    // ApplSeqNums separated by ';'
+    seqs: String,
+}
+
+#[derive(Serialize)]
+struct CancelSummaryRow {
+    inst: i32,
+    orders: i64,
+    executions: i64,
+    cancels: i64,
+    cancelled_quantity: i64,
+    order_to_trade: f64,
+    cancel_to_order: f64,
+    latency_mean: f64,
+    latency_p10: i64,
+    latency_p50: i64,
+    latency_p90: i64,
+    latency_p99: i64,
+    within_100ms: f64,
+    within_1s: f64,
+    alerts: usize,
+}
+
+pub fn surveil(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args(matches)?;
+    let filter = config.filter()?;
+    let (orders, trades) = read_input(&config, &filter)?;
+    // lifetimes and windows are measured on exchange time
+    let clock_type = config.clock_type(ClockType::Exchange);
+    let mut builder = builder(&config, &filter, orders, trades, clock_type)?;
+
+    let thresholds = config.surveillance.clone().unwrap_or_default();
+    let monitor = Arc::new(Mutex::new(SurveillanceMonitor::new(thresholds)));
+    builder.add_observer(Box::new(Arc::clone(&monitor)));
+    builder.process_until(i64::MAX);
+    let mut monitor = monitor.lock().unwrap();
+
+    let mut output = Output::new(&config)?;
+    for alert in monitor.take_alerts() {
+        let seqs: Vec<String> = alert.seqs.iter().map(|seq| seq.to_string()).collect();
+        output.write(&AlertRow {
+            inst: alert.inst_id,
+            time: alert.exchange_time.to_string(),
+            kind: format!("{:?}", alert.kind),
+            side: format!("{:?}", alert.side),
+            price: alert.price as f64 / Book::PRICE_DIVISOR,
+            quantity: alert.quantity,
+            orders: alert.seqs.len(),
+            seqs: seqs.join(";"),
+        })?;
+    }
+    output.flush()?;
+
+    if let Some(summary) = matches.value_of("summary") {
+        let mut output = Output::create(Some(summary), config.output.format)?;
+        for row in monitor.summary() {
+            output.write(&CancelSummaryRow {
+                inst: row.inst_id,
+                orders: row.orders,
+                executions: row.executions,
+                cancels: row.cancels,
+                cancelled_quantity: row.cancelled_quantity,
+                order_to_trade: row.order_to_trade,
+                cancel_to_order: row.cancel_to_order,
+                latency_mean: row.latency_mean,
+                latency_p10: row.latency_p10,
+                latency_p50: row.latency_p50,
+                latency_p90: row.latency_p90,
+                latency_p99: row.latency_p99,
+                within_100ms: row.within_100ms,
+                within_1s: row.within_1s,
+                alerts: row.alerts,
+            })?;
+        }
+        output.flush()?;
+    }
+    return config.save_next_to_output();
+}
+
+#[derive(Serialize)]
+struct LatencyRow {
+    channel: i32,
+    // exchange time of day the bucket starts at
+    start: String,
+    messages: usize,
+    // microseconds
+    mean: f64,
+    min: i64,
+    p50: i64,
+    p90: i64,
+    p99: i64,
+    max: i64,
+}
+
+#[derive(Serialize)]
+struct BurstRow {
+    channel: i32,
+    start: String,
+    end: String,
+    messages: usize,
+    // microseconds
+    duration: i64,
+    max_latency: i64,
+}
+
+#[derive(Serialize)]
+struct StallRow {
+    channel: i32,
+    clock: i64,
+    time: String,
+    // microseconds
+    arrival_gap: i64,
+    exchange_gap: i64,
+    latency_jump: i64,
+}
+
+pub fn latency(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args(matches)?;
+    let filter = config.filter()?;
+    let (orders, trades) = read_input(&config, &filter)?;
+    let date = date(&orders, &trades)?;
+
+    let thresholds = config.latency.clone().unwrap_or_default();
+    let mut analyzer = LatencyAnalyzer::new(thresholds)?;
+    analyzer.add_events(&orders, &trades);
+
+    let mut output = Output::new(&config)?;
+    for bucket in analyzer.buckets() {
+        output.write(&LatencyRow {
+            channel: bucket.channel,
+            start: md::ExchangeTime {
+                date,
+                millis: bucket.start_millis,
+            }
+            .to_string(),
+            messages: bucket.messages,
+            mean: bucket.mean,
+            min: bucket.min,
+            p50: bucket.p50,
+            p90: bucket.p90,
+            p99: bucket.p99,
+            max: bucket.max,
+        })?;
+    }
+    output.flush()?;
+
+    if let Some(bursts) = matches.value_of("bursts") {
+        let mut output = Output::create(Some(bursts), config.output.format)?;
+        for burst in analyzer.take_bursts() {
+            output.write(&BurstRow {
+                channel: burst.channel,
+                start: md::clock_to_string(burst.start),
+                end: md::clock_to_string(burst.end),
+                messages: burst.messages,
+                duration: burst.end - burst.start,
+                max_latency: burst.max_latency,
+            })?;
+        }
+        output.flush()?;
+    }
+    if let Some(stalls) = matches.value_of("stalls") {
+        let mut output = Output::create(Some(stalls), config.output.format)?;
+        for stall in analyzer.take_stalls() {
+            output.write(&StallRow {
+                channel: stall.channel,
+                clock: stall.timestamp,
+                time: md::clock_to_string(stall.timestamp),
+                arrival_gap: stall.arrival_gap,
+                exchange_gap: stall.exchange_gap,
+                latency_jump: stall.latency_jump,
+            })?;
+        }
+        output.flush()?;
+    }
+    return config.save_next_to_output();
+}
+
+#[derive(Serialize)]
+struct SimulationRow {
+    inst: i32,
+    side: String,
+    price: f64,
+    quantity: i64,
+    time: String,
+    // when it joined the book, empty if the replay never got there
+    entry_time: Option<String>,
+    entry_queue_ahead: i64,
+    filled: i64,
+    // time quantity@price of every fill, separated by ';'
+    fills: String,
+    first_fill_time: Option<String>,
+    last_fill_time: Option<String>,
+    cancel_time: Option<String>,
+    // still ahead of it at the end of the replay or when it was cancelled
+    queue_ahead: i64,
+}
+
+pub fn simulate(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args(matches)?;
+    let filter = config.filter()?;
+    let mut hypothetical = Vec::new();
+    for value in matches.values_of("hypothetical").unwrap() {
+        hypothetical.push(HypotheticalOrder::parse(value)?);
+    }
+    let (orders, trades) = read_input(&config, &filter)?;
+    let clock_type = config.clock_type(ClockType::Exchange);
+    let mut builder = builder(&config, &filter, orders, trades, clock_type)?;
+
+    let simulator = Arc::new(Mutex::new(QueueSimulator::new(hypothetical)));
+    builder.add_observer(Box::new(Arc::clone(&simulator)));
+    builder.process_until(i64::MAX);
+
+    let price = |price: i64| price as f64 / Book::PRICE_DIVISOR;
+    let date = builder.date().unwrap_or(0);
+    let time = |millis: i64| md::ExchangeTime { date, millis }.to_string();
+    let mut output = Output::new(&config)?;
+    for simulated in simulator.lock().unwrap().orders() {
+        let fills: Vec<String> = simulated
+            .fills
+            .iter()
+            .map(|fill| {
+                format!(
+                    "{} {}@{}",
+                    fill.exchange_time,
+                    fill.quantity,
+                    price(fill.price)
+                )
+            })
+            .collect();
+        let order = &simulated.order;
+        output.write(&SimulationRow {
+            inst: order.inst_id,
+            side: format!("{:?}", order.side),
+            price: price(order.price),
+            quantity: order.quantity,
+            time: time(order.millis),
+            entry_time: simulated.entry_time.map(|time| time.to_string()),
+            entry_queue_ahead: simulated.entry_queue_ahead,
+            filled: simulated.filled_quantity(),
+            fills: fills.join(";"),
+            first_fill_time: simulated
+                .fills
+                .first()
+                .map(|fill| fill.exchange_time.to_string()),
+            last_fill_time: simulated
+                .fills
+                .last()
+                .map(|fill| fill.exchange_time.to_string()),
+            cancel_time: simulated.cancel_time.map(|time| time.to_string()),
+            queue_ahead: simulated.queue_ahead,
+        })?;
+    }
+    output.flush()?;
+    return config.save_next_to_output();
+}
+
+#[derive(Serialize)]
+struct StrategyFillRow {
+    id: u64,
+    inst: i32,
+    timestamp: i64,
+    time: String,
+    side: String,
+    price: f64,
+    quantity: i64,
+    aggressive: bool,
+    // after the fill
+    position: i64,
+    // average price of everything filled so far
+    average_price: f64,
+}
+
+pub fn backtest(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args(matches)?;
+    let filter = config.filter()?;
+    let impact = ImpactPersistence::parse(matches.value_of("impact").unwrap())?;
+    let passive = matches.value_of("style") == Some("passive");
+    let seed = matches.value_of("seed").unwrap().parse::<u64>()?;
+    let (orders, trades) = read_input(&config, &filter)?;
+
+    // the measured model is only built when something draws from it, the
+    // strategy and the feed each draw from their own copy of it
+    let latency = matches.value_of("latency").unwrap();
+    let feed_latency = matches.is_present("feed-latency");
+    let measured = if latency == "measured" || feed_latency {
+        let bucket_millis = config.latency.clone().unwrap_or_default().bucket_millis;
+        Some(LatencyModel::measured(
+            &orders,
+            &trades,
+            bucket_millis,
+            seed,
+        )?)
+    } else {
+        None
+    };
+    // retimed events only mean something on the arrival clock
+    let (orders, trades, clock_type) = match &measured {
+        Some(measured) if feed_latency => {
+            let (orders, trades) = measured.clone().retime(&orders, &trades);
+            (orders, trades, ClockType::Arrival)
+        }
+        _ => (orders, trades, config.clock_type(ClockType::Exchange)),
+    };
+    let latency = match measured {
+        Some(measured) if latency == "measured" => measured,
+        _ => LatencyModel::constant(latency.parse::<i64>()?),
+    };
+    let builder = builder(&config, &filter, orders, trades, clock_type)?;
+
+    let date = builder.date().unwrap_or(0);
+    let twap = TwapStrategy::parse(matches.value_of("twap").unwrap(), date, passive)?;
+    let mut backtester = Backtester::new(builder, twap);
+    backtester.set_latency_model(latency);
+    backtester.set_impact_persistence(impact);
+    if config.has_schedule() {
+        // every event already reaches the strategy, so only times are passed on
+        if let Some(timestamps) = config.schedule(date, &filter, clock_type)?.timestamps(date) {
+            backtester.set_snapshot_times(timestamps);
+        }
+    }
+    backtester.run();
+
+    let mut output = Output::new(&config)?;
+    let (mut position, mut filled, mut value) = (0, 0, 0);
+    for fill in backtester.fills() {
+        position += match fill.side {
+            md::Side::Bid => fill.quantity,
+            _ => -fill.quantity,
+        };
+        filled += fill.quantity;
+        value += fill.quantity * fill.price;
+        output.write(&StrategyFillRow {
+            id: fill.id,
+            inst: fill.inst_id,
+            timestamp: fill.timestamp,
+            time: md::ExchangeTime::from_clock(fill.timestamp).to_string(),
+            side: format!("{:?}", fill.side),
+            price: fill.price as f64 / Book::PRICE_DIVISOR,
+            quantity: fill.quantity,
+            aggressive: fill.aggressive,
+            position,
+            average_price: value as f64 / filled as f64 / Book::PRICE_DIVISOR,
+        })?;
+    }
+    output.flush()?;
+    return config.save_next_to_output();
+}
+
+#[derive(Serialize)]
+struct LevelRow {
+    side: String,
+    level: usize,
+    price: f64,
+    quantity: i64,
+    // orders we have seen at the level, see Book::orders_at
+    orders: usize,
+}
+
+pub fn book(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args(matches)?;
+    let mut filter = config.filter()?;
+    let inst_id = matches.value_of("symbol").unwrap().parse::<i32>()?;
+    filter.securities = std::iter::once(inst_id).collect();
+    let depth = config.book.depth.unwrap_or(10);
+
+    let (orders, trades) = read_input(&config, &filter)?;
+    let date = date(&orders, &trades)?;
+    let at = md::ExchangeTime::parse(date, matches.value_of("at").unwrap())?;
+    let clock_type = config.clock_type(ClockType::Exchange);
+    let mut builder = builder(&config, &filter, orders, trades, clock_type)?;
+    builder.process_until(at.to_clock());
+
+    let book = match builder.book(inst_id) {
+        Some(book) => book,
+        None => return Err(format!("no book for {} at {}", inst_id, at).into()),
+    };
+    let mut output = Output::new(&config)?;
+    for side in [md::Side::Ask, md::Side::Bid] {
+        for (idx, (price, quantity)) in book.depth(side).take(depth).enumerate() {
+            output.write(&LevelRow {
+                side: format!("{:?}", side),
+                level: idx + 1,
+                price: price as f64 / Book::PRICE_DIVISOR,
+                quantity,
+                orders: book.orders_at(side, price).count(),
+            })?;
+        }
+    }
+    output.flush()?;
+    return config.save_next_to_output();
+}
+
+#[derive(Serialize)]
+struct FeatureRow {
+    clock: i64,
+    time: String,
+    inst: i32,
+    mid: f64,
+    spread: f64,
+    microprice: f64,
+    weighted_mid: f64,
+    imbalance: f64,
+    ofi1: i64,
+    ofi2: i64,
+    ofi3: i64,
+    ofi4: i64,
+    ofi5: i64,
+    // sum over the levels
+    mlofi: i64,
+    bid_slope: f64,
+    ask_slope: f64,
+}
+
+pub fn features(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args(matches)?;
+    let filter = config.filter()?;
+    let (orders, trades) = read_input(&config, &filter)?;
+    let date = date(&orders, &trades)?;
+    // the same schedule and clock as build
+    let clock_type = config.clock_type(if config.schedule.at.is_empty() {
+        ClockType::Arrival
+    } else {
+        ClockType::Exchange
+    });
+    let schedule = config.schedule(date, &filter, clock_type)?;
+    let mut builder = builder(&config, &filter, orders, trades, clock_type)?;
+
+    let calculator = Arc::new(Mutex::new(FeatureCalculator::new()));
+    builder.add_observer(Box::new(Arc::clone(&calculator)));
+    builder.build_on_schedule(&schedule);
+
+    let price = |price: f64| price / Book::PRICE_DIVISOR;
+    let mut output = Output::new(&config)?;
+    for features in calculator.lock().unwrap().take_features() {
+        output.write(&FeatureRow {
+            clock: features.timestamp,
+            time: features.exchange_time.to_string(),
+            inst: features.inst_id,
+            mid: price(features.mid),
+            spread: price(features.spread as f64),
+            microprice: price(features.microprice),
+            weighted_mid: price(features.weighted_mid),
+            imbalance: features.imbalance,
+            ofi1: features.ofi[0],
+            ofi2: features.ofi[1],
+            ofi3: features.ofi[2],
+            ofi4: features.ofi[3],
+            ofi5: features.ofi[4],
+            mlofi: features.multi_level_ofi(),
+            bid_slope: features.bid_slope,
+            ask_slope: features.ask_slope,
+        })?;
+    }
+    output.flush()?;
+    return config.save_next_to_output();
+}
+
+#[derive(Serialize)]
+struct BarRow {
+    inst: i32,
+    date: i32,
+    start: String,
+    end: String,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: i64,
+    amount: f64,
+    vwap: f64,
+    trades: i64,
+    auction: bool,
+}
+
+pub fn bars(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args(matches)?;
+    let filter = config.filter()?;
+    let kind = BarKind::parse(matches.value_of("bar").unwrap())?;
+    let (orders, trades) = read_input(&config, &filter)?;
+    // bars are cut on exchange time, so replay on it too
+    let clock_type = config.clock_type(ClockType::Exchange);
+    let mut builder = builder(&config, &filter, orders, trades, clock_type)?;
+
+    let bar_builder = Arc::new(Mutex::new(BarBuilder::new(kind)));
+    builder.add_observer(Box::new(Arc::clone(&bar_builder)));
+    builder.process_until(i64::MAX);
+    let mut bars = bar_builder.lock().unwrap().finish();
+    // stable, so bars starting at the same time keep their order
+    bars.sort_by_key(|bar| (bar.inst_id, bar.date, bar.start_millis));
+
+    let price = |price: i64| price as f64 / Book::PRICE_DIVISOR;
+    let mut output = Output::new(&config)?;
+    for bar in bars.iter() {
+        let time = |millis: i64| {
+            md::ExchangeTime {
+                date: bar.date,
+                millis,
+            }
+            .to_string()
+        };
+        output.write(&BarRow {
+            inst: bar.inst_id,
+            date: bar.date,
+            start: time(bar.start_millis),
+            end: time(bar.end_millis),
+            open: price(bar.open),
+            high: price(bar.high),
+            low: price(bar.low),
+            close: price(bar.close),
+            volume: bar.volume,
+            amount: price(bar.amount),
+            vwap: bar.vwap() / Book::PRICE_DIVISOR,
+            trades: bar.num_trades,
+            auction: bar.auction,
+        })?;
+    }
+    output.flush()?;
+    return config.save_next_to_output();
+}
+
+// input files of one trading day
+struct Day {
+    date: i32,
+    order: String,
+    trade: String,
+    // exchange snapshots, the schedule of the day unless one is configured
+    snapshot: Option<String>,
+}
+
+// name.csv or name.csv.gz
+fn find_file(dir: &Path, name: &str) -> Option<String> {
+    for filename in [format!("{}.csv", name), format!("{}.csv.gz", name)] {
+        let path = dir.join(filename);
+        if path.is_file() {
+            return Some(path.to_string_lossy().into_owned());
+        }
+    }
+    return None;
+}
+
+// YYYYMMDD directories in date order, days without orders or trades are skipped
+fn discover_days(data_dir: &str) -> Result<Vec<Day>, Box<dyn Error>> {
+    let mut dirs: Vec<(i32, PathBuf)> = Vec::new();
+    for entry in fs::read_dir(data_dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        if !path.is_dir() || name.len() != 8 {
+            continue;
+        }
+        if let Ok(date) = name.parse::<i32>() {
+            dirs.push((date, path));
+        }
+    }
+    dirs.sort();
+
+    let mut days = Vec::new();
+    for (date, dir) in dirs {
+        match (find_file(&dir, "order"), find_file(&dir, "trade")) {
+            (Some(order), Some(trade)) => days.push(Day {
+                date,
+                order,
+                trade,
+                snapshot: find_file(&dir, "snapshot"),
+            }),
+            _ => log::warn!("{} has no order or trade file, skipped", dir.display()),
+        }
+    }
+    return Ok(days);
+}
+
+#[derive(Serialize)]
+struct IndexRow {
+    date: i32,
+    orders: usize,
+    trades: usize,
+    instruments: usize,
+    snapshots: usize,
+    // instruments with a previous close from an earlier day
+    with_reference: usize,
+    output: String,
+}
+
+pub fn batch(matches: &Matches) -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args(matches)?;
+    let filter = config.filter()?;
+    let data_dir = match &config.input.data_dir {
+        Some(data_dir) => data_dir,
+        None => return Err("no data directory, give --data-dir or set input.data_dir".into()),
+    };
+    let output_dir = match &config.output.dir {
+        Some(output_dir) => Path::new(output_dir),
+        None => return Err("no output directory, give --output-dir or set output.dir".into()),
+    };
+    if config.schedule.mdlog.is_some() {
+        return Err("an mdLog schedule covers one day, put a snapshot file in each day".into());
+    }
+    fs::create_dir_all(output_dir)?;
+    let extension = match config.output.format {
+        Format::Csv => "csv",
+        Format::Json => "json",
+    };
+
+    let mut prev_closes = PrevCloses::new();
+    let mut index = Vec::new();
+    for day in discover_days(data_dir)? {
+        log::info!("building {}", day.date);
+        let orders = md::read_csv_filtered::<md::Order>(&day.order, &filter)?;
+        let trades = md::read_csv_filtered::<md::Trade>(&day.trade, &filter)?;
+        let (num_orders, num_trades) = (orders.len(), trades.len());
+
+        let clock_type = config.clock_type(if config.schedule.at.is_empty() {
+            ClockType::Arrival
+        } else {
+            ClockType::Exchange
+        });
+        let schedule = match &day.snapshot {
+            Some(snapshot) if !config.has_schedule() => {
+                schedule::Schedule::from_mdlog(snapshot, &filter, clock_type)?
+            }
+            _ => config.schedule(day.date, &filter, clock_type)?,
+        };
+
+        let reference = prev_closes.reference(day.date);
+        // only today's instruments count, closes carried over from earlier days do not
+        let today: HashSet<i32> = orders
+            .iter()
+            .map(|order| order.SecurityID)
+            .chain(trades.iter().map(|trade| trade.SecurityID))
+            .collect();
+        let with_reference = today
+            .iter()
+            .filter(|inst_id| reference.contains_key(inst_id))
+            .count();
+        // a fresh builder per day, only the reference data is carried over
+        let mut builder = SnapshotBuilder::new(orders, trades);
+        builder.set_clock_type(clock_type);
+        builder.set_reference_data(reference);
+        let filename = output_dir.join(format!("{}.{}", day.date, extension));
+        let filename = filename.to_string_lossy().into_owned();
+        let mut output = Output::create(Some(&filename), config.output.format)?;
+        let num_snapshots = if config.output.extended {
+            let snapshots = builder.build_extended_on_schedule(&schedule);
+            for snapshot in snapshots.iter() {
+                output.write(snapshot)?;
+            }
+            snapshots.len()
+        } else {
+            let snapshots = builder.build_on_schedule(&schedule);
+            for snapshot in snapshots.iter() {
+                output.write(snapshot)?;
+            }
+            snapshots.len()
+        };
+        output.flush()?;
+
+        let instruments = builder.books().count();
+        prev_closes.update(builder.books().map(|book| (book.inst_id(), book.close)));
+
+        index.push(IndexRow {
+            date: day.date,
+            orders: num_orders,
+            trades: num_trades,
+            instruments,
+            snapshots: num_snapshots,
+            with_reference,
+            output: filename,
+        });
+    }
+
+    let filename = output_dir.join(format!("index.{}", extension));
+    let mut output = Output::create(Some(&filename.to_string_lossy()), config.output.format)?;
+    for row in index.iter() {
+        output.write(row)?;
+    }
+    output.flush()?;
+    fs::write(output_dir.join("config.toml"), toml::to_string(&config)?)?;
+    return Ok(());
+}
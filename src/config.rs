@@ -0,0 +1,340 @@
+use reconstruct::latency::LatencyThresholds;
+use reconstruct::md;
+use reconstruct::schedule::{self, Schedule};
+use reconstruct::snapshot_builder::ClockType;
+use reconstruct::surveillance::Thresholds;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+type Matches<'a> = clap::ArgMatches<'a>;
+
+// knobs of a run, read from a toml file given by --config,
+// flags on the command line take precedence over the file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub input: InputConfig,
+    pub book: BookConfig,
+    pub schedule: ScheduleConfig,
+    pub output: OutputConfig,
+    // alert thresholds of the surveil subcommand, the defaults if not given
+    pub surveillance: Option<Thresholds>,
+    // buckets, bursts and stalls of the latency subcommand, the defaults if not given
+    pub latency: Option<LatencyThresholds>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    pub order: Option<String>,
+    pub trade: Option<String>,
+    // root of YYYYMMDD directories for the batch subcommand
+    pub data_dir: Option<String>,
+    pub symbols: Vec<i32>,
+    pub prefix: Option<String>,
+    // arrival time of day, e.g. "09:30:00"
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub dedupe: Dedupe,
+}
+
+// what to do with rows flagged __isRepeated
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dedupe {
+    #[default]
+    Keep,
+    SkipRepeated,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BookConfig {
+    pub init: Option<String>,
+    pub warm_start: Option<String>,
+    // which clock orders events, "arrival" or "exchange"
+    pub clock: Option<Clock>,
+    // levels on each side printed by the book subcommand
+    pub depth: Option<usize>,
+    pub threads: Option<usize>,
+    pub partition: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Clock {
+    Arrival,
+    Exchange,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+    // exchange times of day, e.g. ["10:00:00", "14:00:00"]
+    pub at: Vec<String>,
+    pub every: Option<i64>,
+    pub mdlog: Option<String>,
+    pub every_event: bool,
+    // the default, so only needed to say it explicitly in batch runs
+    pub exchange_cadence: bool,
+    // (start, end) exchange times of day for every and the exchange cadence,
+    // the SZSE sessions if empty
+    pub sessions: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    // stdout if not given
+    pub path: Option<String>,
+    // per day outputs of the batch subcommand
+    pub dir: Option<String>,
+    pub format: Format,
+    // snapshots with high, low, vwap, previous close and totals of each side
+    pub extended: bool,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    #[default]
+    Csv,
+    Json,
+}
+
+fn override_with(value: &mut Option<String>, matches: &Matches, name: &str) {
+    if let Some(v) = matches.value_of(name) {
+        *value = Some(v.to_string());
+    }
+}
+
+impl Config {
+    pub fn load(filename: &str) -> Result<Config, Box<dyn Error>> {
+        let config: Config = toml::from_str(&fs::read_to_string(filename)?)?;
+        if let Some(latency) = &config.latency {
+            latency.validate()?;
+        }
+        return Ok(config);
+    }
+
+    // the file given by --config if any, with flags of the subcommand applied
+    pub fn from_args(matches: &Matches) -> Result<Config, Box<dyn Error>> {
+        let mut config = match matches.value_of("config") {
+            Some(filename) => Config::load(filename)?,
+            None => Config::default(),
+        };
+
+        let input = &mut config.input;
+        override_with(&mut input.order, matches, "order");
+        override_with(&mut input.trade, matches, "trade");
+        override_with(&mut input.data_dir, matches, "data-dir");
+        if let Some(values) = matches.values_of("symbols") {
+            input.symbols = Vec::new();
+            for value in values {
+                input.symbols.push(value.parse::<i32>()?);
+            }
+        }
+        override_with(&mut input.prefix, matches, "prefix");
+        override_with(&mut input.start, matches, "start");
+        override_with(&mut input.end, matches, "end");
+        match matches.value_of("dedupe") {
+            Some("keep") => input.dedupe = Dedupe::Keep,
+            Some("skip-repeated") => input.dedupe = Dedupe::SkipRepeated,
+            _ => {}
+        }
+
+        let book = &mut config.book;
+        override_with(&mut book.init, matches, "init");
+        override_with(&mut book.warm_start, matches, "warm-start");
+        match matches.value_of("clock") {
+            Some("arrival") => book.clock = Some(Clock::Arrival),
+            Some("exchange") => book.clock = Some(Clock::Exchange),
+            _ => {}
+        }
+        if let Some(depth) = matches.value_of("depth") {
+            book.depth = Some(depth.parse::<usize>()?);
+        }
+        if let Some(threads) = matches.value_of("threads") {
+            book.threads = Some(threads.parse::<usize>()?);
+        }
+        override_with(&mut book.partition, matches, "partition");
+
+        // a schedule on the command line replaces the one in the file
+        let schedule = &mut config.schedule;
+        if matches.is_present("at")
+            || matches.is_present("every")
+            || matches.is_present("exchange-cadence")
+            || matches.is_present("mdlog")
+            || matches.is_present("every-event")
+        {
+            let sessions = schedule.sessions.clone();
+            *schedule = ScheduleConfig {
+                sessions,
+                ..ScheduleConfig::default()
+            };
+        }
+        if let Some(values) = matches.values_of("at") {
+            schedule.at = values.map(|v| v.to_string()).collect();
+        }
+        if let Some(every) = matches.value_of("every") {
+            schedule.every = Some(every.parse::<i64>()?);
+        }
+        override_with(&mut schedule.mdlog, matches, "mdlog");
+        schedule.every_event |= matches.is_present("every-event");
+        schedule.exchange_cadence |= matches.is_present("exchange-cadence");
+
+        override_with(&mut config.output.path, matches, "output");
+        override_with(&mut config.output.dir, matches, "output-dir");
+        config.output.extended |= matches.is_present("extended");
+        match matches.value_of("format") {
+            Some("csv") => config.output.format = Format::Csv,
+            Some("json") => config.output.format = Format::Json,
+            _ => {}
+        }
+        return Ok(config);
+    }
+
+    pub fn filter(&self) -> Result<md::Filter, Box<dyn Error>> {
+        let parse = |time: &Option<String>| -> Result<Option<i64>, Box<dyn Error>> {
+            return match time {
+                Some(time) => Ok(Some(md::ExchangeTime::parse(0, time)?.millis)),
+                None => Ok(None),
+            };
+        };
+        return Ok(md::Filter {
+            securities: self.input.symbols.iter().copied().collect(),
+            prefix: self.input.prefix.clone(),
+            start_millis: parse(&self.input.start)?,
+            end_millis: parse(&self.input.end)?,
+            skip_repeated: self.input.dedupe == Dedupe::SkipRepeated,
+        });
+    }
+
+    // the default depends on the subcommand, a warm start reconciles
+    // with exchange snapshots so it always defaults to the exchange clock
+    pub fn clock_type(&self, default: ClockType) -> ClockType {
+        return match self.book.clock {
+            Some(Clock::Arrival) => ClockType::Arrival,
+            Some(Clock::Exchange) => ClockType::Exchange,
+            None if self.book.warm_start.is_some() => ClockType::Exchange,
+            None => default,
+        };
+    }
+
+    // nothing in the file or on the command line
+    pub fn has_schedule(&self) -> bool {
+        let schedule = &self.schedule;
+        return !schedule.at.is_empty()
+            || schedule.every.is_some()
+            || schedule.mdlog.is_some()
+            || schedule.every_event
+            || schedule.exchange_cadence;
+    }
+
+    // interval schedules are laid out on our own grid when sessions are configured
+    pub fn schedule(
+        &self,
+        date: i32,
+        filter: &md::Filter,
+        clock_type: ClockType,
+    ) -> Result<Schedule, Box<dyn Error>> {
+        let schedule = &self.schedule;
+        if !schedule.at.is_empty() {
+            let mut timestamps = Vec::new();
+            for value in schedule.at.iter() {
+                timestamps.push(md::ExchangeTime::parse(date, value)?.to_clock());
+            }
+            return Ok(Schedule::Timestamps(timestamps));
+        }
+        if let Some(mdlog) = &schedule.mdlog {
+            return Schedule::from_mdlog(mdlog, filter, clock_type);
+        }
+        if schedule.every_event {
+            return Ok(Schedule::EveryEvent);
+        }
+
+        let interval = schedule.every.unwrap_or(schedule::EXCHANGE_CADENCE_MILLIS);
+        if interval <= 0 {
+            return Err(format!("snapshot interval must be positive, got {} ms", interval).into());
+        }
+        if schedule.sessions.is_empty() {
+            return Ok(match schedule.every {
+                Some(every) => Schedule::Interval(every),
+                None => Schedule::ExchangeCadence,
+            });
+        }
+        let mut sessions = Vec::new();
+        for (start, end) in schedule.sessions.iter() {
+            sessions.push((
+                md::ExchangeTime::parse(0, start)?.millis,
+                md::ExchangeTime::parse(0, end)?.millis,
+            ));
+        }
+        return Ok(Schedule::Timestamps(schedule::session_grid(
+            date, interval, &sessions,
+        )));
+    }
+
+    // next to the output, e.g. snapshots.csv -> snapshots.config.toml,
+    // nothing is written when the output goes to stdout
+    pub fn save_next_to_output(&self) -> Result<(), Box<dyn Error>> {
+        let output = match &self.output.path {
+            Some(path) => Path::new(path),
+            None => return Ok(()),
+        };
+        fs::write(output.with_extension("config.toml"), toml::to_string(self)?)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATE: i32 = 20200423;
+
+    fn schedule(toml: &str) -> Result<Schedule, Box<dyn Error>> {
+        let config: Config = toml::from_str(toml).unwrap();
+        return config.schedule(DATE, &config.filter()?, ClockType::Arrival);
+    }
+
+    fn clock(time: &str) -> i64 {
+        return md::ExchangeTime::parse(DATE, time).unwrap().to_clock();
+    }
+
+    #[test]
+    fn interval_must_be_positive() {
+        assert!(schedule("[schedule]\nevery = 0").is_err());
+        assert!(schedule("[schedule]\nevery = -3000").is_err());
+        let sessions = "[schedule]\nevery = 0\nsessions = [[\"09:30:00\", \"10:00:00\"]]";
+        assert!(schedule(sessions).is_err());
+    }
+
+    #[test]
+    fn sessions_lay_out_their_own_grid() {
+        let toml = "[schedule]\nevery = 600000\nsessions = [[\"09:35:00\", \"10:05:00\"], [\"13:00:00\", \"13:10:00\"]]";
+        let timestamps = match schedule(toml).unwrap() {
+            Schedule::Timestamps(timestamps) => timestamps,
+            _ => panic!("sessions give timestamps"),
+        };
+        let expected: Vec<i64> = ["09:40:00", "09:50:00", "10:00:00", "13:00:00", "13:10:00"]
+            .iter()
+            .map(|time| clock(time))
+            .collect();
+        assert_eq!(timestamps, expected);
+
+        assert!(matches!(
+            schedule("[schedule]\nevery = 60000").unwrap(),
+            Schedule::Interval(60000)
+        ));
+        assert!(matches!(schedule("").unwrap(), Schedule::ExchangeCadence));
+        assert!(matches!(
+            schedule("[schedule]\nevery_event = true").unwrap(),
+            Schedule::EveryEvent
+        ));
+        let at = schedule("[schedule]\nat = [\"10:00:00\"]").unwrap();
+        assert_eq!(at.timestamps(DATE), Some(vec![clock("10:00:00")]));
+    }
+}
@@ -0,0 +1,164 @@
+use crate::md;
+use crate::observer::BookObserver;
+use crate::snapshot_builder::Book;
+use std::collections::HashSet;
+use std::error::Error;
+
+/// when to dump a book, e.g. to compare with an exchange snapshot
+#[derive(Debug, Copy, Clone)]
+pub enum DumpCondition {
+    /// the book has seen exactly this many trades
+    NumTrades(i64),
+    /// the first message at or after this time of day (exchange time)
+    ExchangeTime(i64),
+    /// the order with this ApplSeqNum once it is added and again if it is
+    /// cancelled, SZSE cancels refer to the order, or the execution with it
+    ApplSeqNum(i64),
+}
+
+impl DumpCondition {
+    /// "num_trades=4277", "time=09:30:00.000" or "seq=12345"
+    pub fn parse(s: &str) -> Result<DumpCondition, Box<dyn Error>> {
+        let (field, value) = match s.find('=') {
+            Some(pos) => (&s[..pos], &s[pos + 1..]),
+            None => return Err(format!("invalid dump condition '{}'", s).into()),
+        };
+        match field {
+            "num_trades" => Ok(DumpCondition::NumTrades(value.parse::<i64>()?)),
+            "time" => Ok(DumpCondition::ExchangeTime(
+                md::ExchangeTime::parse(0, value)?.millis,
+            )),
+            "seq" => Ok(DumpCondition::ApplSeqNum(value.parse::<i64>()?)),
+            _ => Err(format!("unknown dump condition field '{}'", field).into()),
+        }
+    }
+}
+
+/// logs the snapshot of a book at info level on target "book::dump"
+pub struct BookDumper {
+    // None for all instruments
+    inst_id: Option<i32>,
+    condition: DumpCondition,
+    // instruments already dumped for an ExchangeTime condition
+    dumped_: HashSet<i32>,
+    num_dumps_: usize,
+}
+
+impl BookDumper {
+    /// dumps the books of inst_id, or of every instrument for None, when condition is hit
+    pub fn new(inst_id: Option<i32>, condition: DumpCondition) -> BookDumper {
+        BookDumper {
+            inst_id,
+            condition,
+            dumped_: HashSet::new(),
+            num_dumps_: 0,
+        }
+    }
+
+    /// books dumped so far
+    pub fn num_dumps(&self) -> usize {
+        return self.num_dumps_;
+    }
+
+    /// "[inst:]condition", e.g. "2385:num_trades=4277"
+    pub fn parse(s: &str) -> Result<BookDumper, Box<dyn Error>> {
+        return match s.find(':') {
+            Some(pos) if !s[..pos].contains('=') => Ok(BookDumper::new(
+                Some(s[..pos].parse::<i32>()?),
+                DumpCondition::parse(&s[pos + 1..])?,
+            )),
+            _ => Ok(BookDumper::new(None, DumpCondition::parse(s)?)),
+        };
+    }
+
+    // seq is the ApplSeqNum of the order or the execution
+    fn check(&mut self, book: &Book, seq: Option<i64>, traded: bool) {
+        if let Some(inst_id) = self.inst_id {
+            if inst_id != book.inst_id() {
+                return;
+            }
+        }
+
+        let hit = match self.condition {
+            DumpCondition::NumTrades(n) => traded && book.num_trades == n,
+            DumpCondition::ExchangeTime(millis) => {
+                book.exchange_time.millis >= millis && self.dumped_.insert(book.inst_id())
+            }
+            DumpCondition::ApplSeqNum(n) => seq == Some(n),
+        };
+        if hit {
+            self.num_dumps_ += 1;
+            log::info!(
+                target: "book::dump",
+                "inst={} ts={} condition={:?} snapshot={:?}",
+                book.inst_id(),
+                book.timestamp,
+                self.condition,
+                book.to_snapshot()
+            );
+        }
+    }
+}
+
+impl BookObserver for BookDumper {
+    fn on_order_added(&mut self, book: &Book, order: &md::Order) {
+        self.check(book, Some(order.ApplSeqNum), false);
+    }
+
+    fn on_order_cancelled(&mut self, book: &Book, order: &md::Order, _quantity: i64) {
+        self.check(book, Some(order.ApplSeqNum), false);
+    }
+
+    fn on_order_executed(&mut self, book: &Book, trade: &md::Trade) {
+        self.check(book, Some(trade.ApplSeqNum), true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md::testing::{order, trade};
+    use crate::md::Side;
+    use crate::snapshot_builder::SnapshotBuilder;
+    use std::sync::{Arc, Mutex};
+
+    fn num_dumps(condition: &str) -> usize {
+        let orders = vec![
+            order(2290, 1, "10:00:00.000", Side::Bid, 51000, 300),
+            order(2290, 2, "10:00:01.000", Side::Ask, 51000, 100),
+            order(2385, 4, "10:00:02.000", Side::Bid, 95000, 100),
+        ];
+        let trades = vec![
+            trade(2290, 3, "10:00:01.000", 51000, 100, 1, 2),
+            trade(2290, 5, "10:00:03.000", 0, 200, 1, 0),
+        ];
+        let orders = orders.into_iter().map(Arc::new).collect();
+        let trades = trades.into_iter().map(Arc::new).collect();
+        let mut builder = SnapshotBuilder::new(orders, trades);
+        let dumper = Arc::new(Mutex::new(BookDumper::parse(condition).unwrap()));
+        builder.add_observer(Box::new(dumper.clone()));
+        builder.process_until(i64::MAX);
+        let dumps = dumper.lock().unwrap().num_dumps();
+        return dumps;
+    }
+
+    #[test]
+    fn seq_matches_orders_and_executions() {
+        // added, then cancelled by message 5
+        assert_eq!(num_dumps("seq=1"), 2);
+        assert_eq!(num_dumps("seq=3"), 1);
+        // the cancel message itself does not match
+        assert_eq!(num_dumps("seq=5"), 0);
+        assert_eq!(num_dumps("2385:seq=1"), 0);
+    }
+
+    #[test]
+    fn other_conditions() {
+        assert_eq!(num_dumps("num_trades=1"), 1);
+        // once per instrument
+        assert_eq!(num_dumps("time=10:00:00.500"), 2);
+        assert_eq!(num_dumps("2290:time=10:00:00.500"), 1);
+        assert!(DumpCondition::parse("seq").is_err());
+        assert!(DumpCondition::parse("ts=1").is_err());
+    }
+}
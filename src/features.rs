@@ -0,0 +1,298 @@
+use crate::md;
+use crate::observer::BookObserver;
+use crate::snapshot_builder::Book;
+use std::collections::BTreeMap;
+
+/// levels covered by the multi-level features, the depth of exchange snapshots
+pub const FEATURE_LEVELS: usize = 5;
+
+/// microstructure features of one book, prices in book units
+#[derive(Debug, Clone)]
+pub struct Features {
+    /// SecurityID of the instrument
+    pub inst_id: i32,
+    /// clock of the book
+    pub timestamp: i64,
+    /// exchange time of the book
+    pub exchange_time: md::ExchangeTime,
+    /// 0 unless both sides have a level, like the rest of the price features
+    pub mid: f64,
+    /// best ask minus best bid
+    pub spread: i64,
+    /// best prices weighted by the size on the opposite side
+    pub microprice: f64,
+    /// middle of the depth weighted average price of each side over FEATURE_LEVELS
+    pub weighted_mid: f64,
+    /// (bid - ask) / (bid + ask) of the best level quantities, in [-1, 1]
+    pub imbalance: f64,
+    /// order flow imbalance of each level since the previous features of the book,
+    /// ofi[0] is the classic best level OFI
+    pub ofi: [i64; FEATURE_LEVELS],
+    /// shares per tick that cumulative depth grows away from the best price
+    pub bid_slope: f64,
+    /// same as bid_slope for the ask side
+    pub ask_slope: f64,
+}
+
+impl Features {
+    /// sum of the OFI of every level
+    pub fn multi_level_ofi(&self) -> i64 {
+        return self.ofi.iter().sum();
+    }
+}
+
+// (price, quantity) of the best levels, (0, 0) past the end of the book
+type Levels = [(i64, i64); FEATURE_LEVELS];
+
+#[derive(Default)]
+struct State {
+    bids: Levels,
+    asks: Levels,
+    ofi: [i64; FEATURE_LEVELS],
+}
+
+fn top_levels(book: &Book, side: md::Side) -> Levels {
+    let mut levels = [(0, 0); FEATURE_LEVELS];
+    for (idx, level) in book.depth(side).take(FEATURE_LEVELS).enumerate() {
+        levels[idx] = level;
+    }
+    return levels;
+}
+
+// contribution of one level to OFI, better prices add the new queue and
+// worse ones remove the old, a missing level is the worst possible price
+fn level_flow(side: md::Side, before: (i64, i64), after: (i64, i64)) -> i64 {
+    let price = |(price, quantity): (i64, i64)| match side {
+        md::Side::Bid => price,
+        _ if quantity == 0 => i64::MIN,
+        // asks improve downwards
+        _ => -price,
+    };
+    let (old, new) = (price(before), price(after));
+    if new > old {
+        return after.1;
+    } else if new < old {
+        return -before.1;
+    }
+    return after.1 - before.1;
+}
+
+// least squares slope of cumulative quantity against distance from the best price in ticks
+fn depth_slope(levels: &Levels) -> f64 {
+    let best = levels[0].0;
+    let mut points = Vec::with_capacity(FEATURE_LEVELS);
+    let mut cumulative = 0;
+    for (price, quantity) in levels.iter().filter(|(_, quantity)| *quantity > 0) {
+        cumulative += quantity;
+        let ticks = (price - best).abs() as f64 / Book::TICK_SIZE as f64;
+        points.push((ticks, cumulative as f64));
+    }
+    if points.len() < 2 {
+        return 0.0;
+    }
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    if variance == 0.0 {
+        return 0.0;
+    }
+    return covariance / variance;
+}
+
+fn weighted_price(levels: &Levels) -> f64 {
+    let quantity: i64 = levels.iter().map(|(_, quantity)| quantity).sum();
+    if quantity == 0 {
+        return 0.0;
+    }
+    let amount: i64 = levels
+        .iter()
+        .map(|(price, quantity)| price * quantity)
+        .sum();
+    return amount as f64 / quantity as f64;
+}
+
+/// computes features per instrument as books change, usable as an observer;
+/// features are taken whenever the builder takes a snapshot, so they follow
+/// the same schedule
+pub struct FeatureCalculator {
+    // key: SecurityID
+    states_: BTreeMap<i32, State>,
+    features_: Vec<Features>,
+}
+
+impl Default for FeatureCalculator {
+    fn default() -> Self {
+        FeatureCalculator::new()
+    }
+}
+
+impl FeatureCalculator {
+    /// no features until a book changes
+    pub fn new() -> FeatureCalculator {
+        FeatureCalculator {
+            states_: BTreeMap::new(),
+            features_: Vec::new(),
+        }
+    }
+
+    /// accumulate OFI of the change since the last update, called after every
+    /// message that touched a level, repeated calls for one message add nothing
+    pub fn update(&mut self, book: &Book) {
+        let bids = top_levels(book, md::Side::Bid);
+        let asks = top_levels(book, md::Side::Ask);
+        let state = self.states_.entry(book.inst_id()).or_default();
+        if state.bids == bids && state.asks == asks {
+            return;
+        }
+        for level in 0..FEATURE_LEVELS {
+            state.ofi[level] += level_flow(md::Side::Bid, state.bids[level], bids[level])
+                - level_flow(md::Side::Ask, state.asks[level], asks[level]);
+        }
+        state.bids = bids;
+        state.asks = asks;
+    }
+
+    /// features of the book as it is now, OFI starts over from here
+    pub fn features(&mut self, book: &Book) -> Features {
+        self.update(book);
+        let state = self.states_.get_mut(&book.inst_id()).unwrap();
+        let ofi = std::mem::take(&mut state.ofi);
+        let (bids, asks) = (&state.bids, &state.asks);
+
+        let mut features = Features {
+            inst_id: book.inst_id(),
+            timestamp: book.timestamp,
+            exchange_time: book.exchange_time,
+            mid: 0.0,
+            spread: 0,
+            microprice: 0.0,
+            weighted_mid: 0.0,
+            imbalance: 0.0,
+            ofi,
+            bid_slope: depth_slope(bids),
+            ask_slope: depth_slope(asks),
+        };
+        let ((bid, bid_quantity), (ask, ask_quantity)) = (bids[0], asks[0]);
+        if bid_quantity > 0 && ask_quantity > 0 {
+            let (bid_q, ask_q) = (bid_quantity as f64, ask_quantity as f64);
+            features.mid = (bid + ask) as f64 / 2.0;
+            features.spread = ask - bid;
+            features.microprice = (bid as f64 * ask_q + ask as f64 * bid_q) / (bid_q + ask_q);
+            features.weighted_mid = (weighted_price(bids) + weighted_price(asks)) / 2.0;
+        }
+        if bid_quantity + ask_quantity > 0 {
+            features.imbalance =
+                (bid_quantity - ask_quantity) as f64 / (bid_quantity + ask_quantity) as f64;
+        }
+        return features;
+    }
+
+    /// features taken on snapshots in the order they were taken
+    pub fn take_features(&mut self) -> Vec<Features> {
+        return std::mem::take(&mut self.features_);
+    }
+}
+
+impl BookObserver for FeatureCalculator {
+    fn on_level_changed(&mut self, book: &Book, _side: md::Side, _price: i64, _quantity: i64) {
+        self.update(book);
+    }
+
+    fn on_snapshot(&mut self, book: &Book) {
+        let features = self.features(book);
+        self.features_.push(features);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md::Side;
+
+    // a book of 2290 with (price, quantity) levels
+    fn book(bids: &[(i64, i64)], asks: &[(i64, i64)]) -> Book {
+        let mut book = Book::new(2290);
+        for (price, quantity) in bids {
+            book.apply_change(Side::Bid, *price, *quantity);
+        }
+        for (price, quantity) in asks {
+            book.apply_change(Side::Ask, *price, *quantity);
+        }
+        return book;
+    }
+
+    #[test]
+    fn price_features_of_the_best_levels() {
+        let mut calculator = FeatureCalculator::new();
+        let features = calculator.features(&book(&[(50000, 100)], &[(50200, 300)]));
+        assert_eq!(features.mid, 50100.0);
+        assert_eq!(features.spread, 200);
+        // closer to the bid, the ask has more to get through
+        assert_eq!(features.microprice, 50050.0);
+        assert_eq!(features.imbalance, -0.5);
+
+        // one side only, no price features but still an imbalance
+        let features = calculator.features(&book(&[(50000, 100)], &[]));
+        assert_eq!(features.mid, 0.0);
+        assert_eq!(features.microprice, 0.0);
+        assert_eq!(features.imbalance, 1.0);
+    }
+
+    #[test]
+    fn ofi_signs_follow_the_flow() {
+        let mut calculator = FeatureCalculator::new();
+        let mut book = book(&[(50000, 100)], &[(50200, 200), (50300, 100)]);
+
+        // from nothing, both sides appear: bids add, asks take away
+        let features = calculator.features(&book);
+        assert_eq!(features.ofi, [100 - 200, -100, 0, 0, 0]);
+
+        // the best bid grows
+        book.apply_change(Side::Bid, 50000, 50);
+        assert_eq!(calculator.features(&book).ofi, [50, 0, 0, 0, 0]);
+
+        // a better bid adds its queue, the old best moves down a level
+        book.apply_change(Side::Bid, 50100, 30);
+        let features = calculator.features(&book);
+        assert_eq!(features.ofi, [30, 150, 0, 0, 0]);
+        assert_eq!(features.multi_level_ofi(), 180);
+
+        // the best ask is depleted, what was there counts as bought
+        book.apply_change(Side::Ask, 50200, -200);
+        let features = calculator.features(&book);
+        assert_eq!(features.ofi, [200, 100, 0, 0, 0]);
+
+        // the last ask is gone, a missing level is the worst price there is
+        book.apply_change(Side::Ask, 50300, -100);
+        assert_eq!(calculator.features(&book).ofi, [100, 0, 0, 0, 0]);
+        // and an ask at any price improves on it
+        book.apply_change(Side::Ask, 60000, 10);
+        assert_eq!(calculator.features(&book).ofi, [-10, 0, 0, 0, 0]);
+
+        // the best bid cancelled, the one behind becomes the best
+        book.apply_change(Side::Bid, 50100, -30);
+        let features = calculator.features(&book);
+        assert_eq!(features.ofi, [-30, -150, 0, 0, 0]);
+        assert_eq!(features.multi_level_ofi(), -180);
+    }
+
+    #[test]
+    fn ofi_adds_up_between_features() {
+        let mut calculator = FeatureCalculator::new();
+        let mut book = book(&[(50000, 100)], &[(50200, 100)]);
+        calculator.features(&book);
+        book.apply_change(Side::Bid, 50000, 50);
+        calculator.update(&book);
+        // a repeated update for the same change adds nothing
+        calculator.update(&book);
+        book.apply_change(Side::Ask, 50200, 20);
+        calculator.update(&book);
+        assert_eq!(calculator.features(&book).ofi, [50 - 20, 0, 0, 0, 0]);
+        assert_eq!(calculator.features(&book).ofi, [0; FEATURE_LEVELS]);
+    }
+}
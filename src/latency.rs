@@ -0,0 +1,556 @@
+use crate::md;
+use crate::surveillance::percentile;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::Arc;
+
+/// how the analyzer groups latencies and what counts as a burst or a stall
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LatencyThresholds {
+    /// latency distributions are per channel and bucket of exchange time of day
+    pub bucket_millis: i64,
+    /// messages of a channel arriving within this many microseconds of the
+    /// previous one belong to the same burst
+    pub burst_gap_micros: i64,
+    /// messages a burst needs to be reported
+    pub burst_messages: usize,
+    /// a channel stalls when the latency of a message jumps by this many
+    /// milliseconds over the previous one
+    pub stall_millis: i64,
+}
+
+impl Default for LatencyThresholds {
+    fn default() -> Self {
+        LatencyThresholds {
+            bucket_millis: 300_000,
+            burst_gap_micros: 100,
+            burst_messages: 200,
+            stall_millis: 50,
+        }
+    }
+}
+
+impl LatencyThresholds {
+    /// buckets have to be at least a millisecond
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.bucket_millis <= 0 {
+            return Err(format!(
+                "latency buckets must be at least a millisecond, got {} ms",
+                self.bucket_millis
+            )
+            .into());
+        }
+        return Ok(());
+    }
+}
+
+/// microseconds from TransactTime to clockAtArrival, TransactTime only has
+/// milliseconds so small negative values are normal
+pub fn order_latency(order: &md::Order) -> i64 {
+    return order.clockAtArrival - order.exchange_time().to_clock();
+}
+
+/// same as order_latency for a trade
+pub fn trade_latency(trade: &md::Trade) -> i64 {
+    return trade.clockAtArrival - trade.exchange_time().to_clock();
+}
+
+/// feed latency of a channel in one bucket of the day, in microseconds
+#[derive(Debug, Clone)]
+pub struct LatencyBucket {
+    /// ChannelNo of the messages
+    pub channel: i32,
+    /// exchange time of day the bucket starts at, in milliseconds
+    pub start_millis: i64,
+    /// messages in the bucket
+    pub messages: usize,
+    /// mean latency
+    pub mean: f64,
+    /// lowest latency
+    pub min: i64,
+    /// median latency
+    pub p50: i64,
+    /// 90th percentile latency
+    pub p90: i64,
+    /// 99th percentile latency
+    pub p99: i64,
+    /// highest latency
+    pub max: i64,
+}
+
+/// messages of a channel arriving back to back
+#[derive(Debug, Clone)]
+pub struct Burst {
+    /// ChannelNo of the messages
+    pub channel: i32,
+    /// arrival clocks of the first and last message
+    pub start: i64,
+    /// arrival clock of the last message
+    pub end: i64,
+    /// messages in the burst
+    pub messages: usize,
+    /// highest latency within the burst, in microseconds
+    pub max_latency: i64,
+}
+
+/// a gap in a channel that the exchange did not have
+#[derive(Debug, Clone)]
+pub struct Stall {
+    /// ChannelNo of the gap
+    pub channel: i32,
+    /// arrival clock of the message after the gap
+    pub timestamp: i64,
+    /// microseconds between the arrivals around the gap
+    pub arrival_gap: i64,
+    /// microseconds between the exchange times around the gap
+    pub exchange_gap: i64,
+    /// how much later than the previous message it arrived, in microseconds
+    pub latency_jump: i64,
+}
+
+// the last message and the burst in progress of a channel
+struct Channel {
+    arrival: i64,
+    exchange: i64,
+    latency: i64,
+    burst: Burst,
+}
+
+/// feed latency distributions, bursts and stalls of every channel
+pub struct LatencyAnalyzer {
+    thresholds: LatencyThresholds,
+    // key: (ChannelNo, bucket start)
+    latencies_: BTreeMap<(i32, i64), Vec<i64>>,
+    // key: ChannelNo
+    channels_: BTreeMap<i32, Channel>,
+    bursts_: Vec<Burst>,
+    stalls_: Vec<Stall>,
+}
+
+impl LatencyAnalyzer {
+    /// fails if the thresholds are not valid
+    pub fn new(thresholds: LatencyThresholds) -> Result<LatencyAnalyzer, Box<dyn Error>> {
+        thresholds.validate()?;
+        return Ok(LatencyAnalyzer {
+            thresholds,
+            latencies_: BTreeMap::new(),
+            channels_: BTreeMap::new(),
+            bursts_: Vec::new(),
+            stalls_: Vec::new(),
+        });
+    }
+
+    /// every order and trade, in the order each channel delivered them
+    pub fn add_events(&mut self, orders: &[Arc<md::Order>], trades: &[Arc<md::Trade>]) {
+        // (ChannelNo, clockAtArrival, ApplSeqNum, exchange clock)
+        let mut events: Vec<(i32, i64, i64, i64)> = orders
+            .iter()
+            .map(|order| {
+                let exchange = order.exchange_time().to_clock();
+                (
+                    order.ChannelNo,
+                    order.clockAtArrival,
+                    order.ApplSeqNum,
+                    exchange,
+                )
+            })
+            .chain(trades.iter().map(|trade| {
+                let exchange = trade.exchange_time().to_clock();
+                (
+                    trade.ChannelNo,
+                    trade.clockAtArrival,
+                    trade.ApplSeqNum,
+                    exchange,
+                )
+            }))
+            .collect();
+        events.sort_unstable();
+        for (channel, arrival, _, exchange) in events {
+            self.add(channel, exchange, arrival);
+        }
+    }
+
+    /// one message, calls must be in arrival order within a channel
+    pub fn add(&mut self, channel: i32, exchange: i64, arrival: i64) {
+        let latency = arrival - exchange;
+        let millis = md::ExchangeTime::from_clock(exchange).millis;
+        let bucket = millis - millis % self.thresholds.bucket_millis;
+        self.latencies_
+            .entry((channel, bucket))
+            .or_default()
+            .push(latency);
+
+        let burst = Burst {
+            channel,
+            start: arrival,
+            end: arrival,
+            messages: 1,
+            max_latency: latency,
+        };
+        let last = match self.channels_.get_mut(&channel) {
+            Some(last) => last,
+            None => {
+                self.channels_.insert(
+                    channel,
+                    Channel {
+                        arrival,
+                        exchange,
+                        latency,
+                        burst,
+                    },
+                );
+                return;
+            }
+        };
+
+        let latency_jump = latency - last.latency;
+        if latency_jump >= self.thresholds.stall_millis * 1000 {
+            self.stalls_.push(Stall {
+                channel,
+                timestamp: arrival,
+                arrival_gap: arrival - last.arrival,
+                exchange_gap: exchange - last.exchange,
+                latency_jump,
+            });
+        }
+        if arrival - last.arrival <= self.thresholds.burst_gap_micros {
+            last.burst.end = arrival;
+            last.burst.messages += 1;
+            last.burst.max_latency = last.burst.max_latency.max(latency);
+        } else {
+            let done = std::mem::replace(&mut last.burst, burst);
+            if done.messages >= self.thresholds.burst_messages {
+                self.bursts_.push(done);
+            }
+        }
+        last.arrival = arrival;
+        last.exchange = exchange;
+        last.latency = latency;
+    }
+
+    /// distributions by channel and time of day
+    pub fn buckets(&self) -> Vec<LatencyBucket> {
+        let mut rows = Vec::with_capacity(self.latencies_.len());
+        for ((channel, start_millis), latencies) in self.latencies_.iter() {
+            let mut latencies = latencies.clone();
+            latencies.sort_unstable();
+            let messages = latencies.len();
+            rows.push(LatencyBucket {
+                channel: *channel,
+                start_millis: *start_millis,
+                messages,
+                mean: latencies.iter().sum::<i64>() as f64 / messages as f64,
+                min: latencies[0],
+                p50: percentile(&latencies, 50),
+                p90: percentile(&latencies, 90),
+                p99: percentile(&latencies, 99),
+                max: latencies[messages - 1],
+            });
+        }
+        return rows;
+    }
+
+    /// bursts by arrival, including the ones still in progress
+    pub fn take_bursts(&mut self) -> Vec<Burst> {
+        let mut bursts = std::mem::take(&mut self.bursts_);
+        for channel in self.channels_.values() {
+            if channel.burst.messages >= self.thresholds.burst_messages {
+                bursts.push(channel.burst.clone());
+            }
+        }
+        bursts.sort_by_key(|burst| (burst.start, burst.channel));
+        return bursts;
+    }
+
+    /// stalls by arrival
+    pub fn take_stalls(&mut self) -> Vec<Stall> {
+        let mut stalls = std::mem::take(&mut self.stalls_);
+        stalls.sort_by_key(|stall| (stall.timestamp, stall.channel));
+        return stalls;
+    }
+}
+
+/// draws latencies from measured ones, by time of day when there are enough,
+/// with its own generator so runs with the same seed are the same
+#[derive(Debug, Clone)]
+pub struct LatencyModel {
+    // key: bucket start in milliseconds of exchange time of day, sorted values
+    buckets: BTreeMap<i64, Vec<i64>>,
+    bucket_millis: i64,
+    // sorted values of the whole day
+    all: Vec<i64>,
+    // xorshift64 state, never 0
+    state: u64,
+}
+
+impl LatencyModel {
+    // a bucket with fewer samples draws from the whole day
+    const MIN_BUCKET_SAMPLES: usize = 100;
+
+    /// always the same latency
+    pub fn constant(latency: i64) -> LatencyModel {
+        LatencyModel {
+            buckets: BTreeMap::new(),
+            bucket_millis: 0,
+            all: vec![latency],
+            state: 1,
+        }
+    }
+
+    /// (exchange time of day in milliseconds, latency) samples
+    pub fn empirical(
+        samples: &[(i64, i64)],
+        bucket_millis: i64,
+        seed: u64,
+    ) -> Result<LatencyModel, Box<dyn Error>> {
+        if samples.is_empty() {
+            return Err("no latency samples".into());
+        }
+        if bucket_millis <= 0 {
+            return Err(format!("invalid latency bucket of {} ms", bucket_millis).into());
+        }
+        let mut buckets: BTreeMap<i64, Vec<i64>> = BTreeMap::new();
+        for (millis, latency) in samples {
+            buckets
+                .entry(millis - millis % bucket_millis)
+                .or_default()
+                .push(*latency);
+        }
+        buckets.retain(|_, latencies| latencies.len() >= LatencyModel::MIN_BUCKET_SAMPLES);
+        for latencies in buckets.values_mut() {
+            latencies.sort_unstable();
+        }
+        let mut all: Vec<i64> = samples.iter().map(|(_, latency)| *latency).collect();
+        all.sort_unstable();
+        return Ok(LatencyModel {
+            buckets,
+            bucket_millis,
+            all,
+            state: seed.max(1),
+        });
+    }
+
+    /// the feed latency of every order and trade
+    pub fn measured(
+        orders: &[Arc<md::Order>],
+        trades: &[Arc<md::Trade>],
+        bucket_millis: i64,
+        seed: u64,
+    ) -> Result<LatencyModel, Box<dyn Error>> {
+        let samples: Vec<(i64, i64)> = orders
+            .iter()
+            .map(|order| (order.exchange_time().millis, order_latency(order)))
+            .chain(
+                trades
+                    .iter()
+                    .map(|trade| (trade.exchange_time().millis, trade_latency(trade))),
+            )
+            .collect();
+        return LatencyModel::empirical(&samples, bucket_millis, seed);
+    }
+
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        return x;
+    }
+
+    /// a latency in microseconds for a message at this exchange time of day
+    pub fn sample(&mut self, millis: i64) -> i64 {
+        let random = self.next_random();
+        let bucket = match self.bucket_millis {
+            0 => None,
+            bucket_millis => self.buckets.get(&(millis - millis % bucket_millis)),
+        };
+        let latencies = bucket.unwrap_or(&self.all);
+        return latencies[(random % latencies.len() as u64) as usize];
+    }
+
+    /// the events with clockAtArrival replaced by the exchange time plus a
+    /// sampled latency, every channel still delivers in ApplSeqNum order,
+    /// replay them on the arrival clock
+    pub fn retime(
+        &mut self,
+        orders: &[Arc<md::Order>],
+        trades: &[Arc<md::Trade>],
+    ) -> (Vec<Arc<md::Order>>, Vec<Arc<md::Trade>>) {
+        // (ChannelNo, ApplSeqNum, is trade, index)
+        let mut events: Vec<(i32, i64, bool, usize)> = orders
+            .iter()
+            .enumerate()
+            .map(|(idx, order)| (order.ChannelNo, order.ApplSeqNum, false, idx))
+            .chain(
+                trades
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, trade)| (trade.ChannelNo, trade.ApplSeqNum, true, idx)),
+            )
+            .collect();
+        events.sort_unstable();
+
+        let mut order_clocks = vec![0; orders.len()];
+        let mut trade_clocks = vec![0; trades.len()];
+        let mut last: Option<(i32, i64)> = None;
+        for (channel, _, is_trade, idx) in events {
+            let time = if is_trade {
+                trades[idx].exchange_time()
+            } else {
+                orders[idx].exchange_time()
+            };
+            let mut clock = time.to_clock() + self.sample(time.millis);
+            // a feed does not overtake itself
+            if let Some((last_channel, last_clock)) = last {
+                if last_channel == channel {
+                    clock = clock.max(last_clock + 1);
+                }
+            }
+            last = Some((channel, clock));
+            if is_trade {
+                trade_clocks[idx] = clock;
+            } else {
+                order_clocks[idx] = clock;
+            }
+        }
+
+        let mut retimed_orders: Vec<Arc<md::Order>> = orders
+            .iter()
+            .zip(order_clocks)
+            .map(|(order, clock)| {
+                let mut order = md::Order::clone(order);
+                order.clockAtArrival = clock;
+                Arc::new(order)
+            })
+            .collect();
+        let mut retimed_trades: Vec<Arc<md::Trade>> = trades
+            .iter()
+            .zip(trade_clocks)
+            .map(|(trade, clock)| {
+                let mut trade = md::Trade::clone(trade);
+                trade.clockAtArrival = clock;
+                Arc::new(trade)
+            })
+            .collect();
+        retimed_orders.sort_by_key(|order| order.clockAtArrival);
+        retimed_trades.sort_by_key(|trade| trade.clockAtArrival);
+        return (retimed_orders, retimed_trades);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md::testing::{clock, order, trade};
+    use crate::md::Side;
+
+    fn analyzer() -> LatencyAnalyzer {
+        let thresholds = LatencyThresholds {
+            burst_messages: 3,
+            ..LatencyThresholds::default()
+        };
+        return LatencyAnalyzer::new(thresholds).unwrap();
+    }
+
+    #[test]
+    fn bursts_are_messages_within_the_gap() {
+        let mut analyzer = analyzer();
+        let exchange = clock("10:00:00.000");
+        let arrival = exchange + 1000;
+        // gaps of 50 and 100 keep the burst going, 101 ends it
+        for gap in [0, 50, 150, 250, 351, 400, 2000] {
+            analyzer.add(2011, exchange, arrival + gap);
+            // another channel in between does not break it
+            analyzer.add(2012, exchange, arrival + gap + 5000);
+        }
+        let bursts = analyzer.take_bursts();
+        let found: Vec<(i32, i64, i64, usize)> = bursts
+            .iter()
+            .map(|burst| (burst.channel, burst.start, burst.end, burst.messages))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (2011, arrival, arrival + 250, 4),
+                (2012, arrival + 5000, arrival + 5250, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn stalls_are_latency_jumps() {
+        let mut analyzer = analyzer();
+        let exchange = clock("10:00:00.000");
+        analyzer.add(2011, exchange, exchange + 1000);
+        // just under stall_millis later than the previous one
+        analyzer.add(2011, exchange + 10, exchange + 1010 + 49_999);
+        // back to normal, then 50 milliseconds late
+        analyzer.add(2011, exchange + 20, exchange + 1020);
+        analyzer.add(2011, exchange + 30, exchange + 1030 + 50_000);
+        let stalls = analyzer.take_stalls();
+        assert_eq!(stalls.len(), 1);
+        assert_eq!(stalls[0].timestamp, exchange + 51_030);
+        assert_eq!(stalls[0].arrival_gap, 50_010);
+        assert_eq!(stalls[0].exchange_gap, 10);
+        assert_eq!(stalls[0].latency_jump, 50_000);
+    }
+
+    #[test]
+    fn small_buckets_draw_from_the_whole_day() {
+        // a full bucket of 10 from 10:00, one sample short of it of 20 from 10:01
+        let full = md::ExchangeTime::parse(md::testing::DATE, "10:00:00.000")
+            .unwrap()
+            .millis;
+        let short = full + 60_000;
+        let mut samples = vec![(full, 10); LatencyModel::MIN_BUCKET_SAMPLES];
+        samples.extend(vec![(short, 20); LatencyModel::MIN_BUCKET_SAMPLES - 1]);
+        let mut model = LatencyModel::empirical(&samples, 60_000, 7).unwrap();
+
+        assert!((0..1000).all(|_| model.sample(full + 30_000) == 10));
+        let drawn: Vec<i64> = (0..1000).map(|_| model.sample(short)).collect();
+        assert!(drawn.contains(&10));
+        assert!(drawn.contains(&20));
+        // a time without any samples too
+        assert!((0..1000).any(|_| model.sample(full - 60_000) == 10));
+    }
+
+    #[test]
+    fn retime_keeps_the_order_of_a_channel() {
+        // latencies far longer than the time between messages
+        let samples: Vec<(i64, i64)> = (0..1000).map(|idx| (0, idx * 1000)).collect();
+        let mut model = LatencyModel::empirical(&samples, 60_000, 7).unwrap();
+        let mut orders = Vec::new();
+        let mut trades = Vec::new();
+        for seq in 1..=200 {
+            let time = format!("10:00:00.{:03}", seq / 2);
+            if seq % 3 == 0 {
+                trades.push(trade(2290, seq, &time, 0, 100, seq - 1, 0));
+            } else {
+                orders.push(order(2290, seq, &time, Side::Bid, 51000, 100));
+            }
+        }
+        let orders: Vec<Arc<md::Order>> = orders.into_iter().map(Arc::new).collect();
+        let trades: Vec<Arc<md::Trade>> = trades.into_iter().map(Arc::new).collect();
+        let (orders, trades) = model.retime(&orders, &trades);
+
+        let mut events: Vec<(i64, i64)> = orders
+            .iter()
+            .map(|order| (order.clockAtArrival, order.ApplSeqNum))
+            .chain(
+                trades
+                    .iter()
+                    .map(|trade| (trade.clockAtArrival, trade.ApplSeqNum)),
+            )
+            .collect();
+        events.sort_unstable();
+        assert_eq!(events.len(), 200);
+        assert!(events.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(events.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        assert!(orders
+            .windows(2)
+            .all(|pair| pair[0].clockAtArrival <= pair[1].clockAtArrival));
+    }
+}
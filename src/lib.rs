@@ -0,0 +1,61 @@
+//! Rebuilds SZSE order books from order and trade csv files and produces
+//! snapshots in the mdLog format.
+//!
+//! Events are read with `md::read_csv`, replayed by a `SnapshotBuilder`
+//! (or a `ParallelSnapshotBuilder` on a thread pool) and snapshots are
+//! written back with `md::write_csv`. A `BookObserver` added to the
+//! builder sees every change of every book.
+
+// field names follow the columns of exchange csv files
+#![allow(non_snake_case)]
+#![warn(missing_docs)]
+#![allow(clippy::needless_return)]
+
+/// strategy backtests against the rebuilt books
+pub mod backtest;
+/// time, volume and trade-count bars from trades
+pub mod bars;
+/// logs a book when a condition is hit
+pub mod dump;
+/// per-snapshot book features such as spread and imbalance
+pub mod features;
+/// arrival latency per channel, bursts, stalls and latency models
+pub mod latency;
+/// fills and cancels of every order
+pub mod lifecycle;
+/// order, trade and snapshot records and their csv readers and writers
+pub mod md;
+/// callbacks on every change of a book
+pub mod observer;
+/// builds partitions of the instruments on a thread pool
+pub mod parallel;
+/// queue position of hypothetical orders
+pub mod queue_sim;
+/// previous close and price limits of an instrument
+pub mod reference;
+/// trading phases and snapshot schedules
+pub mod schedule;
+/// the order book and the builder that replays events into it
+pub mod snapshot_builder;
+/// cancel statistics and alerts for suspicious order flow
+pub mod surveillance;
+/// trades with the book around them
+pub mod tape;
+
+pub use backtest::{
+    Backtester, Context, ImpactPersistence, Strategy, StrategyFill, StrategyOrder,
+    StrategyOrderType, TwapStrategy,
+};
+pub use bars::{Bar, BarBuilder, BarKind};
+pub use features::{FeatureCalculator, Features};
+pub use latency::{Burst, LatencyAnalyzer, LatencyBucket, LatencyModel, LatencyThresholds, Stall};
+pub use lifecycle::{LifecycleTracker, OrderLifecycle, OrderState};
+pub use md::{Aggressor, ExchangeTime, ExtendedSnapshot, Filter, Order, Side, Snapshot, Trade};
+pub use observer::BookObserver;
+pub use parallel::{ParallelSnapshotBuilder, Partition};
+pub use queue_sim::{HypotheticalOrder, QueueSimulator, SimulatedOrder};
+pub use reference::ReferenceData;
+pub use schedule::Schedule;
+pub use snapshot_builder::{Book, ClockType, SnapshotBuilder};
+pub use surveillance::{Alert, AlertKind, CancelSummary, SurveillanceMonitor, Thresholds};
+pub use tape::{TapeEntry, TradeTape};
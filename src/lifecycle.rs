@@ -0,0 +1,291 @@
+use crate::md;
+use crate::observer::BookObserver;
+use crate::schedule;
+use crate::snapshot_builder::Book;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// where an order ended up by the end of the replay
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum OrderState {
+    /// resting without any execution
+    Open,
+    /// resting with part of it executed
+    PartiallyFilled,
+    /// executed in full
+    Filled,
+    /// cancelled, possibly after some executions,
+    /// or the part of a market order that could not be executed
+    Cancelled,
+}
+
+/// one execution of an order, price in book units
+#[derive(Debug, Copy, Clone)]
+pub struct Fill {
+    /// ApplSeqNum of the trade
+    pub seq: i64,
+    /// exchange time of the trade
+    pub exchange_time: md::ExchangeTime,
+    /// execution price
+    pub price: i64,
+    /// shares executed
+    pub quantity: i64,
+}
+
+/// everything that happened to one order, prices in book units
+#[derive(Debug, Clone)]
+pub struct OrderLifecycle {
+    /// SecurityID of the instrument
+    pub inst_id: i32,
+    /// ApplSeqNum of the order
+    pub seq: i64,
+    /// clock the order arrived at
+    pub clock_at_arrival: i64,
+    /// exchange time of the order
+    pub exchange_time: md::ExchangeTime,
+    /// side of the order
+    pub side: md::Side,
+    /// limit, market or best order
+    pub order_type: md::OrderType,
+    /// price of the order as given
+    pub price: i64,
+    /// quantity of the order
+    pub quantity: i64,
+    /// executions in the order they happened
+    pub fills: Vec<Fill>,
+    /// exchange time of the cancel, None if never cancelled
+    pub cancel_time: Option<md::ExchangeTime>,
+    /// quantity cancelled, 0 if never cancelled
+    pub cancelled_quantity: i64,
+    /// quantity resting ahead of it at its price when it joined the book,
+    /// None for orders that do not rest at a price
+    pub queue_ahead: Option<i64>,
+}
+
+impl OrderLifecycle {
+    /// sum of the fills
+    pub fn filled_quantity(&self) -> i64 {
+        return self.fills.iter().map(|fill| fill.quantity).sum();
+    }
+
+    /// where the order stands after the events seen so far
+    pub fn state(&self) -> OrderState {
+        if self.cancel_time.is_some() {
+            return OrderState::Cancelled;
+        }
+        let filled = self.filled_quantity();
+        if filled >= self.quantity {
+            return OrderState::Filled;
+        } else if self.order_type == md::OrderType::MarketOrder {
+            // what a market order does not take at once is dropped, like the book does
+            return OrderState::Cancelled;
+        } else if filled > 0 {
+            return OrderState::PartiallyFilled;
+        }
+        return OrderState::Open;
+    }
+
+    /// milliseconds of exchange time from submission to the first execution
+    pub fn time_to_first_fill(&self) -> Option<i64> {
+        return self
+            .fills
+            .first()
+            .map(|fill| fill.exchange_time.millis - self.exchange_time.millis);
+    }
+}
+
+/// builds the lifecycle of every order from the replay, usable as an observer
+pub struct LifecycleTracker {
+    // key: (SecurityID, ApplSeqNum)
+    orders_: BTreeMap<(i32, i64), OrderLifecycle>,
+    // queue ahead of the order being applied, taken before it joins the book
+    entry_queue_ahead: Option<i64>,
+}
+
+impl Default for LifecycleTracker {
+    fn default() -> Self {
+        LifecycleTracker::new()
+    }
+}
+
+impl LifecycleTracker {
+    /// tracks every order it sees as an observer
+    pub fn new() -> LifecycleTracker {
+        LifecycleTracker {
+            orders_: BTreeMap::new(),
+            entry_queue_ahead: None,
+        }
+    }
+
+    fn add_fill(&mut self, inst_id: i32, seq: i64, trade: &md::Trade) {
+        if let Some(lifecycle) = self.orders_.get_mut(&(inst_id, seq)) {
+            lifecycle.fills.push(Fill {
+                seq: trade.ApplSeqNum,
+                exchange_time: trade.exchange_time(),
+                price: trade.TradePrice,
+                quantity: trade.TradeQty,
+            });
+        }
+    }
+
+    /// orders by SecurityID and ApplSeqNum, orders we never saw are not included
+    pub fn finish(&mut self) -> Vec<OrderLifecycle> {
+        return std::mem::take(&mut self.orders_).into_values().collect();
+    }
+}
+
+impl BookObserver for LifecycleTracker {
+    fn on_before_order(&mut self, book: &Book, order: &md::Order) {
+        self.entry_queue_ahead = match order.OrderType {
+            md::OrderType::LimitOrder => {
+                // an order that crosses is first at its price if anything is left of it,
+                // nothing is matched in call auctions
+                let matching = schedule::is_matching(order.exchange_time().millis);
+                let crosses = match order.Side {
+                    md::Side::Bid => book.best_ask().is_some_and(|(ask, _)| ask <= order.Price),
+                    _ => book.best_bid().is_some_and(|(bid, _)| bid >= order.Price),
+                };
+                if matching && crosses {
+                    Some(0)
+                } else {
+                    Some(book.depth_at(order.Side, order.Price))
+                }
+            }
+            _ => None,
+        };
+    }
+
+    fn on_order_added(&mut self, book: &Book, order: &md::Order) {
+        self.orders_.insert(
+            (book.inst_id(), order.ApplSeqNum),
+            OrderLifecycle {
+                inst_id: book.inst_id(),
+                seq: order.ApplSeqNum,
+                clock_at_arrival: order.clockAtArrival,
+                exchange_time: order.exchange_time(),
+                side: order.Side,
+                order_type: order.OrderType,
+                price: order.Price,
+                quantity: order.OrderQty,
+                fills: Vec::new(),
+                cancel_time: None,
+                cancelled_quantity: 0,
+                queue_ahead: self.entry_queue_ahead.take(),
+            },
+        );
+    }
+
+    fn on_order_cancelled(&mut self, book: &Book, order: &md::Order, quantity: i64) {
+        if let Some(lifecycle) = self.orders_.get_mut(&(book.inst_id(), order.ApplSeqNum)) {
+            lifecycle.cancel_time = Some(book.exchange_time);
+            lifecycle.cancelled_quantity += quantity;
+        }
+    }
+
+    fn on_order_executed(&mut self, book: &Book, trade: &md::Trade) {
+        self.add_fill(book.inst_id(), trade.BidApplSeqNum, trade);
+        self.add_fill(book.inst_id(), trade.OfferApplSeqNum, trade);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md::testing::{order, trade};
+    use crate::md::Side;
+    use crate::snapshot_builder::{ClockType, SnapshotBuilder};
+    use std::sync::{Arc, Mutex};
+
+    // crosses in the opening call auction and in continuous trading, then a
+    // market buy larger than what is left
+    fn lifecycles() -> BTreeMap<i64, OrderLifecycle> {
+        let mut market = order(2290, 9, "10:00:02.000", Side::Bid, 0, 100);
+        market.OrderType = md::OrderType::MarketOrder;
+        let orders = vec![
+            order(2290, 1, "09:20:00.000", Side::Bid, 51000, 100),
+            order(2290, 2, "09:20:01.000", Side::Ask, 50900, 100),
+            order(2290, 3, "09:21:00.000", Side::Bid, 51000, 200),
+            order(2290, 5, "10:00:00.000", Side::Bid, 51000, 50),
+            order(2290, 6, "10:00:01.000", Side::Ask, 51000, 300),
+            market,
+        ];
+        let trades = vec![
+            trade(2290, 4, "09:25:00.000", 51000, 100, 1, 2),
+            trade(2290, 7, "10:00:01.000", 51000, 200, 3, 6),
+            trade(2290, 8, "10:00:01.000", 51000, 50, 5, 6),
+            trade(2290, 10, "10:00:02.000", 51000, 50, 9, 6),
+        ];
+        let orders = orders.into_iter().map(Arc::new).collect();
+        let trades = trades.into_iter().map(Arc::new).collect();
+        let mut builder = SnapshotBuilder::new(orders, trades);
+        builder.set_clock_type(ClockType::Exchange);
+        let tracker = Arc::new(Mutex::new(LifecycleTracker::new()));
+        builder.add_observer(Box::new(tracker.clone()));
+        builder.process_until(i64::MAX);
+        let lifecycles = tracker.lock().unwrap().finish();
+        return lifecycles
+            .into_iter()
+            .map(|lifecycle| (lifecycle.seq, lifecycle))
+            .collect();
+    }
+
+    #[test]
+    fn queue_ahead_at_entry() {
+        let lifecycles = lifecycles();
+        let queue_ahead = |seq| lifecycles[&seq].queue_ahead;
+        assert_eq!(queue_ahead(1), Some(0));
+        // crossing in the call auction still queues behind the bid
+        assert_eq!(queue_ahead(3), Some(100));
+        assert_eq!(queue_ahead(5), Some(200));
+        // first at its price once it takes what it crosses
+        assert_eq!(queue_ahead(6), Some(0));
+        assert_eq!(queue_ahead(9), None);
+    }
+
+    #[test]
+    fn states_and_first_fills() {
+        let lifecycles = lifecycles();
+        let state = |seq| lifecycles[&seq].state();
+        assert_eq!(state(1), OrderState::Filled);
+        assert_eq!(state(3), OrderState::Filled);
+        assert_eq!(state(6), OrderState::Filled);
+        // what the market order could not take is dropped
+        assert_eq!(state(9), OrderState::Cancelled);
+        assert!(lifecycles[&9].cancel_time.is_none());
+        assert_eq!(lifecycles[&9].filled_quantity(), 50);
+
+        let first_fill = |seq| lifecycles[&seq].time_to_first_fill();
+        // from 09:21:00 to the first execution at 10:00:01
+        assert_eq!(first_fill(3), Some((39 * 60 + 1) * 1000));
+        assert_eq!(first_fill(5), Some(1000));
+        assert_eq!(first_fill(6), Some(0));
+        assert_eq!(lifecycles[&3].fills.len(), 1);
+        assert_eq!(lifecycles[&6].fills.len(), 3);
+    }
+
+    #[test]
+    fn cancels_end_the_lifecycle() {
+        let orders = vec![
+            order(2290, 1, "10:00:00.000", Side::Ask, 51000, 300),
+            order(2290, 2, "10:00:01.000", Side::Bid, 51000, 100),
+            order(2290, 4, "10:00:01.500", Side::Ask, 51100, 100),
+        ];
+        let trades = vec![
+            trade(2290, 3, "10:00:01.000", 51000, 100, 2, 1),
+            trade(2290, 5, "10:00:03.000", 0, 200, 0, 1),
+        ];
+        let orders = orders.into_iter().map(Arc::new).collect();
+        let trades = trades.into_iter().map(Arc::new).collect();
+        let mut builder = SnapshotBuilder::new(orders, trades);
+        let tracker = Arc::new(Mutex::new(LifecycleTracker::new()));
+        builder.add_observer(Box::new(tracker.clone()));
+        builder.process_until(i64::MAX);
+        let lifecycles = tracker.lock().unwrap().finish();
+
+        assert_eq!(lifecycles[0].state(), OrderState::Cancelled);
+        assert_eq!(lifecycles[0].cancelled_quantity, 200);
+        assert_eq!(lifecycles[0].filled_quantity(), 100);
+        assert_eq!(lifecycles[2].state(), OrderState::Open);
+        assert_eq!(lifecycles[2].time_to_first_fill(), None);
+    }
+}
@@ -1,115 +1,452 @@
-mod md;
-mod snapshot_builder;
+#![allow(clippy::needless_return)]
+
+mod commands;
+mod config;
+
 use std::env;
+use std::process;
+
+// orders, trades and which of them to keep, shared by every subcommand
+fn input_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+    vec![
+        clap::Arg::with_name("config")
+            .long("config")
+            .help("toml run config, flags given here take precedence over it")
+            .takes_value(true),
+        clap::Arg::with_name("order")
+            .short("o")
+            .long("order")
+            .help("csv file of orders")
+            .takes_value(true),
+        clap::Arg::with_name("trade")
+            .short("t")
+            .long("trade")
+            .help("csv file of trades")
+            .takes_value(true),
+        clap::Arg::with_name("symbols")
+            .long("symbols")
+            .help("only these SecurityIDs, e.g. 2290,2385")
+            .takes_value(true)
+            .use_delimiter(true),
+        clap::Arg::with_name("prefix")
+            .long("prefix")
+            .help("only SecurityIDs whose 6 digit code starts with this, e.g. 300")
+            .takes_value(true),
+        clap::Arg::with_name("start")
+            .long("start")
+            .help("skip messages arriving before this time of day, e.g. 09:30:00")
+            .takes_value(true),
+        clap::Arg::with_name("end")
+            .long("end")
+            .help("skip messages arriving at or after this time of day, e.g. 10:00:00")
+            .takes_value(true),
+        clap::Arg::with_name("dedupe")
+            .long("dedupe")
+            .help("keep or skip orders and trades flagged __isRepeated")
+            .takes_value(true)
+            .possible_values(&["keep", "skip-repeated"]),
+    ]
+}
+
+fn output_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+    vec![
+        clap::Arg::with_name("output")
+            .long("output")
+            .help("write rows to this file instead of stdout, the effective config goes next to it")
+            .takes_value(true),
+        clap::Arg::with_name("format")
+            .long("format")
+            .help("csv with a header, or one json object per line")
+            .takes_value(true)
+            .possible_values(&["csv", "json"]),
+    ]
+}
+
+// how books start, for subcommands that rebuild them
+fn book_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+    vec![
+        clap::Arg::with_name("init")
+            .long("init")
+            .help("mdLog file to start books from, the last snapshot of each instrument is used")
+            .takes_value(true),
+        clap::Arg::with_name("warm-start")
+            .long("warm-start")
+            .help(
+                "mdLog file to seed books of instruments whose flow starts late \
+                 and reconcile them until they are exact, on the exchange clock",
+            )
+            .takes_value(true),
+        clap::Arg::with_name("clock")
+            .long("clock")
+            .help("clock that orders events, exchange by default for snapshots at exchange times and warm starts")
+            .takes_value(true)
+            .possible_values(&["arrival", "exchange"]),
+    ]
+}
+
+// when to take snapshots, every 3 seconds like the exchange if none is given
+fn schedule_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+    vec![
+        clap::Arg::with_name("at")
+            .long("at")
+            .help("exchange time of snapshot, e.g. 10:00:00.000")
+            .takes_value(true)
+            .multiple(true),
+        clap::Arg::with_name("every")
+            .long("every")
+            .help("snapshot every N milliseconds across the session")
+            .takes_value(true)
+            .conflicts_with_all(&["at", "exchange-cadence", "mdlog", "every-event"]),
+        clap::Arg::with_name("exchange-cadence")
+            .long("exchange-cadence")
+            .help("snapshot every 3 seconds like the exchange")
+            .conflicts_with_all(&["at", "mdlog", "every-event"]),
+        clap::Arg::with_name("mdlog")
+            .long("mdlog")
+            .help("snapshot at every exchange snapshot in this mdLog csv")
+            .takes_value(true)
+            .conflicts_with_all(&["at", "every-event"]),
+        clap::Arg::with_name("every-event")
+            .long("every-event")
+            .help("snapshot after every order and trade")
+            .conflicts_with("at"),
+    ]
+}
+
+fn extended_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name("extended")
+        .long("extended")
+        .help("add high, low, vwap, previous close, total and average price of each side")
+}
 
 fn main() {
-    let matches = clap::App::new(env::args().next().unwrap())
+    let build = clap::SubCommand::with_name("build")
+        .about("rebuild books and write snapshots on a schedule")
+        .args(&input_args())
+        .args(&output_args())
+        .args(&book_args())
+        .args(&schedule_args())
+        .arg(
+            clap::Arg::with_name("dump-when")
+                .long("dump-when")
+                .help("log the book when [inst:]num_trades=N, time=HH:MM:SS.mmm or seq=N")
+                .takes_value(true)
+                .multiple(true),
+        )
         .arg(
-            clap::Arg::with_name("order")
-                .short("o")
-                .long("order")
-                .help("csv file of orders")
+            clap::Arg::with_name("threads")
+                .long("threads")
+                .help("rebuild books on this many worker threads")
+                .takes_value(true)
+                .conflicts_with("dump-when"),
+        )
+        .arg(
+            clap::Arg::with_name("partition")
+                .long("partition")
+                .help("how to split work between threads")
+                .takes_value(true)
+                .possible_values(&["instrument", "channel"])
+                .requires("threads"),
+        )
+        .arg(
+            clap::Arg::with_name("save-checkpoint")
+                .long("save-checkpoint")
+                .help("replay until --checkpoint-at, save the builder state to this file and exit")
+                .takes_value(true)
+                .requires("checkpoint-at")
+                .conflicts_with("threads"),
+        )
+        .arg(
+            clap::Arg::with_name("checkpoint-at")
+                .long("checkpoint-at")
+                .help("exchange time of day of the checkpoint, e.g. 13:00:00")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("restore")
+                .long("restore")
+                .help("resume from a checkpoint saved with the same order and trade files")
+                .takes_value(true)
+                .conflicts_with_all(&["threads", "init"]),
+        )
+        .arg(extended_arg().conflicts_with("threads"));
+
+    let validate = clap::SubCommand::with_name("validate")
+        .about("compare rebuilt books with exchange snapshots, one summary row per instrument")
+        .args(&input_args())
+        .args(&output_args())
+        .args(&book_args())
+        .arg(
+            clap::Arg::with_name("mdlog")
+                .long("mdlog")
+                .help("mdLog csv with the exchange snapshots to compare with")
+                .takes_value(true),
+        );
+
+    let replay = clap::SubCommand::with_name("replay")
+        .about("write every book event, one row per event")
+        .args(&input_args())
+        .args(&output_args())
+        .args(&book_args());
+
+    let stats = clap::SubCommand::with_name("stats")
+        .about("summary of the order and trade files, one row per instrument")
+        .args(&input_args())
+        .args(&output_args());
+
+    let book = clap::SubCommand::with_name("book")
+        .about("print the book of one instrument as of an exchange time")
+        .args(&input_args())
+        .args(&output_args())
+        .args(&book_args())
+        .arg(
+            clap::Arg::with_name("symbol")
+                .long("symbol")
+                .help("SecurityID of the book")
                 .required(true)
                 .takes_value(true),
         )
         .arg(
-            clap::Arg::with_name("trade")
-                .short("t")
-                .long("trade")
-                .help("csv file of trades")
+            clap::Arg::with_name("at")
+                .long("at")
+                .help("exchange time, e.g. 10:00:00.000")
                 .required(true)
                 .takes_value(true),
         )
-        .get_matches();
-    let orders = md::read_csv::<md::Order>(matches.value_of("order").unwrap()).unwrap();
-    let trades = md::read_csv::<md::Trade>(matches.value_of("trade").unwrap()).unwrap();
-
-    let mut builder = snapshot_builder::SnapshotBuilder::new(orders, trades);
-
-    let snapshot_2290 = md::Snapshot {
-        ms: "09:25:45.090771",
-        clock: 1587605145091829,
-        threadId: 23994,
-        clockAtArrival: 1587605145091648,
-        sequenceNo: 1543447,
-        source: 24,
-        StockID: 2290,
-        exchange: "SZ",
-        time: "09:25:00.000",
-        cum_volume: 0,
-        cum_amount: 0.0,
-        close: 0.0,
-        __origTickSeq: 0,
-        bid1p: 5.12,
-        bid2p: 5.11,
-        bid3p: 5.10,
-        bid4p: 5.08,
-        bid5p: 5.07,
-        bid1q: 3000,
-        bid2q: 1500,
-        bid3q: 9800,
-        bid4q: 15800,
-        bid5q: 1000,
-        ask1p: 5.22,
-        ask2p: 5.23,
-        ask3p: 5.30,
-        ask4p: 5.35,
-        ask5p: 5.38,
-        ask1q: 1000,
-        ask2q: 600,
-        ask3q: 1001,
-        ask4q: 1000,
-        ask5q: 1200,
-        openPrice: 0.0,
-        numTrades: 0,
-    };
+        .arg(
+            clap::Arg::with_name("depth")
+                .long("depth")
+                .help("number of levels on each side, 10 by default")
+                .takes_value(true),
+        );
 
-    let snapshot_2385 = md::Snapshot {
-        ms: "09:25:45.124771",
-        clock: 1587605145125220,
-        threadId: 23994,
-        clockAtArrival: 1587605145124998,
-        sequenceNo: 1548408,
-        source: 24,
-        StockID: 2385,
-        exchange: "SZ",
-        time: "09:25:00.000",
-        cum_volume: 11641250,
-        cum_amount: 111756000.0,
-        close: 9.60,
-        __origTickSeq: 0,
-        bid1p: 9.60,
-        bid2p: 9.59,
-        bid3p: 9.58,
-        bid4p: 9.57,
-        bid5p: 9.56,
-        bid1q: 552050,
-        bid2q: 15500,
-        bid3q: 96900,
-        bid4q: 1700,
-        bid5q: 8900,
-        ask1p: 9.61,
-        ask2p: 9.62,
-        ask3p: 9.63,
-        ask4p: 9.64,
-        ask5p: 9.65,
-        ask1q: 30300,
-        ask2q: 110700,
-        ask3q: 30733,
-        ask4q: 41700,
-        ask5q: 285300,
-        openPrice: 9.6,
-        numTrades: 3493,
-    };
+    let features = clap::SubCommand::with_name("features")
+        .about(
+            "order flow imbalance, microprice, spread and depth features on the snapshot schedule",
+        )
+        .args(&input_args())
+        .args(&output_args())
+        .args(&book_args())
+        .args(&schedule_args());
+
+    let lifecycle = clap::SubCommand::with_name("lifecycle")
+        .about("fills, cancel and final state of every order, one row per order")
+        .args(&input_args())
+        .args(&output_args())
+        .args(&book_args());
 
-    // builder.init(&vec![snapshot_2290, snapshot_2385]);
+    let latency = clap::SubCommand::with_name("latency")
+        .about(
+            "feed latency from TransactTime to clockAtArrival per channel and time of day, \
+             buckets, bursts and stalls are set in the [latency] section of --config",
+        )
+        .args(&input_args())
+        .args(&output_args())
+        .arg(
+            clap::Arg::with_name("bursts")
+                .long("bursts")
+                .help("also write messages arriving back to back on a channel here")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("stalls")
+                .long("stalls")
+                .help("also write gaps of a channel the exchange did not have here")
+                .takes_value(true),
+        );
 
-    // 1587605145124998: 09:25:00
-    // 1587605445122501: 09:30:00
-    // 1587605991164248: 09:39:06
-    let timestamps = vec![1587605991164248];
-    let snapshots = builder.build_snapshot(&timestamps);
-    for snapshot in snapshots {
-        println!("{:?}", snapshot);
+    let surveil = clap::SubCommand::with_name("surveil")
+        .about(
+            "alerts on large cancels near the touch and layering, one row per alert, \
+             thresholds are read from the [surveillance] section of --config",
+        )
+        .args(&input_args())
+        .args(&output_args())
+        .args(&book_args())
+        .arg(
+            clap::Arg::with_name("summary")
+                .long("summary")
+                .help("also write order-to-trade ratios and cancel latencies per instrument here")
+                .takes_value(true),
+        );
+
+    let simulate = clap::SubCommand::with_name("simulate")
+        .about("how hypothetical limit orders would have queued and filled, one row per order")
+        .args(&input_args())
+        .args(&output_args())
+        .args(&book_args())
+        .arg(
+            clap::Arg::with_name("hypothetical")
+                .long("hypothetical")
+                .help(
+                    "inst,side,price,quantity,time[,cancel time], \
+                     e.g. 2290,bid,5.12,1000,09:35:00,10:00:00",
+                )
+                .required(true)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        );
+
+    let backtest = clap::SubCommand::with_name("backtest")
+        .about(
+            "execute a TWAP order against the rebuilt books with latency and market impact, \
+             one row per fill",
+        )
+        .args(&input_args())
+        .args(&output_args())
+        .args(&book_args())
+        .args(&schedule_args())
+        .arg(
+            clap::Arg::with_name("twap")
+                .long("twap")
+                .help(
+                    "inst,side,quantity,start,end,slices, \
+                     e.g. 2290,buy,10000,09:35:00,10:35:00,12",
+                )
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("style")
+                .long("style")
+                .help("take the opposite touch, or join the touch of our side")
+                .takes_value(true)
+                .possible_values(&["aggressive", "passive"])
+                .default_value("aggressive"),
+        )
+        .arg(
+            clap::Arg::with_name("latency")
+                .long("latency")
+                .help(
+                    "microseconds from a decision to its arrival at the book, \
+                     or measured to draw them from the feed latency of the input",
+                )
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            clap::Arg::with_name("feed-latency")
+                .long("feed-latency")
+                .help(
+                    "replay events at their exchange time plus a latency drawn from \
+                     the measured ones instead of their arrival clocks",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("seed")
+                .long("seed")
+                .help("seed of the latency draws")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            clap::Arg::with_name("impact")
+                .long("impact")
+                .help(
+                    "how long taken liquidity stays gone: transient, permanent, \
+                     or decay=N for a half-life of N milliseconds",
+                )
+                .takes_value(true)
+                .default_value("transient"),
+        );
+
+    let tape = clap::SubCommand::with_name("tape")
+        .about("every execution with its aggressor and the book it found, one row per trade")
+        .args(&input_args())
+        .args(&output_args())
+        .args(&book_args());
+
+    let bars = clap::SubCommand::with_name("bars")
+        .about("aggregate executions into OHLCV bars, one row per bar")
+        .args(&input_args())
+        .args(&output_args())
+        .args(&book_args())
+        .arg(
+            clap::Arg::with_name("bar")
+                .long("bar")
+                .help("time=1m, time=5s, volume=100000, dollar=1000000 (yuan) or tick=100")
+                .required(true)
+                .takes_value(true),
+        );
+
+    let batch = clap::SubCommand::with_name("batch")
+        .about(
+            "build every day under a directory of YYYYMMDD/{order,trade,snapshot}.csv[.gz], \
+             carrying the previous close over to the next day's price limits",
+        )
+        .args(&input_args())
+        .args(&schedule_args())
+        .arg(
+            clap::Arg::with_name("data-dir")
+                .long("data-dir")
+                .help("directory holding one directory per trading day")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("output-dir")
+                .long("output-dir")
+                .help("where per day snapshots, the index and the effective config go")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("format")
+                .long("format")
+                .help("csv with a header, or one json object per line")
+                .takes_value(true)
+                .possible_values(&["csv", "json"]),
+        )
+        .arg(
+            clap::Arg::with_name("clock")
+                .long("clock")
+                .help("clock that orders events")
+                .takes_value(true)
+                .possible_values(&["arrival", "exchange"]),
+        )
+        .arg(extended_arg());
+
+    let matches = clap::App::new(env::args().next().unwrap())
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(build)
+        .subcommand(validate)
+        .subcommand(replay)
+        .subcommand(stats)
+        .subcommand(book)
+        .subcommand(features)
+        .subcommand(tape)
+        .subcommand(lifecycle)
+        .subcommand(surveil)
+        .subcommand(latency)
+        .subcommand(simulate)
+        .subcommand(backtest)
+        .subcommand(bars)
+        .subcommand(batch)
+        .after_help(
+            "Logging is controlled by RUST_LOG, e.g. RUST_LOG=book::level=debug,book::cross=debug \
+             for level and cross events, append '/^inst=2385 ' to only keep one instrument.",
+        )
+        .get_matches();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let result = match matches.subcommand() {
+        ("build", Some(matches)) => commands::build(matches),
+        ("validate", Some(matches)) => commands::validate(matches),
+        ("replay", Some(matches)) => commands::replay(matches),
+        ("stats", Some(matches)) => commands::stats(matches),
+        ("book", Some(matches)) => commands::book(matches),
+        ("features", Some(matches)) => commands::features(matches),
+        ("tape", Some(matches)) => commands::tape(matches),
+        ("lifecycle", Some(matches)) => commands::lifecycle(matches),
+        ("surveil", Some(matches)) => commands::surveil(matches),
+        ("latency", Some(matches)) => commands::latency(matches),
+        ("simulate", Some(matches)) => commands::simulate(matches),
+        ("backtest", Some(matches)) => commands::backtest(matches),
+        ("bars", Some(matches)) => commands::bars(matches),
+        ("batch", Some(matches)) => commands::batch(matches),
+        _ => unreachable!(),
+    };
+    if let Err(err) = result {
+        log::error!("{}", err);
+        process::exit(1);
     }
 }
@@ -1,14 +1,26 @@
+use crate::schedule;
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::error::Error;
-use std::rc::Rc;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
 
-#[derive(Debug, Copy, Clone)]
+/// side of an order, Unknown for anything else in the csv
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum Side {
+    /// buy order
     Bid,
+    /// sell order
     Ask,
+    /// anything else
     Unknown,
 }
 
 impl Side {
+    /// "1" is Bid and "2" Ask
     pub fn from_string(s: &str) -> Side {
         match s {
             "1" => Side::Bid,
@@ -16,6 +28,7 @@ impl Side {
             _ => Side::Unknown,
         }
     }
+    /// Bid for Ask and the other way around
     pub fn opposite(&self) -> Side {
         match self {
             Side::Bid => Side::Ask,
@@ -25,14 +38,21 @@ impl Side {
     }
 }
 
+/// type of an order
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum OrderType {
+    /// executes at any price, what is left is cancelled
     MarketOrder,
+    /// rests at its price
     LimitOrder,
+    /// a limit order at the best price of its own side
     BestOrder,
+    /// anything else
     Unknown,
 }
 
 impl OrderType {
+    /// "1" is MarketOrder, "2" LimitOrder and "U" BestOrder
     pub fn from_string(s: &str) -> OrderType {
         match s {
             "1" => OrderType::MarketOrder,
@@ -43,29 +63,227 @@ impl OrderType {
     }
 }
 
+/// SZSE TransactTime is YYYYMMDDHHMMSSmmm in Beijing time,
+/// we keep the date and the milliseconds since midnight
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct ExchangeTime {
+    /// trading day as YYYYMMDD
+    pub date: i32,
+    /// milliseconds since midnight
+    pub millis: i64,
+}
+
+impl ExchangeTime {
+    // exchange local time is UTC+8
+    const UTC_OFFSET_MICROS: i64 = 8 * 3600 * 1000000;
+    const MICROS_PER_DAY: i64 = 24 * 3600 * 1000000;
+
+    /// from a TransactTime, YYYYMMDDHHMMSSmmm
+    pub fn from_transact_time(t: i64) -> ExchangeTime {
+        let date = (t / 1000000000) as i32;
+        let hhmmssmmm = t % 1000000000;
+        let hours = hhmmssmmm / 10000000;
+        let minutes = hhmmssmmm / 100000 % 100;
+        let seconds = hhmmssmmm / 1000 % 100;
+        let millis = hhmmssmmm % 1000;
+        ExchangeTime {
+            date,
+            millis: ((hours * 60 + minutes) * 60 + seconds) * 1000 + millis,
+        }
+    }
+
+    /// parse "HH:MM", "HH:MM:SS" or "HH:MM:SS.mmm" on the given date
+    pub fn parse(date: i32, s: &str) -> Result<ExchangeTime, Box<dyn Error>> {
+        let (hms, frac) = match s.find('.') {
+            Some(pos) => (&s[..pos], &s[pos + 1..]),
+            None => (s, "0"),
+        };
+        let parts: Vec<&str> = hms.split(':').collect();
+        if parts.len() < 2 || parts.len() > 3 {
+            return Err(format!("invalid exchange time '{}'", s).into());
+        }
+        let hours = parts[0].parse::<i64>()?;
+        let minutes = parts[1].parse::<i64>()?;
+        let seconds = match parts.get(2) {
+            Some(seconds) => seconds.parse::<i64>()?,
+            None => 0,
+        };
+        // "5" means 500ms, "123456" means 123ms
+        let mut millis = 0;
+        for (i, c) in frac.chars().take(3).enumerate() {
+            let digit = c
+                .to_digit(10)
+                .ok_or(format!("invalid exchange time '{}'", s))?;
+            millis += digit as i64 * 10i64.pow(2 - i as u32);
+        }
+        return Ok(ExchangeTime {
+            date,
+            millis: ((hours * 60 + minutes) * 60 + seconds) * 1000 + millis,
+        });
+    }
+
+    /// microseconds since unix epoch, the same unit as clockAtArrival
+    pub fn to_clock(self) -> i64 {
+        let days = days_from_civil(self.date / 10000, self.date / 100 % 100, self.date % 100);
+        return days * ExchangeTime::MICROS_PER_DAY + self.millis * 1000
+            - ExchangeTime::UTC_OFFSET_MICROS;
+    }
+
+    /// the exchange time of a clock, the reverse of to_clock
+    pub fn from_clock(clock: i64) -> ExchangeTime {
+        let local = clock + ExchangeTime::UTC_OFFSET_MICROS;
+        let (y, m, d) = civil_from_days(local.div_euclid(ExchangeTime::MICROS_PER_DAY));
+        ExchangeTime {
+            date: y * 10000 + m * 100 + d,
+            millis: local.rem_euclid(ExchangeTime::MICROS_PER_DAY) / 1000,
+        }
+    }
+}
+
+/// time of day of a clock in exchange local time, e.g. "09:25:45.124998"
+pub fn clock_to_string(clock: i64) -> String {
+    let micros = (clock + ExchangeTime::UTC_OFFSET_MICROS).rem_euclid(ExchangeTime::MICROS_PER_DAY);
+    return format!(
+        "{:02}:{:02}:{:02}.{:06}",
+        micros / 3600000000,
+        micros / 60000000 % 60,
+        micros / 1000000 % 60,
+        micros % 1000000
+    );
+}
+
+impl fmt::Display for ExchangeTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}.{:03}",
+            self.millis / 3600000,
+            self.millis / 60000 % 60,
+            self.millis / 1000 % 60,
+            self.millis % 1000
+        )
+    }
+}
+
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(y: i32, m: i32, d: i32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y } as i64;
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (m as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    return era * 146097 + doe - 719468;
+}
+
+fn civil_from_days(days: i64) -> (i32, i32, i32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = if m <= 2 {
+        yoe + era * 400 + 1
+    } else {
+        yoe + era * 400
+    };
+    return (y as i32, m as i32, d as i32);
+}
+
+/// a row of an exchange csv file
 pub trait Convertable {
+    /// parses the row, panics on a malformed field
     fn from_string_record(sr: &csv::StringRecord) -> Self;
+    /// SecurityID and clockAtArrival, read before parsing the whole row
+    fn security_and_clock(sr: &csv::StringRecord) -> (i32, i64);
+    /// __isRepeated, set when the row was already sent by another source
+    fn is_repeated(_sr: &csv::StringRecord) -> bool {
+        false
+    }
+}
+
+/// which rows to keep while reading
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// SecurityIDs to keep, empty for all
+    pub securities: HashSet<i32>,
+    /// prefix of the 6 digit code, e.g. "300" for ChiNext
+    pub prefix: Option<String>,
+    /// time of day of clockAtArrival in milliseconds, [start, end)
+    /// note that the book misses everything before start unless it is initialized
+    pub start_millis: Option<i64>,
+    /// exclusive end of that time of day
+    pub end_millis: Option<i64>,
+    /// drop orders and trades flagged __isRepeated
+    pub skip_repeated: bool,
+}
+
+impl Filter {
+    /// whether a row of the instrument arriving at clock is kept
+    pub fn accept(&self, security_id: i32, clock: i64) -> bool {
+        if !self.securities.is_empty() && !self.securities.contains(&security_id) {
+            return false;
+        }
+        if let Some(prefix) = &self.prefix {
+            if !format!("{:06}", security_id).starts_with(prefix.as_str()) {
+                return false;
+            }
+        }
+        if self.start_millis.is_some() || self.end_millis.is_some() {
+            let millis = ExchangeTime::from_clock(clock).millis;
+            if self.start_millis.is_some_and(|start| millis < start) {
+                return false;
+            }
+            if self.end_millis.is_some_and(|end| millis >= end) {
+                return false;
+            }
+        }
+        return true;
+    }
 }
 
+/// a row of an SZSE order csv file, prices are scaled by Book::PRICE_DIVISOR
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Order {
+    /// microseconds since unix epoch the row arrived at
     pub clockAtArrival: i64,
     sequenceNo: i64,
     exchId: i8,
     securityType: i8,
     __isRepeated: i8,
-    TransactTime: i64,
-    ChannelNo: i32,
+    /// exchange time as YYYYMMDDHHMMSSmmm
+    pub TransactTime: i64,
+    /// channel the row was sent on
+    pub ChannelNo: i32,
+    /// sequence number within the channel
     pub ApplSeqNum: i64,
+    /// instrument code
     pub SecurityID: i32,
     secid: i32,
     mdSource: i8,
+    /// side of the order
     pub Side: Side,
+    /// type of the order
     pub OrderType: OrderType,
     __origTickSeq: i8,
+    /// price of the order
     pub Price: i64,
+    /// quantity of the order
     pub OrderQty: i64,
 }
 
+impl Order {
+    /// TransactTime as an ExchangeTime
+    pub fn exchange_time(&self) -> ExchangeTime {
+        ExchangeTime::from_transact_time(self.TransactTime)
+    }
+}
+
 impl Convertable for Order {
     fn from_string_record(row: &csv::StringRecord) -> Order {
         Order {
@@ -87,15 +305,32 @@ impl Convertable for Order {
             OrderQty: row[15].parse::<i64>().unwrap(),
         }
     }
+
+    fn security_and_clock(row: &csv::StringRecord) -> (i32, i64) {
+        (
+            row[8].parse::<i32>().unwrap(),
+            row[0].parse::<i64>().unwrap(),
+        )
+    }
+
+    fn is_repeated(row: &csv::StringRecord) -> bool {
+        &row[4] != "0"
+    }
 }
 
+/// kind of a trade row
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum ExecuteType {
+    /// an order was cancelled
     Cancelled,
+    /// two orders were executed against each other
     Traded,
+    /// anything else
     Unknown,
 }
 
 impl ExecuteType {
+    /// "4" is Cancelled and "F" Traded
     pub fn from_string(s: &str) -> ExecuteType {
         match s {
             "4" => ExecuteType::Cancelled,
@@ -105,31 +340,124 @@ impl ExecuteType {
     }
 }
 
+/// who initiated an execution
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Aggressor {
+    /// the buy order crossed the spread
+    Buy,
+    /// the sell order crossed the spread
+    Sell,
+    /// matched in a call auction, neither side crossed the spread
+    Auction,
+    /// cancels, or executions without both orders
+    Unknown,
+}
+
+impl Aggressor {
+    /// the later of the two orders crossed the spread, SZSE ApplSeqNums
+    /// increase within a channel so the larger one arrived later
+    pub fn classify(
+        exec_type: ExecuteType,
+        time: ExchangeTime,
+        bid_seq: i64,
+        offer_seq: i64,
+    ) -> Aggressor {
+        match exec_type {
+            ExecuteType::Traded => {}
+            _ => return Aggressor::Unknown,
+        }
+        if schedule::is_call_auction(time.millis) {
+            return Aggressor::Auction;
+        }
+        if bid_seq == 0 || offer_seq == 0 {
+            return Aggressor::Unknown;
+        }
+        return if bid_seq > offer_seq {
+            Aggressor::Buy
+        } else {
+            Aggressor::Sell
+        };
+    }
+
+    /// 'B' or 'S' like TradeBSFlag, 'N' otherwise
+    pub fn to_flag(self) -> char {
+        match self {
+            Aggressor::Buy => 'B',
+            Aggressor::Sell => 'S',
+            Aggressor::Auction | Aggressor::Unknown => 'N',
+        }
+    }
+
+    /// the side of the book the aggressor trades from, Unknown without one
+    pub fn side(self) -> Side {
+        match self {
+            Aggressor::Buy => Side::Bid,
+            Aggressor::Sell => Side::Ask,
+            Aggressor::Auction | Aggressor::Unknown => Side::Unknown,
+        }
+    }
+}
+
+/// a row of an SZSE trade csv file, either an execution or a cancel
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Trade {
+    /// microseconds since unix epoch the row arrived at
     pub clockAtArrival: i64,
     sequenceNo: i64,
     exchId: i8,
     securityType: i8,
     __isRepeated: i8,
-    TransactTime: i64,
-    ChannelNo: i32,
-    ApplSeqNum: i64,
+    /// exchange time as YYYYMMDDHHMMSSmmm
+    pub TransactTime: i64,
+    /// channel the row was sent on
+    pub ChannelNo: i32,
+    /// sequence number within the channel
+    pub ApplSeqNum: i64,
+    /// instrument code
     pub SecurityID: i32,
     secid: i32,
     mdSource: i8,
+    /// execution or cancel
     pub ExecType: ExecuteType,
+    // the csv has no usable flag, it is derived from the ApplSeqNums, see Aggressor
     TradeBSFlag: char,
     __origTickSeq: i8,
+    /// execution price, 0 for a cancel
     pub TradePrice: i64,
+    /// quantity executed or cancelled
     pub TradeQty: i64,
     TradeMoney: i64,
+    /// ApplSeqNum of the buy order, 0 for a cancel of a sell order
     pub BidApplSeqNum: i64,
+    /// ApplSeqNum of the sell order, 0 for a cancel of a buy order
     pub OfferApplSeqNum: i64,
 }
 
+impl Trade {
+    /// TransactTime as an ExchangeTime
+    pub fn exchange_time(&self) -> ExchangeTime {
+        ExchangeTime::from_transact_time(self.TransactTime)
+    }
+
+    /// who initiated the execution, see Aggressor::classify
+    pub fn aggressor(&self) -> Aggressor {
+        return Aggressor::classify(
+            self.ExecType,
+            self.exchange_time(),
+            self.BidApplSeqNum,
+            self.OfferApplSeqNum,
+        );
+    }
+
+    /// 'B' or 'S' for the aggressor of an execution in continuous trading, 'N' otherwise
+    pub fn bs_flag(&self) -> char {
+        self.TradeBSFlag
+    }
+}
+
 impl Convertable for Trade {
     fn from_string_record(row: &csv::StringRecord) -> Trade {
-        Trade {
+        let mut trade = Trade {
             clockAtArrival: row[0].parse::<i64>().unwrap(),
             sequenceNo: row[1].parse::<i64>().unwrap(),
             exchId: row[2].parse::<i8>().unwrap(),
@@ -149,58 +477,427 @@ impl Convertable for Trade {
             TradeMoney: row[16].parse::<i64>().unwrap(),
             BidApplSeqNum: row[17].parse::<i64>().unwrap(),
             OfferApplSeqNum: row[18].parse::<i64>().unwrap(),
-        }
+        };
+        trade.TradeBSFlag = trade.aggressor().to_flag();
+        return trade;
     }
+
+    fn security_and_clock(row: &csv::StringRecord) -> (i32, i64) {
+        (
+            row[8].parse::<i32>().unwrap(),
+            row[0].parse::<i64>().unwrap(),
+        )
+    }
+    fn is_repeated(row: &csv::StringRecord) -> bool {
+        &row[4] != "0"
+    }
+}
+
+/// every row of an order, trade or mdLog csv file
+pub fn read_csv<T: Convertable>(filename: &str) -> Result<Vec<Arc<T>>, Box<dyn Error>> {
+    return read_csv_filtered(filename, &Filter::default());
 }
 
-pub fn read_csv<T: Convertable>(filename: &str) -> Result<Vec<Rc<T>>, Box<dyn Error>> {
+/// files ending in .gz are decompressed while reading,
+/// rows rejected by the filter are never parsed
+pub fn read_csv_filtered<T: Convertable>(
+    filename: &str,
+    filter: &Filter,
+) -> Result<Vec<Arc<T>>, Box<dyn Error>> {
     // Build the CSV reader and iterate over each record.
-    let mut rdr = csv::Reader::from_path(filename)?;
+    let file = File::open(filename)?;
+    let reader: Box<dyn io::Read> = if filename.ends_with(".gz") {
+        Box::new(GzDecoder::new(io::BufReader::new(file)))
+    } else {
+        Box::new(file)
+    };
+    let mut rdr = csv::Reader::from_reader(reader);
     let mut result = Vec::new();
 
-    let records = rdr.records().into_iter();
+    let records = rdr.records();
     for maybe_row in records {
         let row = maybe_row?;
-        result.push(Rc::new(T::from_string_record(&row)));
+        let (security_id, clock) = T::security_and_clock(&row);
+        if !filter.accept(security_id, clock) {
+            continue;
+        }
+        if filter.skip_repeated && T::is_repeated(&row) {
+            continue;
+        }
+        result.push(Arc::new(T::from_string_record(&row)));
     }
     return Ok(result);
 }
 
-#[derive(Debug)]
+/// rows are written with a header of field names, so snapshots
+/// come out in the mdLog format and can be read back with read_csv
+pub fn write_csv<T: Serialize>(filename: &str, rows: &[T]) -> Result<(), Box<dyn Error>> {
+    return write_csv_to(File::create(filename)?, rows);
+}
+
+/// the same as write_csv to any writer, e.g. stdout
+pub fn write_csv_to<T: Serialize, W: io::Write>(
+    writer: W,
+    rows: &[T],
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for row in rows {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+    return Ok(());
+}
+
+/// a 5 level snapshot, the same columns as an mdLog file
+#[derive(Debug, Clone, Serialize)]
 pub struct Snapshot {
-    pub ms: &'static str,
+    /// time of day of clock, e.g. "09:25:45.124998"
+    pub ms: String,
+    /// microseconds since unix epoch the snapshot is taken at
     pub clock: i64,
+    /// thread of the recorder
     pub threadId: i32,
+    /// same as clock for rebuilt snapshots
     pub clockAtArrival: i64,
+    /// sequence number of the recorder, -1 for rebuilt snapshots
     pub sequenceNo: i64,
+    /// feed of the recorder
     pub source: i8,
+    /// SecurityID of the instrument
     pub StockID: i32,
-    pub exchange: &'static str,
-    pub time: &'static str,
+    /// "SZ"
+    pub exchange: String,
+    /// exchange time of the book, e.g. "09:25:45.120"
+    pub time: String,
+    /// shares traded so far
     pub cum_volume: i64,
+    /// value traded so far
     pub cum_amount: f64,
+    /// last trade price, 0 before the first trade
     pub close: f64,
+    /// tick sequence of the recorder, -1 for rebuilt snapshots
     pub __origTickSeq: i8,
+    /// price of the bid level 1, 0 if there is none
     pub bid1p: f64,
+    /// price of the bid level 2, 0 if there is none
     pub bid2p: f64,
+    /// price of the bid level 3, 0 if there is none
     pub bid3p: f64,
+    /// price of the bid level 4, 0 if there is none
     pub bid4p: f64,
+    /// price of the bid level 5, 0 if there is none
     pub bid5p: f64,
+    /// quantity of the bid level 1
     pub bid1q: i64,
+    /// quantity of the bid level 2
     pub bid2q: i64,
+    /// quantity of the bid level 3
     pub bid3q: i64,
+    /// quantity of the bid level 4
     pub bid4q: i64,
+    /// quantity of the bid level 5
     pub bid5q: i64,
+    /// price of the ask level 1, 0 if there is none
     pub ask1p: f64,
+    /// price of the ask level 2, 0 if there is none
     pub ask2p: f64,
+    /// price of the ask level 3, 0 if there is none
     pub ask3p: f64,
+    /// price of the ask level 4, 0 if there is none
     pub ask4p: f64,
+    /// price of the ask level 5, 0 if there is none
     pub ask5p: f64,
+    /// quantity of the ask level 1
     pub ask1q: i64,
+    /// quantity of the ask level 2
     pub ask2q: i64,
+    /// quantity of the ask level 3
     pub ask3q: i64,
+    /// quantity of the ask level 4
     pub ask4q: i64,
+    /// quantity of the ask level 5
     pub ask5q: i64,
+    /// first trade price, 0 before the first trade
     pub openPrice: f64,
+    /// executions so far
     pub numTrades: i64,
 }
+
+impl Snapshot {
+    /// the date comes from clockAtArrival
+    pub fn exchange_time(&self) -> Result<ExchangeTime, Box<dyn Error>> {
+        let date = ExchangeTime::from_clock(self.clockAtArrival).date;
+        return ExchangeTime::parse(date, &self.time);
+    }
+
+    /// (price, quantity) from best to worst
+    pub fn bids(&self) -> [(f64, i64); 5] {
+        [
+            (self.bid1p, self.bid1q),
+            (self.bid2p, self.bid2q),
+            (self.bid3p, self.bid3q),
+            (self.bid4p, self.bid4q),
+            (self.bid5p, self.bid5q),
+        ]
+    }
+
+    /// (price, quantity) from best to worst
+    pub fn asks(&self) -> [(f64, i64); 5] {
+        [
+            (self.ask1p, self.ask1q),
+            (self.ask2p, self.ask2q),
+            (self.ask3p, self.ask3q),
+            (self.ask4p, self.ask4q),
+            (self.ask5p, self.ask5q),
+        ]
+    }
+}
+
+impl Convertable for Snapshot {
+    fn from_string_record(row: &csv::StringRecord) -> Snapshot {
+        Snapshot {
+            ms: row[0].to_string(),
+            clock: row[1].parse::<i64>().unwrap(),
+            threadId: row[2].parse::<i32>().unwrap(),
+            clockAtArrival: row[3].parse::<i64>().unwrap(),
+            sequenceNo: row[4].parse::<i64>().unwrap(),
+            source: row[5].parse::<i8>().unwrap(),
+            StockID: row[6].parse::<i32>().unwrap(),
+            exchange: row[7].to_string(),
+            time: row[8].to_string(),
+            cum_volume: row[9].parse::<i64>().unwrap(),
+            cum_amount: row[10].parse::<f64>().unwrap(),
+            close: row[11].parse::<f64>().unwrap(),
+            __origTickSeq: row[12].parse::<i8>().unwrap(),
+            bid1p: row[13].parse::<f64>().unwrap(),
+            bid2p: row[14].parse::<f64>().unwrap(),
+            bid3p: row[15].parse::<f64>().unwrap(),
+            bid4p: row[16].parse::<f64>().unwrap(),
+            bid5p: row[17].parse::<f64>().unwrap(),
+            bid1q: row[18].parse::<i64>().unwrap(),
+            bid2q: row[19].parse::<i64>().unwrap(),
+            bid3q: row[20].parse::<i64>().unwrap(),
+            bid4q: row[21].parse::<i64>().unwrap(),
+            bid5q: row[22].parse::<i64>().unwrap(),
+            ask1p: row[23].parse::<f64>().unwrap(),
+            ask2p: row[24].parse::<f64>().unwrap(),
+            ask3p: row[25].parse::<f64>().unwrap(),
+            ask4p: row[26].parse::<f64>().unwrap(),
+            ask5p: row[27].parse::<f64>().unwrap(),
+            ask1q: row[28].parse::<i64>().unwrap(),
+            ask2q: row[29].parse::<i64>().unwrap(),
+            ask3q: row[30].parse::<i64>().unwrap(),
+            ask4q: row[31].parse::<i64>().unwrap(),
+            ask5q: row[32].parse::<i64>().unwrap(),
+            openPrice: row[33].parse::<f64>().unwrap(),
+            numTrades: row[34].parse::<i64>().unwrap(),
+        }
+    }
+
+    fn security_and_clock(row: &csv::StringRecord) -> (i32, i64) {
+        (
+            row[6].parse::<i32>().unwrap(),
+            row[3].parse::<i64>().unwrap(),
+        )
+    }
+}
+
+/// a snapshot with the Level-2 statistics of the exchange that mdLog does not carry,
+/// written as the mdLog columns followed by the statistics
+#[derive(Debug, Clone)]
+pub struct ExtendedSnapshot {
+    /// the mdLog columns
+    pub snapshot: Snapshot,
+    /// highest trade price, 0 before the first trade
+    pub highPrice: f64,
+    /// lowest trade price, 0 before the first trade
+    pub lowPrice: f64,
+    /// 0 before the first trade
+    pub vwap: f64,
+    /// 0 if the previous close is not known
+    pub prevClose: f64,
+    /// quantity resting on the bid side
+    pub totalBidQty: i64,
+    /// quantity resting on the ask side
+    pub totalAskQty: i64,
+    /// weighted by quantity over every price level, 0 for an empty side
+    pub avgBidPrice: f64,
+    /// same as avgBidPrice for the ask side
+    pub avgAskPrice: f64,
+}
+
+// csv can not flatten nested structs, so every column is listed here
+impl Serialize for ExtendedSnapshot {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let s = &self.snapshot;
+        let mut row = serializer.serialize_struct("ExtendedSnapshot", 43)?;
+        row.serialize_field("ms", &s.ms)?;
+        row.serialize_field("clock", &s.clock)?;
+        row.serialize_field("threadId", &s.threadId)?;
+        row.serialize_field("clockAtArrival", &s.clockAtArrival)?;
+        row.serialize_field("sequenceNo", &s.sequenceNo)?;
+        row.serialize_field("source", &s.source)?;
+        row.serialize_field("StockID", &s.StockID)?;
+        row.serialize_field("exchange", &s.exchange)?;
+        row.serialize_field("time", &s.time)?;
+        row.serialize_field("cum_volume", &s.cum_volume)?;
+        row.serialize_field("cum_amount", &s.cum_amount)?;
+        row.serialize_field("close", &s.close)?;
+        row.serialize_field("__origTickSeq", &s.__origTickSeq)?;
+        row.serialize_field("bid1p", &s.bid1p)?;
+        row.serialize_field("bid2p", &s.bid2p)?;
+        row.serialize_field("bid3p", &s.bid3p)?;
+        row.serialize_field("bid4p", &s.bid4p)?;
+        row.serialize_field("bid5p", &s.bid5p)?;
+        row.serialize_field("bid1q", &s.bid1q)?;
+        row.serialize_field("bid2q", &s.bid2q)?;
+        row.serialize_field("bid3q", &s.bid3q)?;
+        row.serialize_field("bid4q", &s.bid4q)?;
+        row.serialize_field("bid5q", &s.bid5q)?;
+        row.serialize_field("ask1p", &s.ask1p)?;
+        row.serialize_field("ask2p", &s.ask2p)?;
+        row.serialize_field("ask3p", &s.ask3p)?;
+        row.serialize_field("ask4p", &s.ask4p)?;
+        row.serialize_field("ask5p", &s.ask5p)?;
+        row.serialize_field("ask1q", &s.ask1q)?;
+        row.serialize_field("ask2q", &s.ask2q)?;
+        row.serialize_field("ask3q", &s.ask3q)?;
+        row.serialize_field("ask4q", &s.ask4q)?;
+        row.serialize_field("ask5q", &s.ask5q)?;
+        row.serialize_field("openPrice", &s.openPrice)?;
+        row.serialize_field("numTrades", &s.numTrades)?;
+        row.serialize_field("highPrice", &self.highPrice)?;
+        row.serialize_field("lowPrice", &self.lowPrice)?;
+        row.serialize_field("vwap", &self.vwap)?;
+        row.serialize_field("prevClose", &self.prevClose)?;
+        row.serialize_field("totalBidQty", &self.totalBidQty)?;
+        row.serialize_field("totalAskQty", &self.totalAskQty)?;
+        row.serialize_field("avgBidPrice", &self.avgBidPrice)?;
+        row.serialize_field("avgAskPrice", &self.avgAskPrice)?;
+        return row.end();
+    }
+}
+
+// rows as the csv files have them, for tests of the modules that replay them
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+
+    pub const DATE: i32 = 20200423;
+
+    // "10:00:00.000" on DATE as YYYYMMDDHHMMSSmmm
+    fn transact_time(time: &str) -> i64 {
+        let millis = ExchangeTime::parse(DATE, time).unwrap().millis;
+        let hhmmssmmm = millis / 3600000 * 10000000
+            + millis / 60000 % 60 * 100000
+            + millis / 1000 % 60 * 1000
+            + millis % 1000;
+        return DATE as i64 * 1000000000 + hhmmssmmm;
+    }
+
+    /// clock of an exchange time of day on DATE
+    pub fn clock(time: &str) -> i64 {
+        return ExchangeTime::parse(DATE, time).unwrap().to_clock();
+    }
+
+    /// a limit order arriving 500 microseconds after its exchange time
+    pub fn order(inst: i32, seq: i64, time: &str, side: Side, price: i64, qty: i64) -> Order {
+        let side = match side {
+            Side::Bid => "1",
+            _ => "2",
+        };
+        let row = csv::StringRecord::from(vec![
+            (clock(time) + 500).to_string(),
+            seq.to_string(),
+            "2".to_string(),
+            "1".to_string(),
+            "0".to_string(),
+            transact_time(time).to_string(),
+            "2011".to_string(),
+            seq.to_string(),
+            inst.to_string(),
+            inst.to_string(),
+            "0".to_string(),
+            side.to_string(),
+            "2".to_string(),
+            "0".to_string(),
+            price.to_string(),
+            qty.to_string(),
+        ]);
+        return Order::from_string_record(&row);
+    }
+
+    /// an execution, or a cancel of the order with the non-zero seq when price is 0
+    pub fn trade(
+        inst: i32,
+        seq: i64,
+        time: &str,
+        price: i64,
+        qty: i64,
+        bid_seq: i64,
+        offer_seq: i64,
+    ) -> Trade {
+        let exec_type = if price == 0 { "4" } else { "F" };
+        let row = csv::StringRecord::from(vec![
+            (clock(time) + 500).to_string(),
+            seq.to_string(),
+            "2".to_string(),
+            "1".to_string(),
+            "0".to_string(),
+            transact_time(time).to_string(),
+            "2011".to_string(),
+            seq.to_string(),
+            inst.to_string(),
+            inst.to_string(),
+            "0".to_string(),
+            exec_type.to_string(),
+            "N".to_string(),
+            "0".to_string(),
+            price.to_string(),
+            qty.to_string(),
+            (price * qty).to_string(),
+            bid_seq.to_string(),
+            offer_seq.to_string(),
+        ]);
+        return Trade::from_string_record(&row);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::trade;
+    use super::*;
+
+    fn aggressor(time: &str, bid_seq: i64, offer_seq: i64) -> Aggressor {
+        return trade(2290, 100, time, 51000, 100, bid_seq, offer_seq).aggressor();
+    }
+
+    #[test]
+    fn the_later_order_is_the_aggressor() {
+        assert_eq!(aggressor("10:00:00.000", 20, 10), Aggressor::Buy);
+        assert_eq!(aggressor("10:00:00.000", 10, 20), Aggressor::Sell);
+        assert_eq!(aggressor("09:30:00.000", 20, 10), Aggressor::Buy);
+        assert_eq!(aggressor("14:56:59.999", 10, 20), Aggressor::Sell);
+        // executions without both orders
+        assert_eq!(aggressor("10:00:00.000", 0, 20), Aggressor::Unknown);
+    }
+
+    #[test]
+    fn call_auction_prints_have_no_aggressor() {
+        assert_eq!(aggressor("09:25:00.000", 20, 10), Aggressor::Auction);
+        assert_eq!(aggressor("09:25:00.000", 10, 20), Aggressor::Auction);
+        assert_eq!(aggressor("15:00:00.000", 20, 10), Aggressor::Auction);
+        assert_eq!(aggressor("14:57:00.000", 10, 20), Aggressor::Auction);
+    }
+
+    #[test]
+    fn cancels_have_no_aggressor() {
+        let cancel = trade(2290, 100, "10:00:00.000", 0, 100, 20, 0);
+        assert_eq!(cancel.aggressor(), Aggressor::Unknown);
+        let time = cancel.exchange_time();
+        let classified = Aggressor::classify(ExecuteType::Cancelled, time, 20, 10);
+        assert_eq!(classified, Aggressor::Unknown);
+    }
+}
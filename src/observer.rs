@@ -0,0 +1,72 @@
+use crate::md;
+use crate::snapshot_builder::Book;
+use std::sync::{Arc, Mutex};
+
+/// callbacks are invoked after the message is fully applied,
+/// so the book passed in is already updated, except on_before_order
+pub trait BookObserver {
+    /// the book as the order found it, called before the order is applied,
+    /// e.g. to see what an aggressive order is about to take
+    fn on_before_order(&mut self, _book: &Book, _order: &md::Order) {}
+
+    /// a new order, whether it rests or not
+    fn on_order_added(&mut self, _book: &Book, _order: &md::Order) {}
+
+    /// quantity of order was cancelled
+    fn on_order_cancelled(&mut self, _book: &Book, _order: &md::Order, _quantity: i64) {}
+
+    /// an execution, cancels go to on_order_cancelled
+    fn on_order_executed(&mut self, _book: &Book, _trade: &md::Trade) {}
+
+    /// quantity is the new total at the level, 0 if the level is removed
+    fn on_level_changed(&mut self, _book: &Book, _side: md::Side, _price: i64, _quantity: i64) {}
+
+    /// called at most once per message
+    fn on_top_of_book_changed(&mut self, _book: &Book) {}
+
+    /// quantity matched when a call auction is uncrossed
+    fn on_auction_uncross(&mut self, _book: &Book, _quantity: i64) {}
+
+    /// a snapshot of the book is taken on the schedule of the builder
+    fn on_snapshot(&mut self, _book: &Book) {}
+}
+
+/// lets the caller keep a handle on an observer owned by a builder,
+/// e.g. to take results out of it once the replay is done
+impl<T: BookObserver> BookObserver for Arc<Mutex<T>> {
+    fn on_before_order(&mut self, book: &Book, order: &md::Order) {
+        self.lock().unwrap().on_before_order(book, order);
+    }
+
+    fn on_order_added(&mut self, book: &Book, order: &md::Order) {
+        self.lock().unwrap().on_order_added(book, order);
+    }
+
+    fn on_order_cancelled(&mut self, book: &Book, order: &md::Order, quantity: i64) {
+        self.lock()
+            .unwrap()
+            .on_order_cancelled(book, order, quantity);
+    }
+
+    fn on_order_executed(&mut self, book: &Book, trade: &md::Trade) {
+        self.lock().unwrap().on_order_executed(book, trade);
+    }
+
+    fn on_level_changed(&mut self, book: &Book, side: md::Side, price: i64, quantity: i64) {
+        self.lock()
+            .unwrap()
+            .on_level_changed(book, side, price, quantity);
+    }
+
+    fn on_top_of_book_changed(&mut self, book: &Book) {
+        self.lock().unwrap().on_top_of_book_changed(book);
+    }
+
+    fn on_auction_uncross(&mut self, book: &Book, quantity: i64) {
+        self.lock().unwrap().on_auction_uncross(book, quantity);
+    }
+
+    fn on_snapshot(&mut self, book: &Book) {
+        self.lock().unwrap().on_snapshot(book);
+    }
+}
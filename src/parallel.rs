@@ -0,0 +1,342 @@
+use crate::md;
+use crate::schedule::Schedule;
+use crate::snapshot_builder::{ClockType, SnapshotBuilder};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// how the event stream is split between workers
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Partition {
+    /// one job per SecurityID
+    Instrument,
+    /// one job per ChannelNo, fewer and larger jobs
+    Channel,
+}
+
+// events of one partition
+struct Job {
+    orders: Vec<Arc<md::Order>>,
+    trades: Vec<Arc<md::Trade>>,
+    snapshots: Vec<Arc<md::Snapshot>>,
+    init: Vec<md::Snapshot>,
+    // positions of its events in the replay of every instrument, ascending
+    ranks: Vec<usize>,
+}
+
+impl Job {
+    fn empty() -> Job {
+        Job {
+            orders: Vec::new(),
+            trades: Vec::new(),
+            snapshots: Vec::new(),
+            init: Vec::new(),
+            ranks: Vec::new(),
+        }
+    }
+}
+
+/// books of different instruments are independent, so they are
+/// rebuilt on a pool of threads and merged back in the order one builder
+/// would have produced them
+pub struct ParallelSnapshotBuilder {
+    orders_: Vec<Arc<md::Order>>,
+    trades_: Vec<Arc<md::Trade>>,
+    snapshots_: Vec<Arc<md::Snapshot>>,
+    init_: Vec<md::Snapshot>,
+    clock_type_: ClockType,
+    partition_: Partition,
+    threads_: usize,
+}
+
+impl ParallelSnapshotBuilder {
+    /// rebuilds on threads workers, at least one, on the arrival clock
+    pub fn new(
+        orders: Vec<Arc<md::Order>>,
+        trades: Vec<Arc<md::Trade>>,
+        threads: usize,
+    ) -> ParallelSnapshotBuilder {
+        ParallelSnapshotBuilder {
+            orders_: orders,
+            trades_: trades,
+            snapshots_: Vec::new(),
+            init_: Vec::new(),
+            clock_type_: ClockType::Arrival,
+            partition_: Partition::Instrument,
+            threads_: threads.max(1),
+        }
+    }
+
+    /// see SnapshotBuilder::set_clock_type
+    pub fn set_clock_type(&mut self, clock_type: ClockType) {
+        self.clock_type_ = clock_type;
+    }
+
+    /// Instrument by default
+    pub fn set_partition(&mut self, partition: Partition) {
+        self.partition_ = partition;
+    }
+
+    /// see SnapshotBuilder::set_exchange_snapshots
+    pub fn set_exchange_snapshots(&mut self, snapshots: Vec<Arc<md::Snapshot>>) {
+        self.snapshots_ = snapshots;
+    }
+
+    /// start from these snapshots, see SnapshotBuilder::init
+    pub fn init(&mut self, snapshots: &[md::Snapshot]) {
+        self.init_.extend_from_slice(snapshots);
+    }
+
+    /// the same output as SnapshotBuilder::build_on_schedule
+    pub fn build_on_schedule(self, schedule: &Schedule) -> Vec<md::Snapshot> {
+        let clock_type = self.clock_type_;
+        let threads = self.threads_;
+        // the same day for every job, even one with only exchange snapshots
+        let date = match self.orders_.first() {
+            Some(order) => order.exchange_time().date,
+            None => match self.trades_.first() {
+                Some(trade) => trade.exchange_time().date,
+                None => return Vec::new(),
+            },
+        };
+        let timestamps = schedule.timestamps(date);
+        let jobs = self.partition(timestamps.is_none());
+
+        let queue = Mutex::new(jobs.into_iter().enumerate().collect::<VecDeque<_>>());
+        let results = Mutex::new(BTreeMap::new());
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let (idx, job) = match queue.lock().unwrap().pop_front() {
+                        Some(next) => next,
+                        None => return,
+                    };
+                    let snapshots = run_job(job, timestamps.as_deref(), clock_type);
+                    results.lock().unwrap().insert(idx, snapshots);
+                });
+            }
+        });
+
+        // the timestamp index or the rank of the event, then SecurityID,
+        // like the books of a single builder
+        let mut snapshots: Vec<(usize, md::Snapshot)> = results
+            .into_inner()
+            .unwrap()
+            .into_values()
+            .flatten()
+            .collect();
+        snapshots.sort_by_key(|(key, snapshot)| (*key, snapshot.StockID));
+        return snapshots
+            .into_iter()
+            .map(|(_, snapshot)| snapshot)
+            .collect();
+    }
+
+    // ranks follow SnapshotBuilder::next_event: by time on the replay clock,
+    // orders then trades then exchange snapshots, each in input order
+    fn ranks(&self) -> (Vec<usize>, Vec<usize>, Vec<usize>) {
+        let clock_type = self.clock_type_;
+        let mut events: Vec<(i64, u8, usize)> = Vec::new();
+        for (idx, order) in self.orders_.iter().enumerate() {
+            events.push((clock_type.order_time(order), 0, idx));
+        }
+        for (idx, trade) in self.trades_.iter().enumerate() {
+            events.push((clock_type.trade_time(trade), 1, idx));
+        }
+        for (idx, snapshot) in self.snapshots_.iter().enumerate() {
+            events.push((clock_type.snapshot_time(snapshot), 2, idx));
+        }
+        events.sort_unstable();
+
+        let mut ranks = (
+            vec![0; self.orders_.len()],
+            vec![0; self.trades_.len()],
+            vec![0; self.snapshots_.len()],
+        );
+        for (rank, (_, stream, idx)) in events.into_iter().enumerate() {
+            match stream {
+                0 => ranks.0[idx] = rank,
+                1 => ranks.1[idx] = rank,
+                _ => ranks.2[idx] = rank,
+            }
+        }
+        return ranks;
+    }
+
+    // ranked when every event is its own snapshot
+    fn partition(self, ranked: bool) -> Vec<Job> {
+        let partition = self.partition_;
+        let (order_ranks, trade_ranks, snapshot_ranks) = if ranked {
+            self.ranks()
+        } else {
+            (Vec::new(), Vec::new(), Vec::new())
+        };
+        // instruments of a channel go to the same job
+        let mut keys = HashMap::new();
+        for order in self.orders_.iter() {
+            keys.entry(order.SecurityID).or_insert(match partition {
+                Partition::Instrument => order.SecurityID,
+                Partition::Channel => order.ChannelNo,
+            });
+        }
+
+        let mut grouped: BTreeMap<i32, Job> = BTreeMap::new();
+        for (idx, order) in self.orders_.into_iter().enumerate() {
+            let key = *keys.get(&order.SecurityID).unwrap();
+            let job = grouped.entry(key).or_insert_with(Job::empty);
+            job.orders.push(order);
+            job.ranks.extend(order_ranks.get(idx));
+        }
+        for (idx, trade) in self.trades_.into_iter().enumerate() {
+            let key = *keys.get(&trade.SecurityID).unwrap_or(&trade.SecurityID);
+            let job = grouped.entry(key).or_insert_with(Job::empty);
+            job.trades.push(trade);
+            job.ranks.extend(trade_ranks.get(idx));
+        }
+        for (idx, snapshot) in self.snapshots_.into_iter().enumerate() {
+            let key = *keys.get(&snapshot.StockID).unwrap_or(&snapshot.StockID);
+            let job = grouped.entry(key).or_insert_with(Job::empty);
+            job.snapshots.push(snapshot);
+            job.ranks.extend(snapshot_ranks.get(idx));
+        }
+        for snapshot in self.init_ {
+            let key = *keys.get(&snapshot.StockID).unwrap_or(&snapshot.StockID);
+            let job = grouped.entry(key).or_insert_with(Job::empty);
+            job.init.push(snapshot);
+        }
+        let mut jobs: Vec<Job> = grouped.into_values().collect();
+        for job in jobs.iter_mut() {
+            job.ranks.sort_unstable();
+        }
+        return jobs;
+    }
+}
+
+// snapshots keyed by the index of their timestamp, or by the rank of
+// their event when there are no timestamps
+fn run_job(
+    job: Job,
+    timestamps: Option<&[i64]>,
+    clock_type: ClockType,
+) -> Vec<(usize, md::Snapshot)> {
+    let mut builder = SnapshotBuilder::new(job.orders, job.trades);
+    builder.set_clock_type(clock_type);
+    builder.set_exchange_snapshots(job.snapshots);
+    if !job.init.is_empty() {
+        builder.init(&job.init);
+    }
+
+    let mut snapshots = Vec::new();
+    match timestamps {
+        Some(timestamps) => {
+            let groups = builder.build_snapshot_groups(timestamps);
+            for (idx, group) in groups.into_iter().enumerate() {
+                snapshots.extend(group.into_iter().map(|snapshot| (idx, snapshot)));
+            }
+        }
+        None => {
+            // the job replays its events in the order of their ranks
+            let mut ranks = job.ranks.into_iter();
+            while let Some(changed) = builder.step() {
+                let rank = ranks.next().unwrap();
+                if let Some(book) = changed.and_then(|inst_id| builder.book(inst_id)) {
+                    snapshots.push((rank, book.to_snapshot()));
+                }
+            }
+        }
+    }
+    return snapshots;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md::testing::{clock, order, trade};
+    use crate::md::Side;
+    use crate::snapshot_builder::Book;
+
+    // 2290 and 2385 on one channel and 2291 on another, in arrival order,
+    // with events of different instruments at the same time on both clocks
+    fn events() -> (Vec<Arc<md::Order>>, Vec<Arc<md::Trade>>) {
+        let mut orders = vec![
+            order(2385, 1, "10:00:00.000", Side::Ask, 95000, 100),
+            order(2290, 2, "10:00:00.000", Side::Bid, 51000, 100),
+            order(2291, 3, "10:00:00.000", Side::Bid, 30000, 100),
+            order(2290, 4, "10:00:01.000", Side::Ask, 51000, 100),
+            order(2291, 6, "10:00:01.000", Side::Ask, 30100, 100),
+            order(2385, 7, "10:00:01.000", Side::Bid, 94900, 100),
+        ];
+        let mut trades = vec![
+            trade(2291, 8, "10:00:00.000", 0, 100, 3, 0),
+            trade(2290, 5, "10:00:01.000", 51000, 100, 2, 4),
+            trade(2385, 9, "10:00:01.000", 0, 100, 1, 0),
+        ];
+        for order in orders.iter_mut() {
+            if order.SecurityID == 2291 {
+                order.ChannelNo = 2012;
+            }
+        }
+        for trade in trades.iter_mut() {
+            if trade.SecurityID == 2291 {
+                trade.ChannelNo = 2012;
+            }
+        }
+        let orders = orders.into_iter().map(Arc::new).collect();
+        let trades = trades.into_iter().map(Arc::new).collect();
+        return (orders, trades);
+    }
+
+    // an exchange snapshot of an instrument with no orders or trades
+    fn exchange_snapshot() -> Arc<md::Snapshot> {
+        let mut book = Book::new(2386);
+        book.timestamp = clock("10:00:00.000") + 500;
+        book.exchange_time = md::ExchangeTime::from_clock(clock("10:00:00.000"));
+        book.apply_change(Side::Bid, 12000, 300);
+        return Arc::new(book.to_snapshot());
+    }
+
+    fn single(clock_type: ClockType, schedule: &Schedule) -> Vec<String> {
+        let (orders, trades) = events();
+        let mut builder = SnapshotBuilder::new(orders, trades);
+        builder.set_clock_type(clock_type);
+        if clock_type == ClockType::Exchange {
+            builder.set_exchange_snapshots(vec![exchange_snapshot()]);
+        }
+        let snapshots = builder.build_on_schedule(schedule);
+        return snapshots.iter().map(|row| format!("{:?}", row)).collect();
+    }
+
+    fn parallel(clock_type: ClockType, schedule: &Schedule, partition: Partition) -> Vec<String> {
+        let (orders, trades) = events();
+        let mut builder = ParallelSnapshotBuilder::new(orders, trades, 2);
+        builder.set_clock_type(clock_type);
+        builder.set_partition(partition);
+        if clock_type == ClockType::Exchange {
+            builder.set_exchange_snapshots(vec![exchange_snapshot()]);
+        }
+        let snapshots = builder.build_on_schedule(schedule);
+        return snapshots.iter().map(|row| format!("{:?}", row)).collect();
+    }
+
+    #[test]
+    fn same_output_as_a_single_builder() {
+        let schedules = [
+            Schedule::EveryEvent,
+            Schedule::Timestamps(vec![
+                clock("10:00:00.000") + 1000,
+                clock("10:00:01.000") + 1000,
+            ]),
+        ];
+        for schedule in schedules.iter() {
+            for clock_type in [ClockType::Arrival, ClockType::Exchange] {
+                let expected = single(clock_type, schedule);
+                // a job with only exchange snapshots still has the day
+                let seeded = expected.iter().any(|row| row.contains("StockID: 2386"));
+                assert_eq!(seeded, clock_type == ClockType::Exchange);
+                for partition in [Partition::Instrument, Partition::Channel] {
+                    assert_eq!(parallel(clock_type, schedule, partition), expected);
+                }
+            }
+        }
+    }
+}
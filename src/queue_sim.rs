@@ -0,0 +1,336 @@
+use crate::lifecycle::Fill;
+use crate::md;
+use crate::observer::BookObserver;
+use crate::schedule;
+use crate::snapshot_builder::Book;
+use std::collections::HashSet;
+use std::error::Error;
+
+/// a limit order that is not sent anywhere, prices in book units
+#[derive(Debug, Clone)]
+pub struct HypotheticalOrder {
+    /// SecurityID of the instrument
+    pub inst_id: i32,
+    /// Bid to buy, Ask to sell
+    pub side: md::Side,
+    /// limit price
+    pub price: i64,
+    /// shares to trade
+    pub quantity: i64,
+    /// exchange time of day it joins the book, in milliseconds
+    pub millis: i64,
+    /// cancelled at this exchange time of day if anything is left
+    pub cancel_millis: Option<i64>,
+}
+
+impl HypotheticalOrder {
+    /// "2290,bid,5.12,1000,09:35:00" with an optional cancel time as a sixth field
+    pub fn parse(s: &str) -> Result<HypotheticalOrder, Box<dyn Error>> {
+        let fields: Vec<&str> = s.split(',').map(|field| field.trim()).collect();
+        if fields.len() != 5 && fields.len() != 6 {
+            return Err(format!("invalid hypothetical order '{}'", s).into());
+        }
+        let side = match fields[1] {
+            "bid" | "buy" => md::Side::Bid,
+            "ask" | "sell" => md::Side::Ask,
+            side => return Err(format!("unknown side '{}' in '{}'", side, s).into()),
+        };
+        let price = fields[2].parse::<f64>()?;
+        return Ok(HypotheticalOrder {
+            inst_id: fields[0].parse::<i32>()?,
+            side,
+            price: (price * Book::PRICE_DIVISOR).round() as i64,
+            quantity: fields[3].parse::<i64>()?,
+            millis: md::ExchangeTime::parse(0, fields[4])?.millis,
+            cancel_millis: match fields.get(5) {
+                Some(time) => Some(md::ExchangeTime::parse(0, time)?.millis),
+                None => None,
+            },
+        });
+    }
+}
+
+/// what the hypothetical order would have gone through
+#[derive(Debug, Clone)]
+pub struct SimulatedOrder {
+    /// the order as given
+    pub order: HypotheticalOrder,
+    /// when it joined the book, None if the replay never got there
+    pub entry_time: Option<md::ExchangeTime>,
+    /// quantity ahead of it in its queue when it joined
+    pub entry_queue_ahead: i64,
+    /// quantity ahead of it now
+    pub queue_ahead: i64,
+    /// executions in the order they happened
+    pub fills: Vec<Fill>,
+    /// when it was cancelled, None if it was not
+    pub cancel_time: Option<md::ExchangeTime>,
+    // resting orders that were ahead of it when it joined
+    ahead: HashSet<i64>,
+}
+
+impl SimulatedOrder {
+    /// not in the book until the replay reaches its time
+    pub fn new(order: HypotheticalOrder) -> SimulatedOrder {
+        SimulatedOrder {
+            order,
+            entry_time: None,
+            entry_queue_ahead: 0,
+            queue_ahead: 0,
+            fills: Vec::new(),
+            cancel_time: None,
+            ahead: HashSet::new(),
+        }
+    }
+
+    /// sum of the fills
+    pub fn filled_quantity(&self) -> i64 {
+        return self.fills.iter().map(|fill| fill.quantity).sum();
+    }
+
+    /// quantity not filled yet, including any cancelled part
+    pub fn remaining_quantity(&self) -> i64 {
+        return self.order.quantity - self.filled_quantity();
+    }
+
+    /// still resting in the book
+    pub fn is_active(&self) -> bool {
+        return self.entry_time.is_some()
+            && self.cancel_time.is_none()
+            && self.remaining_quantity() > 0;
+    }
+
+    // better for the order than the price, e.g. a higher bid
+    fn improves_on(&self, price: i64) -> bool {
+        match self.order.side {
+            md::Side::Bid => self.order.price > price,
+            _ => self.order.price < price,
+        }
+    }
+
+    fn fill(&mut self, seq: i64, time: md::ExchangeTime, price: i64, quantity: i64) {
+        let quantity = quantity.min(self.remaining_quantity());
+        if quantity > 0 {
+            self.fills.push(Fill {
+                seq,
+                exchange_time: time,
+                price,
+                quantity,
+            });
+        }
+    }
+
+    /// joins the book at the time, taking what it crosses at once and
+    /// queueing behind everything resting at its price
+    pub fn enter(&mut self, book: &Book, time: md::ExchangeTime) {
+        self.entry_time = Some(time);
+        let side = self.order.side;
+        // nothing matches in call auctions
+        let opposite: Vec<(i64, i64)> = if schedule::is_matching(time.millis) {
+            book.depth(side.opposite()).collect()
+        } else {
+            Vec::new()
+        };
+        // there is no trade for what it takes at once, so no seq either
+        for (price, quantity) in opposite {
+            if self.remaining_quantity() == 0
+                || !(self.improves_on(price) || self.order.price == price)
+            {
+                break;
+            }
+            self.fill(0, time, price, quantity);
+        }
+        self.join(book, time);
+    }
+
+    /// queues behind everything resting at its price without taking anything,
+    /// for callers that match the aggressive part themselves
+    pub fn join(&mut self, book: &Book, time: md::ExchangeTime) {
+        self.entry_time = Some(time);
+        let side = self.order.side;
+        let level = book.depth_at(side, self.order.price);
+        self.ahead = book
+            .orders_at(side, self.order.price)
+            .map(|(order, _)| order.ApplSeqNum)
+            .collect();
+        self.entry_queue_ahead = level;
+        self.queue_ahead = level;
+    }
+
+    /// an order of the book was cancelled
+    pub fn on_cancel(&mut self, order: &md::Order, quantity: i64) {
+        if self.ahead.contains(&order.ApplSeqNum) {
+            self.queue_ahead = (self.queue_ahead - quantity).max(0);
+        }
+    }
+
+    /// an execution of the book, orders at our price fill in time priority
+    /// and anything trading through our price would have taken all of us
+    pub fn on_trade(&mut self, trade: &md::Trade) {
+        let passive = match trade.aggressor() {
+            md::Aggressor::Buy => md::Side::Ask,
+            md::Aggressor::Sell => md::Side::Bid,
+            // both sides rest in a call auction
+            md::Aggressor::Auction => self.order.side,
+            md::Aggressor::Unknown => return,
+        };
+        if !matches!(
+            (passive, self.order.side),
+            (md::Side::Bid, md::Side::Bid) | (md::Side::Ask, md::Side::Ask)
+        ) {
+            return;
+        }
+        let time = trade.exchange_time();
+        if self.improves_on(trade.TradePrice) {
+            self.fill(
+                trade.ApplSeqNum,
+                time,
+                self.order.price,
+                self.remaining_quantity(),
+            );
+        } else if trade.TradePrice == self.order.price {
+            let behind = trade.TradeQty - self.queue_ahead;
+            self.queue_ahead = (self.queue_ahead - trade.TradeQty).max(0);
+            if behind > 0 {
+                self.fill(trade.ApplSeqNum, time, self.order.price, behind);
+            }
+        }
+    }
+}
+
+/// replays hypothetical orders against the real books without changing them,
+/// usable as an observer
+pub struct QueueSimulator {
+    orders_: Vec<SimulatedOrder>,
+}
+
+impl QueueSimulator {
+    /// simulates each of orders independently of the others
+    pub fn new(orders: Vec<HypotheticalOrder>) -> QueueSimulator {
+        QueueSimulator {
+            orders_: orders.into_iter().map(SimulatedOrder::new).collect(),
+        }
+    }
+
+    // enter and cancel orders whose time has come
+    fn advance(&mut self, book: &Book, time: md::ExchangeTime) {
+        let millis = time.millis;
+        for simulated in self.orders_.iter_mut() {
+            if simulated.order.inst_id != book.inst_id() {
+                continue;
+            }
+            if simulated.entry_time.is_none() && millis >= simulated.order.millis {
+                simulated.enter(book, time);
+            }
+            let expired = simulated
+                .order
+                .cancel_millis
+                .is_some_and(|cancel| millis >= cancel);
+            if expired && simulated.is_active() {
+                simulated.cancel_time = Some(time);
+            }
+        }
+    }
+
+    /// the orders in the order they were given
+    pub fn orders(&self) -> &[SimulatedOrder] {
+        return &self.orders_;
+    }
+}
+
+impl BookObserver for QueueSimulator {
+    // the book as the first order at or after its time found it
+    fn on_before_order(&mut self, book: &Book, order: &md::Order) {
+        self.advance(book, order.exchange_time());
+    }
+
+    fn on_order_cancelled(&mut self, book: &Book, order: &md::Order, quantity: i64) {
+        self.advance(book, book.exchange_time);
+        for simulated in self.orders_.iter_mut() {
+            if simulated.order.inst_id == book.inst_id() && simulated.is_active() {
+                simulated.on_cancel(order, quantity);
+            }
+        }
+    }
+
+    fn on_order_executed(&mut self, book: &Book, trade: &md::Trade) {
+        self.advance(book, book.exchange_time);
+        for simulated in self.orders_.iter_mut() {
+            if simulated.order.inst_id == book.inst_id() && simulated.is_active() {
+                simulated.on_trade(trade);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md::testing::{order, trade};
+    use crate::md::Side;
+    use crate::snapshot_builder::{ClockType, SnapshotBuilder};
+    use std::sync::{Arc, Mutex};
+
+    // bids of 100 and 200 ahead of a hypothetical bid of 300 at 51000 and one
+    // of 100 behind it, a cancel of each, then sells that take the level and
+    // trade through it
+    fn simulate(hypothetical: &str) -> SimulatedOrder {
+        let orders = vec![
+            order(2290, 1, "10:00:00.000", Side::Bid, 51000, 100),
+            order(2290, 2, "10:00:00.000", Side::Bid, 51000, 200),
+            order(2290, 3, "10:00:00.000", Side::Bid, 50900, 500),
+            order(2290, 4, "10:00:01.000", Side::Bid, 51000, 100),
+            order(2290, 7, "10:00:03.000", Side::Ask, 51000, 300),
+            order(2290, 10, "10:00:04.000", Side::Ask, 50900, 500),
+        ];
+        let trades = vec![
+            trade(2290, 5, "10:00:02.000", 0, 100, 1, 0),
+            trade(2290, 6, "10:00:02.000", 0, 50, 4, 0),
+            trade(2290, 8, "10:00:03.000", 51000, 200, 2, 7),
+            trade(2290, 9, "10:00:03.000", 51000, 50, 4, 7),
+            trade(2290, 11, "10:00:04.000", 50900, 500, 3, 10),
+        ];
+        let orders = orders.into_iter().map(Arc::new).collect();
+        let trades = trades.into_iter().map(Arc::new).collect();
+        let mut builder = SnapshotBuilder::new(orders, trades);
+        builder.set_clock_type(ClockType::Exchange);
+        let order = HypotheticalOrder::parse(hypothetical).unwrap();
+        let simulator = Arc::new(Mutex::new(QueueSimulator::new(vec![order])));
+        builder.add_observer(Box::new(simulator.clone()));
+        builder.process_until(i64::MAX);
+        let simulated = simulator.lock().unwrap().orders()[0].clone();
+        return simulated;
+    }
+
+    fn fills(simulated: &SimulatedOrder) -> Vec<(i64, i64, i64)> {
+        return simulated
+            .fills
+            .iter()
+            .map(|fill| (fill.seq, fill.price, fill.quantity))
+            .collect();
+    }
+
+    #[test]
+    fn cancels_ahead_move_it_up_and_trades_fill_it() {
+        let simulated = simulate("2290,buy,5.1,300,10:00:00.500");
+        assert_eq!(simulated.entry_queue_ahead, 300);
+        // the cancel of 1 moved it up, the one of 4 behind it did not, the
+        // trade of 250 at its price took 2 and 50 of it, the trade through
+        // its price the rest
+        assert_eq!(fills(&simulated), vec![(9, 51000, 50), (11, 51000, 250)]);
+        assert_eq!(simulated.queue_ahead, 0);
+        assert!(simulated.cancel_time.is_none());
+        assert!(!simulated.is_active());
+    }
+
+    #[test]
+    fn expired_orders_stop_filling() {
+        let simulated = simulate("2290,buy,5.1,300,10:00:00.500,10:00:03.500");
+        assert_eq!(fills(&simulated), vec![(9, 51000, 50)]);
+        // with the first message after its cancel time
+        let cancel = md::ExchangeTime::parse(md::testing::DATE, "10:00:04.000").unwrap();
+        assert_eq!(simulated.cancel_time.unwrap().millis, cancel.millis);
+        assert_eq!(simulated.remaining_quantity(), 250);
+        assert!(!simulated.is_active());
+    }
+}
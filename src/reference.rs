@@ -0,0 +1,140 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// static data of an instrument for one trading day, prices in book units
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReferenceData {
+    /// close of the previous trading day
+    pub prev_close: i64,
+    /// highest price an order may have
+    pub upper_limit: i64,
+    /// lowest price an order may have
+    pub lower_limit: i64,
+}
+
+// ChiNext moved to a 20% limit on this day
+const CHINEXT_REFORM_DATE: i32 = 20200824;
+
+impl ReferenceData {
+    /// limits of SZSE A shares from the previous close, 10% or 20% for ChiNext
+    /// after its reform, rounded to the tick, ST shares are not known here
+    pub fn from_prev_close(inst_id: i32, date: i32, prev_close: i64) -> ReferenceData {
+        let chinext = format!("{:06}", inst_id).starts_with("300");
+        let percent = if chinext && date >= CHINEXT_REFORM_DATE {
+            20
+        } else {
+            10
+        };
+        // a tick is 100 book units, so 10000 once multiplied by a percentage
+        let limit = |percent: i64| (prev_close * percent + 5000) / 10000 * 100;
+        ReferenceData {
+            prev_close,
+            upper_limit: limit(100 + percent),
+            lower_limit: limit(100 - percent),
+        }
+    }
+
+    /// whether price is within the limits, both included
+    pub fn within_limits(&self, price: i64) -> bool {
+        return self.lower_limit <= price && price <= self.upper_limit;
+    }
+}
+
+/// previous closes carried over from day to day, e.g. over a batch of days
+#[derive(Debug, Clone, Default)]
+pub struct PrevCloses {
+    // key: SecurityID, value: last trade price of the last day it traded
+    closes_: HashMap<i32, i64>,
+}
+
+impl PrevCloses {
+    /// no closes until the first day is done
+    pub fn new() -> PrevCloses {
+        PrevCloses::default()
+    }
+
+    /// reference data on date of every instrument with an earlier close
+    pub fn reference(&self, date: i32) -> HashMap<i32, ReferenceData> {
+        return self
+            .closes_
+            .iter()
+            .map(|(inst_id, close)| {
+                (
+                    *inst_id,
+                    ReferenceData::from_prev_close(*inst_id, date, *close),
+                )
+            })
+            .collect();
+    }
+
+    /// (SecurityID, last trade price) at the end of a day, 0 if it did not
+    /// trade, instruments that did not trade keep their earlier close
+    pub fn update(&mut self, closes: impl IntoIterator<Item = (i32, i64)>) {
+        for (inst_id, close) in closes {
+            if close != 0 {
+                self.closes_.insert(inst_id, close);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_round_half_up_to_the_tick() {
+        // 10.05 * 1.1 = 11.055 and 10.05 * 0.9 = 9.045
+        let reference = ReferenceData::from_prev_close(2290, 20200423, 100500);
+        assert_eq!(reference.upper_limit, 110600);
+        assert_eq!(reference.lower_limit, 90500);
+        // 3.33 * 1.1 = 3.663 and 3.33 * 0.9 = 2.997
+        let reference = ReferenceData::from_prev_close(2290, 20200423, 33300);
+        assert_eq!(reference.upper_limit, 36600);
+        assert_eq!(reference.lower_limit, 30000);
+        assert!(reference.within_limits(36600));
+        assert!(reference.within_limits(30000));
+        assert!(!reference.within_limits(36700));
+        assert!(!reference.within_limits(29900));
+    }
+
+    #[test]
+    fn chinext_limits_widen_with_the_reform() {
+        let limits = |inst_id, date| {
+            let reference = ReferenceData::from_prev_close(inst_id, date, 50000);
+            (reference.lower_limit, reference.upper_limit)
+        };
+        assert_eq!(limits(300750, 20200821), (45000, 55000));
+        assert_eq!(limits(300750, 20200824), (40000, 60000));
+        assert_eq!(limits(300750, 20200825), (40000, 60000));
+        // main board and SME board stay at 10%
+        assert_eq!(limits(2290, 20200824), (45000, 55000));
+        assert_eq!(limits(600, 20200824), (45000, 55000));
+    }
+
+    #[test]
+    fn closes_carry_over_days_without_trades() {
+        let mut prev_closes = PrevCloses::new();
+        assert!(prev_closes.reference(20200422).is_empty());
+        prev_closes.update(vec![(2290, 51000), (2385, 95000), (2291, 0)]);
+
+        let reference = prev_closes.reference(20200423);
+        assert_eq!(reference.len(), 2);
+        assert_eq!(reference[&2290].prev_close, 51000);
+        assert_eq!(
+            reference[&2385],
+            ReferenceData::from_prev_close(2385, 20200423, 95000)
+        );
+
+        // 2385 is missing on the second day, 2291 trades for the first time
+        prev_closes.update(vec![(2290, 52000), (2291, 30000)]);
+        let reference = prev_closes.reference(20200424);
+        assert_eq!(reference[&2290].prev_close, 52000);
+        assert_eq!(reference[&2385].prev_close, 95000);
+        assert_eq!(reference[&2291].prev_close, 30000);
+
+        // and a book without trades on the third day keeps its close too
+        prev_closes.update(vec![(2385, 0)]);
+        assert_eq!(prev_closes.reference(20200427)[&2385].prev_close, 95000);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::md;
+use crate::snapshot_builder::ClockType;
+use std::error::Error;
+
+/// SZSE trading hours in exchange time, lunch break is skipped
+pub const SESSIONS: [(i64, i64); 2] = [
+    // opening call auction and morning session: 09:15 - 11:30
+    ((9 * 3600 + 15 * 60) * 1000, (11 * 3600 + 30 * 60) * 1000),
+    // afternoon session and closing call auction: 13:00 - 15:00
+    (13 * 3600 * 1000, 15 * 3600 * 1000),
+];
+
+/// continuous trading starts once the opening call auction is over
+pub const CONTINUOUS_START_MILLIS: i64 = (9 * 3600 + 30 * 60) * 1000;
+/// the closing call auction runs from 14:57 until the close
+pub const CLOSING_AUCTION_START_MILLIS: i64 = (14 * 3600 + 57 * 60) * 1000;
+
+/// exchange time of day in one of the call auctions, where there is no aggressor
+pub fn is_call_auction(millis: i64) -> bool {
+    return !(CONTINUOUS_START_MILLIS..CLOSING_AUCTION_START_MILLIS).contains(&millis);
+}
+
+/// the opening call auction is matched at 09:25:00.000 exchange time
+pub const OPENING_MATCH_MILLIS: i64 = (9 * 3600 + 25 * 60) * 1000;
+/// the closing call auction is matched at 15:00:00.000 exchange time
+pub const CLOSING_MATCH_MILLIS: i64 = 15 * 3600 * 1000;
+
+/// exchange time of day when an order matches the book as it arrives,
+/// orders of a call auction wait for it to be matched,
+/// orders between the opening match and 09:30 wait for continuous trading
+/// in time priority, which ends with the same book as matching them at once
+pub fn is_matching(millis: i64) -> bool {
+    if (OPENING_MATCH_MILLIS..CONTINUOUS_START_MILLIS).contains(&millis) {
+        return true;
+    }
+    return !is_call_auction(millis) || millis >= CLOSING_MATCH_MILLIS;
+}
+
+/// exchange snapshots are published every 3 seconds
+pub const EXCHANGE_CADENCE_MILLIS: i64 = 3000;
+
+/// when snapshots are taken
+pub enum Schedule {
+    /// every N milliseconds across the whole session
+    Interval(i64),
+    /// every 3 seconds, aligned to exchange snapshot times
+    ExchangeCadence,
+    /// the given clocks, e.g. snapshot times from an mdLog file
+    Timestamps(Vec<i64>),
+    /// after every order or trade
+    EveryEvent,
+}
+
+impl Schedule {
+    /// one snapshot time per exchange snapshot in the mdLog file
+    pub fn from_mdlog(
+        filename: &str,
+        filter: &md::Filter,
+        clock_type: ClockType,
+    ) -> Result<Schedule, Box<dyn Error>> {
+        let snapshots = md::read_csv_filtered::<md::Snapshot>(filename, filter)?;
+        let mut timestamps = Vec::with_capacity(snapshots.len());
+        for snapshot in snapshots.iter() {
+            let ts = match clock_type {
+                ClockType::Arrival => snapshot.clockAtArrival,
+                ClockType::Exchange => snapshot.exchange_time()?.to_clock(),
+            };
+            timestamps.push(ts);
+        }
+        timestamps.sort_unstable();
+        timestamps.dedup();
+        return Ok(Schedule::Timestamps(timestamps));
+    }
+
+    /// snapshot clocks on the given trading day, None for EveryEvent
+    pub fn timestamps(&self, date: i32) -> Option<Vec<i64>> {
+        match self {
+            Schedule::Interval(millis) => Some(session_grid(date, *millis, &SESSIONS)),
+            Schedule::ExchangeCadence => {
+                Some(session_grid(date, EXCHANGE_CADENCE_MILLIS, &SESSIONS))
+            }
+            Schedule::Timestamps(timestamps) => Some(timestamps.clone()),
+            Schedule::EveryEvent => None,
+        }
+    }
+}
+
+/// grid points are aligned to multiples of interval since midnight,
+/// sessions are (start, end) in milliseconds of exchange time of day
+pub fn session_grid(date: i32, interval: i64, sessions: &[(i64, i64)]) -> Vec<i64> {
+    assert!(interval > 0, "snapshot interval must be positive");
+    let mut timestamps = Vec::new();
+    for (start, end) in sessions.iter() {
+        let mut millis = (start + interval - 1) / interval * interval;
+        while millis <= *end {
+            timestamps.push(md::ExchangeTime { date, millis }.to_clock());
+            millis += interval;
+        }
+    }
+    return timestamps;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md::testing::{clock, DATE};
+
+    fn times(timestamps: &[i64]) -> Vec<i64> {
+        return timestamps
+            .iter()
+            .map(|ts| md::ExchangeTime::from_clock(*ts).millis)
+            .collect();
+    }
+
+    #[test]
+    fn grid_is_aligned_and_skips_the_lunch_break() {
+        let grid = times(&session_grid(DATE, 60_000, &SESSIONS));
+        // 09:15 to 11:30 and 13:00 to 15:00, both ends included
+        assert_eq!(grid.len(), 136 + 121);
+        assert_eq!(grid[0], SESSIONS[0].0);
+        assert_eq!(grid[135], SESSIONS[0].1);
+        assert_eq!(grid[136], SESSIONS[1].0);
+        assert_eq!(grid[grid.len() - 1], SESSIONS[1].1);
+
+        // the first multiple of 7 seconds after 09:15:00 is 09:15:06
+        let grid = session_grid(DATE, 7000, &SESSIONS);
+        assert_eq!(grid[0], clock("09:15:06.000"));
+        let grid = times(&grid);
+        assert!(grid.iter().all(|millis| millis % 7000 == 0));
+        assert!(grid.windows(2).all(|pair| pair[0] < pair[1]));
+        let lunch = SESSIONS[0].1 + 1..SESSIONS[1].0;
+        assert!(!grid.iter().any(|millis| lunch.contains(millis)));
+        assert!(grid[grid.len() - 1] <= SESSIONS[1].1);
+    }
+
+    #[test]
+    fn every_event_has_no_timestamps() {
+        assert!(Schedule::EveryEvent.timestamps(DATE).is_none());
+        let cadence = Schedule::ExchangeCadence.timestamps(DATE).unwrap();
+        assert_eq!(
+            cadence,
+            session_grid(DATE, EXCHANGE_CADENCE_MILLIS, &SESSIONS)
+        );
+        let timestamps = vec![clock("10:00:00.000")];
+        assert_eq!(
+            Schedule::Timestamps(timestamps.clone()).timestamps(DATE),
+            Some(timestamps)
+        );
+    }
+}
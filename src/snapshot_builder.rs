@@ -1,17 +1,47 @@
 use crate::md;
+use crate::observer::BookObserver;
+use crate::reference::ReferenceData;
+use crate::schedule::{self, Schedule};
+use serde::{Deserialize, Serialize};
 use std::cmp;
-use std::collections::{HashMap, VecDeque};
-use std::rc::Rc;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::sync::Arc;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 struct Level {
     pub price: i64,
     pub quantity: i64,
+    /// part of quantity not backed by orders we have seen,
+    /// e.g. seeded from an exchange snapshot when the capture starts late
+    pub unattributed: i64,
 }
 
-struct Book {
+// what happened to a book while handling one message,
+// handed to observers once the message is fully applied
+#[derive(Clone)]
+enum BookEvent {
+    OrderAdded(Arc<md::Order>),
+    /// the order and the cancelled quantity
+    OrderCancelled(Arc<md::Order>, i64),
+    OrderExecuted(Arc<md::Trade>),
+    /// side, price and quantity after the change, 0 if the level is removed
+    LevelChanged(md::Side, i64, i64),
+    TopOfBookChanged,
+    /// quantity matched when a call auction is uncrossed
+    AuctionUncross(i64),
+}
+
+/// order book of one instrument rebuilt from its orders and trades
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Book {
     inst_id: i32,
+    /// clock of the latest message applied
     pub timestamp: i64,
+    /// exchange time of the latest message applied
+    pub exchange_time: md::ExchangeTime,
     // this does not include best orders
     bid_levels: VecDeque<Level>,
     bid_best_order_quantity: i64,
@@ -20,42 +50,360 @@ struct Book {
 
     // key: ApplSeqNum of order
     // value: order
-    orders_: HashMap<i64, Rc<md::Order>>,
+    orders_: HashMap<i64, Arc<md::Order>>,
+    // ApplSeqNums of resting limit orders per price, in time priority,
+    // unattributed quantity of a level is ahead of all of them
+    bid_queues: BTreeMap<i64, VecDeque<i64>>,
+    ask_queues: BTreeMap<i64, VecDeque<i64>>,
+    // key: ApplSeqNum of a resting order
+    // value: quantity not yet executed or cancelled
+    remaining_: HashMap<i64, i64>,
+
+    // previous close and price limits if known
+    reference: Option<ReferenceData>,
+
+    // started from an exchange snapshot or referenced orders we have not seen,
+    // such a book is reconciled with every exchange snapshot
+    warm: bool,
+    // cancelled quantity of unknown orders, we can not tell the level
+    unresolved_cancel_quantity: i64,
 
     // some accumulated statics
+    /// shares traded so far
     pub cum_volume: i64,
+    /// sum of price times quantity of the executions so far
     pub cum_amount: i64,
+    /// executions so far
     pub num_trades: i64,
-    pub close: i64,      // latest trade price
-    pub open_price: i64, // first trade price
+    /// latest trade price
+    pub close: i64,
+    /// first trade price
+    pub open_price: i64,
+    /// highest trade price, 0 before the first trade
+    pub high: i64,
+    /// lowest trade price, 0 before the first trade
+    pub low: i64,
+
+    // not yet dispatched to observers
+    #[serde(skip)]
+    events_: Vec<BookEvent>,
 }
 
 impl Book {
+    /// prices are kept as integers, yuan times PRICE_DIVISOR
     pub const PRICE_DIVISOR: f64 = 10000.0;
+    /// opening call auction matches at 09:25:00.000 exchange time
+    pub const AUCTION_END_MILLIS: i64 = schedule::OPENING_MATCH_MILLIS;
+    /// 0.01 yuan, in units of PRICE_DIVISOR
+    pub const TICK_SIZE: i64 = 100;
 
+    /// an empty book without reference data
     pub fn new(inst_id: i32) -> Book {
         Book {
-            inst_id: inst_id,
+            inst_id,
             timestamp: 0,
+            exchange_time: md::ExchangeTime::default(),
             bid_levels: VecDeque::new(),
             bid_best_order_quantity: 0,
             ask_levels: VecDeque::new(),
             ask_best_order_quantity: 0,
             orders_: HashMap::new(),
+            bid_queues: BTreeMap::new(),
+            ask_queues: BTreeMap::new(),
+            remaining_: HashMap::new(),
+            reference: None,
+            warm: false,
+            unresolved_cancel_quantity: 0,
             cum_volume: 0,
             cum_amount: 0,
             num_trades: 0,
             close: 0,
             open_price: 0,
+            high: 0,
+            low: 0,
+            events_: Vec::new(),
+        }
+    }
+
+    /// SecurityID of the instrument
+    pub fn inst_id(&self) -> i32 {
+        self.inst_id
+    }
+
+    /// previous close and price limits if known
+    pub fn reference(&self) -> Option<&ReferenceData> {
+        self.reference.as_ref()
+    }
+
+    /// limit orders outside the price limits are logged from now on
+    pub fn set_reference(&mut self, reference: ReferenceData) {
+        self.reference = Some(reference);
+    }
+
+    /// started from an exchange snapshot or saw orders from before the replay,
+    /// such a book is reconciled with every exchange snapshot
+    pub fn is_warm(&self) -> bool {
+        self.warm
+    }
+
+    /// 0 once everything in the book is explained by orders we have seen
+    pub fn unattributed_quantity(&self) -> i64 {
+        let levels = self.bid_levels.iter().chain(self.ask_levels.iter());
+        return levels.map(|level| level.unattributed).sum::<i64>()
+            + self.unresolved_cancel_quantity;
+    }
+
+    fn levels_mut(&mut self, side: md::Side) -> Option<&mut VecDeque<Level>> {
+        match side {
+            md::Side::Bid => Some(&mut self.bid_levels),
+            md::Side::Ask => Some(&mut self.ask_levels),
+            md::Side::Unknown => None,
+        }
+    }
+
+    /// (price, quantity) of the best bid, best orders are not included
+    pub fn best_bid(&self) -> Option<(i64, i64)> {
+        return self
+            .bid_levels
+            .front()
+            .map(|level| (level.price, level.quantity));
+    }
+
+    /// (price, quantity) of the best ask, best orders are not included
+    pub fn best_ask(&self) -> Option<(i64, i64)> {
+        return self
+            .ask_levels
+            .front()
+            .map(|level| (level.price, level.quantity));
+    }
+
+    /// (price, quantity) from best to worst
+    pub fn depth(&self, side: md::Side) -> impl Iterator<Item = (i64, i64)> + '_ {
+        return self
+            .levels(side)
+            .into_iter()
+            .flatten()
+            .map(|level| (level.price, level.quantity));
+    }
+
+    /// quantity resting at the price, 0 if there is no such level
+    pub fn depth_at(&self, side: md::Side, price: i64) -> i64 {
+        return self
+            .depth(side)
+            .find(|(p, _)| *p == price)
+            .map_or(0, |(_, quantity)| quantity);
+    }
+
+    /// total quantity of the best n levels
+    pub fn cumulative_depth(&self, side: md::Side, n: usize) -> i64 {
+        return self.depth(side).take(n).map(|(_, quantity)| quantity).sum();
+    }
+
+    /// quantity to take from this side of the book so its best price moves
+    /// by ticks, None if the book is not deep enough to tell
+    pub fn volume_to_move(&self, side: md::Side, ticks: i64) -> Option<i64> {
+        let (best, _) = self.depth(side).next()?;
+        let target = match side {
+            md::Side::Bid => best - ticks * Book::TICK_SIZE,
+            _ => best + ticks * Book::TICK_SIZE,
+        };
+        let mut volume = 0;
+        for (price, quantity) in self.depth(side) {
+            let beyond = match side {
+                md::Side::Bid => price <= target,
+                _ => price >= target,
+            };
+            if beyond {
+                return Some(volume);
+            }
+            volume += quantity;
+        }
+        return None;
+    }
+
+    /// orders we have seen at the level with their remaining quantity,
+    /// in time priority, unattributed quantity of a warm book is not included
+    pub fn orders_at(
+        &self,
+        side: md::Side,
+        price: i64,
+    ) -> impl Iterator<Item = (&md::Order, i64)> + '_ {
+        let queue = match side {
+            md::Side::Bid => self.bid_queues.get(&price),
+            md::Side::Ask => self.ask_queues.get(&price),
+            md::Side::Unknown => None,
+        };
+        return queue.into_iter().flatten().map(move |seq| {
+            (
+                self.orders_[seq].as_ref(),
+                self.remaining_.get(seq).copied().unwrap_or(0),
+            )
+        });
+    }
+
+    /// any order of the day by ApplSeqNum, including filled and cancelled ones
+    pub fn order(&self, seq: i64) -> Option<&md::Order> {
+        return self.orders_.get(&seq).map(|order| order.as_ref());
+    }
+
+    /// quantity of the order still resting in the book, 0 once it is gone
+    pub fn remaining_quantity(&self, seq: i64) -> i64 {
+        return self.remaining_.get(&seq).copied().unwrap_or(0);
+    }
+
+    /// previous close in book units, if reference data was given
+    pub fn prev_close(&self) -> Option<i64> {
+        return self.reference.map(|reference| reference.prev_close);
+    }
+
+    /// volume weighted average price of the day in book units, 0 before the first trade
+    pub fn vwap(&self) -> f64 {
+        if self.cum_volume == 0 {
+            return 0.0;
+        }
+        return self.cum_amount as f64 / self.cum_volume as f64;
+    }
+
+    /// quantity resting on every level of the side, best orders are not included
+    pub fn total_quantity(&self, side: md::Side) -> i64 {
+        return self.depth(side).map(|(_, quantity)| quantity).sum();
+    }
+
+    /// average price of every level of the side weighted by quantity,
+    /// in book units, 0 for an empty side
+    pub fn weighted_average_price(&self, side: md::Side) -> f64 {
+        let total = self.total_quantity(side);
+        if total == 0 {
+            return 0.0;
+        }
+        let amount: i64 = self
+            .depth(side)
+            .map(|(price, quantity)| price * quantity)
+            .sum();
+        return amount as f64 / total as f64;
+    }
+
+    fn levels(&self, side: md::Side) -> Option<&VecDeque<Level>> {
+        match side {
+            md::Side::Bid => Some(&self.bid_levels),
+            md::Side::Ask => Some(&self.ask_levels),
+            md::Side::Unknown => None,
+        }
+    }
+
+    fn queues_mut(&mut self, side: md::Side) -> Option<&mut BTreeMap<i64, VecDeque<i64>>> {
+        match side {
+            md::Side::Bid => Some(&mut self.bid_queues),
+            md::Side::Ask => Some(&mut self.ask_queues),
+            md::Side::Unknown => None,
+        }
+    }
+
+    fn enqueue(&mut self, order: &md::Order) {
+        if let Some(queues) = self.queues_mut(order.Side) {
+            queues
+                .entry(order.Price)
+                .or_default()
+                .push_back(order.ApplSeqNum);
+            self.remaining_.insert(order.ApplSeqNum, order.OrderQty);
+        }
+    }
+
+    // an order leaves the queue once nothing remains
+    fn reduce_order(&mut self, order: &md::Order, quantity: i64) {
+        let remaining = match self.remaining_.get_mut(&order.ApplSeqNum) {
+            Some(remaining) => remaining,
+            None => return,
+        };
+        *remaining -= quantity;
+        if *remaining > 0 {
+            return;
+        }
+        self.remaining_.remove(&order.ApplSeqNum);
+        if let Some(queues) = self.queues_mut(order.Side) {
+            if let Some(queue) = queues.get_mut(&order.Price) {
+                queue.retain(|seq| *seq != order.ApplSeqNum);
+                if queue.is_empty() {
+                    queues.remove(&order.Price);
+                }
+            }
+        }
+    }
+
+    // executions at a level fill its orders in time priority
+    fn fill_queue(&mut self, side: md::Side, price: i64, mut quantity: i64) {
+        let queues = match side {
+            md::Side::Bid => &mut self.bid_queues,
+            md::Side::Ask => &mut self.ask_queues,
+            md::Side::Unknown => return,
+        };
+        let queue = match queues.get_mut(&price) {
+            Some(queue) => queue,
+            None => return,
+        };
+        while quantity > 0 {
+            let seq = match queue.front() {
+                Some(seq) => *seq,
+                None => break,
+            };
+            let remaining = self.remaining_.entry(seq).or_insert(0);
+            let filled = cmp::min(*remaining, quantity);
+            *remaining -= filled;
+            quantity -= filled;
+            if *remaining <= 0 {
+                self.remaining_.remove(&seq);
+                queue.pop_front();
+            }
+        }
+        if queue.is_empty() {
+            queues.remove(&price);
+        }
+    }
+
+    // a level that is gone can not have orders left
+    fn drop_queue(&mut self, side: md::Side, price: i64) {
+        let queue = match self
+            .queues_mut(side)
+            .and_then(|queues| queues.remove(&price))
+        {
+            Some(queue) => queue,
+            None => return,
+        };
+        for seq in queue {
+            self.remaining_.remove(&seq);
+        }
+    }
+
+    fn set_unattributed(&mut self, side: md::Side, price: i64, unattributed: i64) {
+        if let Some(levels) = self.levels_mut(side) {
+            if let Some(level) = levels.iter_mut().find(|level| level.price == price) {
+                level.unattributed = cmp::min(unattributed, level.quantity);
+            }
+        }
+    }
+
+    // orders resting before the snapshot are ahead in the queue,
+    // so a trade at the top level consumes their quantity first
+    fn consume_unattributed(&mut self, quantity: i64) {
+        for levels in [&mut self.bid_levels, &mut self.ask_levels] {
+            if let Some(level) = levels.front_mut() {
+                level.unattributed = cmp::max(level.unattributed - quantity, 0);
+            }
         }
     }
 
-    pub fn apply_change(&mut self, side: md::Side, price: i64, quantity: i64) {
+    // adds quantity, negative to take away, at a price level of side
+    pub(crate) fn apply_change(&mut self, side: md::Side, price: i64, quantity: i64) {
         let (levels, aggressive_ordering) = match side {
             md::Side::Bid => (&mut self.bid_levels, cmp::Ordering::Greater),
             md::Side::Ask => (&mut self.ask_levels, cmp::Ordering::Less),
             md::Side::Unknown => {
-                println!("Unknown side is impossible, skip");
+                log::warn!(
+                    target: "book::level",
+                    "inst={} ts={} unknown side is impossible, skip",
+                    self.inst_id,
+                    self.timestamp
+                );
                 return;
             }
         };
@@ -70,42 +418,110 @@ impl Book {
         }
 
         if levels.len() == idx || levels[idx].price != price {
+            if quantity <= 0 && self.warm {
+                // taken from an order we never saw at a level we do not know
+                log::warn!(
+                    target: "book::level",
+                    "inst={} ts={} side={:?} price={} qty={} on unknown level, skip",
+                    self.inst_id,
+                    self.timestamp,
+                    side,
+                    price,
+                    quantity
+                );
+                return;
+            }
             // it's a new level
             let level = Level {
-                price: price,
-                quantity: quantity,
+                price,
+                quantity,
+                unattributed: 0,
             };
-            println!(
-                "At timestamp {}, insert {:?} at {} for {:?} side of instrument {}",
-                self.timestamp, &level, idx, side, self.inst_id
+            log::debug!(
+                target: "book::level",
+                "inst={} ts={} side={:?} idx={} insert price={} qty={}",
+                self.inst_id,
+                self.timestamp,
+                side,
+                idx,
+                level.price,
+                level.quantity
             );
             levels.insert(idx, level);
+            self.events_
+                .push(BookEvent::LevelChanged(side, price, quantity));
+            if idx == 0 {
+                self.events_.push(BookEvent::TopOfBookChanged);
+            }
             return;
         }
 
-        let prev_level = levels[idx].clone();
+        let prev_level = levels[idx];
         // level exists, update it
         levels[idx].quantity += quantity;
-        println!(
-            "At timestamp {}, update level {} from {:?} to {:?} for {:?} side of instrument {}",
-            self.timestamp, idx, prev_level, &levels[idx], side, self.inst_id
+        log::debug!(
+            target: "book::level",
+            "inst={} ts={} side={:?} idx={} update price={} qty={} -> {}",
+            self.inst_id,
+            self.timestamp,
+            side,
+            idx,
+            prev_level.price,
+            prev_level.quantity,
+            levels[idx].quantity
         );
+        let remaining = cmp::max(levels[idx].quantity, 0);
+        levels[idx].unattributed = cmp::min(levels[idx].unattributed, remaining);
         if levels[idx].quantity <= 0 {
             levels.remove(idx);
+            self.drop_queue(side, price);
+        }
+        self.events_
+            .push(BookEvent::LevelChanged(side, price, remaining));
+        if idx == 0 {
+            self.events_.push(BookEvent::TopOfBookChanged);
         }
     }
 
-    pub fn handle_order(&mut self, order: &Rc<md::Order>) {
-        if self.timestamp > order.clockAtArrival {
+    /// timestamp is the event time on the clock the builder replays on
+    pub fn handle_order(&mut self, order: &Arc<md::Order>, timestamp: i64) {
+        if self.timestamp > timestamp {
             // it's possible that multiple message comes in 1 packet, do not use >=
             return;
         }
 
-        self.timestamp = order.clockAtArrival;
-        self.orders_.insert(order.ApplSeqNum, Rc::clone(order));
+        self.timestamp = timestamp;
+        self.exchange_time = order.exchange_time();
+        self.orders_.insert(order.ApplSeqNum, Arc::clone(order));
+        self.events_.push(BookEvent::OrderAdded(Arc::clone(order)));
+
+        if self.crossed() {
+            // the first order after a call auction is matched may find the book crossed
+            let uncrossed_quantity = self.handle_cross();
+            self.events_
+                .push(BookEvent::AuctionUncross(uncrossed_quantity));
+        }
+
+        if let Some(reference) = &self.reference {
+            if order.OrderType == md::OrderType::LimitOrder && !reference.within_limits(order.Price)
+            {
+                // the exchange rejects these, so the limits are likely wrong
+                log::warn!(
+                    target: "book::order",
+                    "inst={} ts={} seq={} price={} outside limits {}..{}",
+                    self.inst_id,
+                    timestamp,
+                    order.ApplSeqNum,
+                    order.Price,
+                    reference.lower_limit,
+                    reference.upper_limit
+                );
+            }
+        }
 
         match order.OrderType {
             md::OrderType::LimitOrder => {
+                self.enqueue(order);
                 self.apply_change(order.Side, order.Price, order.OrderQty);
                 if self.crossed() {
                     self.handle_cross();
@@ -117,18 +533,24 @@ impl Book {
                     md::Side::Ask => self.ask_best_order_quantity += order.OrderQty,
                     md::Side::Unknown => {}
                 }
-                println!(
-                    "snapshot when receive best order at {}: {:?}",
-                    order.clockAtArrival,
+                log::debug!(
+                    target: "book::order",
+                    "inst={} ts={} best order side={:?} qty={} snapshot={:?}",
+                    self.inst_id,
+                    timestamp,
+                    order.Side,
+                    order.OrderQty,
                     self.to_snapshot()
                 );
             }
             md::OrderType::MarketOrder => {
                 assert!(!self.crossed());
+                self.enqueue(order);
                 self.apply_change(order.Side, order.Price, order.OrderQty);
                 let traded_quantity = self.handle_cross();
                 // remove the remaining quantity
                 if traded_quantity < order.OrderQty {
+                    self.reduce_order(order, order.OrderQty - traded_quantity);
                     self.apply_change(order.Side, order.Price, traded_quantity - order.OrderQty);
                 }
             }
@@ -137,8 +559,8 @@ impl Book {
     }
 
     fn crossed(&self) -> bool {
-        // last order timestamp before 09:25
-        if self.timestamp <= 1587605144280888 {
+        // no matching until a call auction is matched
+        if !schedule::is_matching(self.exchange_time.millis) {
             return false;
         }
 
@@ -152,12 +574,24 @@ impl Book {
     fn handle_cross(&mut self) -> i64 {
         let mut total_traded = 0;
         while self.crossed() {
-            println!(
-                "handle simulated trade for {}, top bid = {:?}, top ask = {:?}",
-                &self.inst_id, &self.bid_levels[0], &self.ask_levels[0]
+            log::debug!(
+                target: "book::cross",
+                "inst={} ts={} simulated trade bid={}x{} ask={}x{}",
+                self.inst_id,
+                self.timestamp,
+                self.bid_levels[0].price,
+                self.bid_levels[0].quantity,
+                self.ask_levels[0].price,
+                self.ask_levels[0].quantity
             );
 
             let cross_quantity = cmp::min(self.bid_levels[0].quantity, self.ask_levels[0].quantity);
+            for side in [md::Side::Bid, md::Side::Ask] {
+                let level = self.levels(side).unwrap()[0];
+                let unattributed = cmp::min(level.unattributed, cross_quantity);
+                self.fill_queue(side, level.price, cross_quantity - unattributed);
+            }
+            self.consume_unattributed(cross_quantity);
             // note that the price is not trade price
             // it only means to remove from level 0
             self.apply_change(md::Side::Bid, self.bid_levels[0].price, -cross_quantity);
@@ -168,23 +602,35 @@ impl Book {
         return total_traded;
     }
 
-    pub fn handle_trade(&mut self, trade: &md::Trade) {
-        if self.timestamp > trade.clockAtArrival {
+    /// an execution or a cancel, timestamp as for handle_order
+    pub fn handle_trade(&mut self, trade: &Arc<md::Trade>, timestamp: i64) {
+        if self.timestamp > timestamp {
             // it's possible that multiple message comes in 1 packet, do not use >=
             return;
         }
-        self.timestamp = trade.clockAtArrival;
+        self.timestamp = timestamp;
+        self.exchange_time = trade.exchange_time();
 
         match trade.ExecType {
             md::ExecuteType::Traded => {
+                if self.crossed() {
+                    // the first execution of a call auction match, the book only
+                    // changes through crosses so the auction is matched here
+                    let uncrossed_quantity = self.handle_cross();
+                    self.events_
+                        .push(BookEvent::AuctionUncross(uncrossed_quantity));
+                }
                 self.num_trades += 1;
                 self.cum_volume += trade.TradeQty;
                 self.cum_amount += trade.TradeQty * trade.TradePrice;
                 self.close = trade.TradePrice;
+                self.update_high_low(trade.TradePrice);
                 if self.open_price == 0 {
                     // only update once in a day
                     self.open_price = trade.TradePrice;
                 }
+                self.events_
+                    .push(BookEvent::OrderExecuted(Arc::clone(trade)));
 
                 // the trade is simulated in cross event
                 // we don't need to change the order book again
@@ -202,14 +648,42 @@ impl Book {
             }
             md::ExecuteType::Cancelled => {
                 // we can only query the price
-                let order = if trade.BidApplSeqNum != 0 {
-                    &self.orders_[&trade.BidApplSeqNum]
+                let seq = if trade.BidApplSeqNum != 0 {
+                    trade.BidApplSeqNum
                 } else {
-                    &self.orders_[&trade.OfferApplSeqNum]
+                    trade.OfferApplSeqNum
                 };
+                let order = match self.orders_.get(&seq) {
+                    Some(order) => Arc::clone(order),
+                    None => {
+                        // submitted before our capture started
+                        log::warn!(
+                            target: "book::order",
+                            "inst={} ts={} cancel of unknown order seq={} qty={}",
+                            self.inst_id,
+                            self.timestamp,
+                            seq,
+                            trade.TradeQty
+                        );
+                        self.warm = true;
+                        self.unresolved_cancel_quantity += trade.TradeQty;
+                        return;
+                    }
+                };
+                self.events_.push(BookEvent::OrderCancelled(
+                    Arc::clone(&order),
+                    trade.TradeQty,
+                ));
 
                 match order.OrderType {
                     md::OrderType::LimitOrder => {
+                        // more than we thought was left, so our guess of which
+                        // orders were filled was wrong, take it from the others
+                        let excess = trade.TradeQty - self.remaining_quantity(order.ApplSeqNum);
+                        self.reduce_order(&order, trade.TradeQty);
+                        if excess > 0 {
+                            self.fill_queue(order.Side, order.Price, excess);
+                        }
                         self.apply_change(order.Side, order.Price, -trade.TradeQty);
                     }
                     md::OrderType::BestOrder => match order.Side {
@@ -225,221 +699,1199 @@ impl Book {
 
             md::ExecuteType::Unknown => {}
         }
+    }
 
-        // Debug
-        if self.num_trades == 4277 {
-            println!(
-                "snapshot when 2385 has {} trade: {:?}",
-                self.num_trades,
-                self.to_snapshot()
-            );
+    fn update_high_low(&mut self, price: i64) {
+        if price <= 0 {
+            return;
+        }
+        self.high = cmp::max(self.high, price);
+        self.low = if self.low == 0 {
+            price
+        } else {
+            cmp::min(self.low, price)
+        };
+    }
+
+    fn to_price(price: f64) -> i64 {
+        (price * Book::PRICE_DIVISOR).round() as i64
+    }
+
+    /// start from an exchange snapshot, none of its quantity belongs to orders we know
+    pub fn seed(&mut self, snapshot: &md::Snapshot, timestamp: i64) {
+        self.timestamp = timestamp;
+        if let Ok(exchange_time) = snapshot.exchange_time() {
+            self.exchange_time = exchange_time;
+        }
+        self.warm = true;
+        self.cum_volume = snapshot.cum_volume;
+        self.cum_amount = Book::to_price(snapshot.cum_amount);
+        self.num_trades = snapshot.numTrades;
+        self.close = Book::to_price(snapshot.close);
+        self.open_price = Book::to_price(snapshot.openPrice);
+        // mdLog has no high and low, the open and close are all we know of the range
+        self.update_high_low(self.open_price);
+        self.update_high_low(self.close);
+        for (side, levels) in [
+            (md::Side::Bid, snapshot.bids()),
+            (md::Side::Ask, snapshot.asks()),
+        ] {
+            for (price, quantity) in levels.iter() {
+                if *quantity <= 0 {
+                    continue;
+                }
+                let price = Book::to_price(*price);
+                self.apply_change(side, price, *quantity);
+                self.set_unattributed(side, price, *quantity);
+            }
+        }
+    }
+
+    /// make the levels covered by an exchange snapshot agree with it, only the
+    /// unattributed part is adjusted so what we know from orders is never lost
+    pub fn reconcile(&mut self, snapshot: &md::Snapshot, timestamp: i64) {
+        self.timestamp = cmp::max(self.timestamp, timestamp);
+        if let Ok(exchange_time) = snapshot.exchange_time() {
+            self.exchange_time = cmp::max(self.exchange_time, exchange_time);
+        }
+        for (side, expected) in [
+            (md::Side::Bid, snapshot.bids()),
+            (md::Side::Ask, snapshot.asks()),
+        ] {
+            let expected: Vec<(i64, i64)> = expected
+                .iter()
+                .filter(|(_, quantity)| *quantity > 0)
+                .map(|(price, quantity)| (Book::to_price(*price), *quantity))
+                .collect();
+            self.reconcile_side(side, &expected);
+        }
+
+        // the snapshot has seen every trade, including those before our capture
+        self.unresolved_cancel_quantity = 0;
+        self.cum_volume = snapshot.cum_volume;
+        self.cum_amount = Book::to_price(snapshot.cum_amount);
+        self.num_trades = snapshot.numTrades;
+        self.close = Book::to_price(snapshot.close);
+        self.open_price = Book::to_price(snapshot.openPrice);
+        // mdLog has no high and low, the open and close are all we know of the range
+        self.update_high_low(self.open_price);
+        self.update_high_low(self.close);
+    }
+
+    fn reconcile_side(&mut self, side: md::Side, expected: &[(i64, i64)]) {
+        let worst = match expected.last() {
+            Some((price, _)) => *price,
+            None => return,
+        };
+        let covered = |price: i64| match side {
+            md::Side::Bid => price >= worst,
+            _ => price <= worst,
+        };
+
+        let current: Vec<Level> = match self.levels_mut(side) {
+            Some(levels) => levels.iter().copied().collect(),
+            None => return,
+        };
+        // levels the exchange does not have can only lose what we can not explain
+        for level in current.iter() {
+            if covered(level.price)
+                && level.unattributed > 0
+                && !expected.iter().any(|(price, _)| *price == level.price)
+            {
+                self.apply_change(side, level.price, -level.unattributed);
+                self.set_unattributed(side, level.price, 0);
+            }
+        }
+        for (price, quantity) in expected.iter() {
+            let level = current.iter().find(|level| level.price == *price);
+            let (known, attributed) = match level {
+                Some(level) => (level.quantity, level.quantity - level.unattributed),
+                None => (0, 0),
+            };
+            let unattributed = cmp::max(quantity - attributed, 0);
+            let delta = attributed + unattributed - known;
+            if delta != 0 {
+                self.apply_change(side, *price, delta);
+            }
+            self.set_unattributed(side, *price, unattributed);
         }
     }
 
+    // empty level when the book is shallower than a snapshot
+    fn level_at(levels: &VecDeque<Level>, idx: usize) -> Level {
+        levels.get(idx).copied().unwrap_or(Level {
+            price: 0,
+            quantity: 0,
+            unattributed: 0,
+        })
+    }
+
+    /// the book in the mdLog format
     pub fn to_snapshot(&self) -> md::Snapshot {
+        let bids: Vec<Level> = (0..5)
+            .map(|i| Book::level_at(&self.bid_levels, i))
+            .collect();
+        let asks: Vec<Level> = (0..5)
+            .map(|i| Book::level_at(&self.ask_levels, i))
+            .collect();
         md::Snapshot {
-            ms: "08:24:47.847788",
+            ms: md::clock_to_string(self.timestamp),
             clock: self.timestamp,
             threadId: 23994,
             clockAtArrival: self.timestamp,
             sequenceNo: -1,
             source: 24,
             StockID: self.inst_id,
-            exchange: "SZ",
-            time: "08:24:03.000",
+            exchange: "SZ".to_string(),
+            time: self.exchange_time.to_string(),
             cum_volume: self.cum_volume,
             cum_amount: self.cum_amount as f64 / Book::PRICE_DIVISOR,
             close: self.close as f64 / Book::PRICE_DIVISOR,
             __origTickSeq: -1,
-            bid1p: self.bid_levels[0].price as f64 / Book::PRICE_DIVISOR,
-            bid2p: self.bid_levels[1].price as f64 / Book::PRICE_DIVISOR,
-            bid3p: self.bid_levels[2].price as f64 / Book::PRICE_DIVISOR,
-            bid4p: self.bid_levels[3].price as f64 / Book::PRICE_DIVISOR,
-            bid5p: self.bid_levels[4].price as f64 / Book::PRICE_DIVISOR,
-            bid1q: self.bid_levels[0].quantity,
-            bid2q: self.bid_levels[1].quantity,
-            bid3q: self.bid_levels[2].quantity,
-            bid4q: self.bid_levels[3].quantity,
-            bid5q: self.bid_levels[4].quantity,
-            ask1p: self.ask_levels[0].price as f64 / Book::PRICE_DIVISOR,
-            ask2p: self.ask_levels[1].price as f64 / Book::PRICE_DIVISOR,
-            ask3p: self.ask_levels[2].price as f64 / Book::PRICE_DIVISOR,
-            ask4p: self.ask_levels[3].price as f64 / Book::PRICE_DIVISOR,
-            ask5p: self.ask_levels[4].price as f64 / Book::PRICE_DIVISOR,
-            ask1q: self.ask_levels[0].quantity,
-            ask2q: self.ask_levels[1].quantity,
-            ask3q: self.ask_levels[2].quantity,
-            ask4q: self.ask_levels[3].quantity,
-            ask5q: self.ask_levels[4].quantity,
+            bid1p: bids[0].price as f64 / Book::PRICE_DIVISOR,
+            bid2p: bids[1].price as f64 / Book::PRICE_DIVISOR,
+            bid3p: bids[2].price as f64 / Book::PRICE_DIVISOR,
+            bid4p: bids[3].price as f64 / Book::PRICE_DIVISOR,
+            bid5p: bids[4].price as f64 / Book::PRICE_DIVISOR,
+            bid1q: bids[0].quantity,
+            bid2q: bids[1].quantity,
+            bid3q: bids[2].quantity,
+            bid4q: bids[3].quantity,
+            bid5q: bids[4].quantity,
+            ask1p: asks[0].price as f64 / Book::PRICE_DIVISOR,
+            ask2p: asks[1].price as f64 / Book::PRICE_DIVISOR,
+            ask3p: asks[2].price as f64 / Book::PRICE_DIVISOR,
+            ask4p: asks[3].price as f64 / Book::PRICE_DIVISOR,
+            ask5p: asks[4].price as f64 / Book::PRICE_DIVISOR,
+            ask1q: asks[0].quantity,
+            ask2q: asks[1].quantity,
+            ask3q: asks[2].quantity,
+            ask4q: asks[3].quantity,
+            ask5q: asks[4].quantity,
             openPrice: self.open_price as f64 / Book::PRICE_DIVISOR,
             numTrades: self.num_trades,
         }
     }
+
+    /// the snapshot with the statistics of an exchange Level-2 snapshot
+    pub fn to_extended_snapshot(&self) -> md::ExtendedSnapshot {
+        md::ExtendedSnapshot {
+            snapshot: self.to_snapshot(),
+            highPrice: self.high as f64 / Book::PRICE_DIVISOR,
+            lowPrice: self.low as f64 / Book::PRICE_DIVISOR,
+            vwap: self.vwap() / Book::PRICE_DIVISOR,
+            prevClose: self.prev_close().unwrap_or(0) as f64 / Book::PRICE_DIVISOR,
+            totalBidQty: self.total_quantity(md::Side::Bid),
+            totalAskQty: self.total_quantity(md::Side::Ask),
+            avgBidPrice: self.weighted_average_price(md::Side::Bid) / Book::PRICE_DIVISOR,
+            avgAskPrice: self.weighted_average_price(md::Side::Ask) / Book::PRICE_DIVISOR,
+        }
+    }
+}
+
+/// which clock orders events and snapshot timestamps
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClockType {
+    /// clockAtArrival, depends on our capture host
+    Arrival,
+    /// TransactTime, converted to the same unit as clockAtArrival
+    Exchange,
+}
+
+impl ClockType {
+    // time of each kind of event on this clock
+    pub(crate) fn order_time(self, order: &md::Order) -> i64 {
+        match self {
+            ClockType::Arrival => order.clockAtArrival,
+            ClockType::Exchange => order.exchange_time().to_clock(),
+        }
+    }
+
+    pub(crate) fn trade_time(self, trade: &md::Trade) -> i64 {
+        match self {
+            ClockType::Arrival => trade.clockAtArrival,
+            ClockType::Exchange => trade.exchange_time().to_clock(),
+        }
+    }
+
+    pub(crate) fn snapshot_time(self, snapshot: &md::Snapshot) -> i64 {
+        match self {
+            ClockType::Arrival => snapshot.clockAtArrival,
+            ClockType::Exchange => match snapshot.exchange_time() {
+                Ok(time) => time.to_clock(),
+                Err(_) => snapshot.clockAtArrival,
+            },
+        }
+    }
+}
+
+// everything needed to resume a replay over the same input files,
+// observers keep their own state and are not included
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    clock_type: ClockType,
+    num_orders: usize,
+    num_trades: usize,
+    num_snapshots: usize,
+    order_idx: usize,
+    trade_idx: usize,
+    snapshot_idx: usize,
+    last_time: i64,
+    books: &'a BTreeMap<i32, Book>,
+}
+
+#[derive(Deserialize)]
+struct Checkpoint {
+    clock_type: ClockType,
+    num_orders: usize,
+    num_trades: usize,
+    num_snapshots: usize,
+    order_idx: usize,
+    trade_idx: usize,
+    snapshot_idx: usize,
+    last_time: i64,
+    books: BTreeMap<i32, Book>,
+}
+
+// in-memory copy of the builder taken while replaying forward,
+// every event before time is processed and none after
+struct RewindPoint {
+    time: i64,
+    order_idx: usize,
+    trade_idx: usize,
+    snapshot_idx: usize,
+    books: BTreeMap<i32, Book>,
 }
 
+#[derive(Debug, Copy, Clone)]
+enum Stream {
+    Order,
+    Trade,
+    Snapshot,
+}
+
+// builders are moved to worker threads and async tasks
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<SnapshotBuilder>();
+};
+
+/// replays orders and trades of a day in time order, keeping a book per instrument
 pub struct SnapshotBuilder {
-    orders_: Vec<Rc<md::Order>>,
-    trades_: Vec<Rc<md::Trade>>,
+    orders_: Vec<Arc<md::Order>>,
+    trades_: Vec<Arc<md::Trade>>,
+    // exchange snapshots for warm start
+    snapshots_: Vec<Arc<md::Snapshot>>,
+    // applied to every book when it is created
+    reference_: HashMap<i32, ReferenceData>,
+    clock_type_: ClockType,
 
-    // key: stock id
-    books_: HashMap<i32, Book>,
+    // key: stock id, ordered so that snapshots come out in the same order every run
+    books_: BTreeMap<i32, Book>,
+
+    observers_: Vec<Box<dyn BookObserver + Send>>,
 
     // current status
     order_idx_: usize,
     trade_idx_: usize,
+    snapshot_idx_: usize,
+    // time of the last processed event
+    last_time_: i64,
+
+    // sorted by time, 0 interval disables them
+    rewind_points_: Vec<RewindPoint>,
+    rewind_interval_: i64,
+    // where reset goes back to: the start of the day with the books from init,
+    // or a restored checkpoint
+    start_: RewindPoint,
 }
 
 impl SnapshotBuilder {
-    pub fn new(orders: Vec<Rc<md::Order>>, trades: Vec<Rc<md::Trade>>) -> SnapshotBuilder {
+    /// replays orders and trades on the arrival clock
+    pub fn new(orders: Vec<Arc<md::Order>>, trades: Vec<Arc<md::Trade>>) -> SnapshotBuilder {
         SnapshotBuilder {
             orders_: orders,
             trades_: trades,
-            books_: HashMap::new(),
+            snapshots_: Vec::new(),
+            reference_: HashMap::new(),
+            clock_type_: ClockType::Arrival,
+            books_: BTreeMap::new(),
+            observers_: Vec::new(),
 
             order_idx_: 0,
             trade_idx_: 0,
+            snapshot_idx_: 0,
+            last_time_: i64::MIN,
+
+            rewind_points_: Vec::new(),
+            rewind_interval_: 0,
+            start_: RewindPoint {
+                time: i64::MIN,
+                order_idx: 0,
+                trade_idx: 0,
+                snapshot_idx: 0,
+                books: BTreeMap::new(),
+            },
         }
     }
 
-    fn process_order(&mut self) {
-        let order = &self.orders_[self.order_idx_];
+    /// observer sees every change of every book from now on
+    pub fn add_observer(&mut self, observer: Box<dyn BookObserver + Send>) {
+        self.observers_.push(observer);
+    }
 
-        if !self.books_.contains_key(&order.SecurityID) {
-            self.books_
-                .insert(order.SecurityID, Book::new(order.SecurityID));
+    /// warm start when our capture starts late: an instrument first seen in an
+    /// exchange snapshot is seeded from it, and books that are not exact are
+    /// reconciled with every later snapshot until the flow explains them,
+    /// replay on the exchange clock: on ours a snapshot is reconciled after
+    /// flow that came after its exchange time
+    pub fn set_exchange_snapshots(&mut self, snapshots: Vec<Arc<md::Snapshot>>) {
+        self.snapshots_ = snapshots;
+        self.snapshot_idx_ = 0;
+        self.rewind_points_.clear();
+        self.sort_streams();
+    }
+
+    /// previous close and price limits per instrument, e.g. carried over from
+    /// the previous day, books that already exist are updated too
+    pub fn set_reference_data(&mut self, reference: HashMap<i32, ReferenceData>) {
+        let books = self.books_.iter_mut().chain(self.start_.books.iter_mut());
+        for (inst_id, book) in books {
+            if let Some(data) = reference.get(inst_id) {
+                book.set_reference(*data);
+            }
         }
+        self.reference_ = reference;
+        // books of earlier points have the old limits
+        self.rewind_points_.clear();
+    }
 
-        let book = self.books_.get_mut(&order.SecurityID).unwrap();
-        book.handle_order(order);
+    fn new_book(reference: &HashMap<i32, ReferenceData>, inst_id: i32) -> Book {
+        let mut book = Book::new(inst_id);
+        if let Some(data) = reference.get(&inst_id) {
+            book.set_reference(*data);
+        }
+        return book;
+    }
+
+    /// timestamps given to build_snapshot are on this clock,
+    /// set it before the replay starts since the streams are reordered
+    pub fn set_clock_type(&mut self, clock_type: ClockType) {
+        if clock_type != self.clock_type_ {
+            // positions in the streams mean something else on another clock
+            self.rewind_points_.clear();
+            self.clock_type_ = clock_type;
+            self.sort_streams();
+        }
+    }
+
+    // next_event only compares the heads of the streams, so each one has to be
+    // in the order of the replay clock, ties keep their order in the input
+    fn sort_streams(&mut self) {
+        let clock_type = self.clock_type_;
+        self.orders_
+            .sort_by_cached_key(|order| clock_type.order_time(order));
+        self.trades_
+            .sort_by_cached_key(|trade| clock_type.trade_time(trade));
+        self.snapshots_
+            .sort_by_cached_key(|snapshot| clock_type.snapshot_time(snapshot));
+    }
+
+    /// how often rewind points are taken, in microseconds of the replay clock,
+    /// each one is a copy of all books so memory grows with the day,
+    /// none are taken unless this is set, e.g. to a minute for interactive use
+    pub fn set_rewind_interval(&mut self, interval: i64) {
+        self.rewind_interval_ = interval;
+    }
+
+    fn order_time(&self, idx: usize) -> i64 {
+        return self.clock_type_.order_time(&self.orders_[idx]);
+    }
+
+    fn trade_time(&self, idx: usize) -> i64 {
+        return self.clock_type_.trade_time(&self.trades_[idx]);
+    }
+
+    fn process_order(&mut self) -> i32 {
+        let timestamp = self.order_time(self.order_idx_);
+        let order = &self.orders_[self.order_idx_];
+
+        let reference = &self.reference_;
+        let book = self
+            .books_
+            .entry(order.SecurityID)
+            .or_insert_with(|| SnapshotBuilder::new_book(reference, order.SecurityID));
+        for observer in self.observers_.iter_mut() {
+            observer.on_before_order(book, order);
+        }
+        book.handle_order(order, timestamp);
 
         self.order_idx_ += 1;
+        let inst_id = order.SecurityID;
+        self.notify(inst_id);
+        return inst_id;
     }
 
-    fn process_trade(&mut self) {
+    fn process_trade(&mut self) -> i32 {
+        let timestamp = self.trade_time(self.trade_idx_);
         let trade = &self.trades_[self.trade_idx_];
-        // no trade can happen without a book
-        // it's a safe assumption that process order shall already create a book
-        let book = self.books_.get_mut(&trade.SecurityID).unwrap();
-        book.handle_trade(trade);
+        // without a warm start a trade can not come before the first order,
+        // with one the book may not be seeded yet
+        let reference = &self.reference_;
+        let book = self
+            .books_
+            .entry(trade.SecurityID)
+            .or_insert_with(|| SnapshotBuilder::new_book(reference, trade.SecurityID));
+        book.handle_trade(trade, timestamp);
 
         self.trade_idx_ += 1;
+        let inst_id = trade.SecurityID;
+        self.notify(inst_id);
+        return inst_id;
     }
 
-    // process event until timestamp
-    pub fn process_until(&mut self, timestamp: i64) {
-        while (self.order_idx_ < self.orders_.len()
-            && self.orders_[self.order_idx_].clockAtArrival < timestamp)
-            && (self.trade_idx_ < self.trades_.len()
-                && self.trades_[self.trade_idx_].clockAtArrival < timestamp)
-        {
-            let order = &self.orders_[self.order_idx_];
-            let trade = &self.trades_[self.trade_idx_];
-
-            if order.clockAtArrival <= trade.clockAtArrival {
-                // can not be '<' because we need always process orders first
-                self.process_order();
-            } else {
-                self.process_trade()
+    // None when there is no book to seed, e.g. an empty snapshot before the open
+    fn process_snapshot(&mut self) -> Option<i32> {
+        let timestamp = self.snapshot_time(self.snapshot_idx_);
+        let snapshot = &self.snapshots_[self.snapshot_idx_];
+        let inst_id = snapshot.StockID;
+
+        let empty = snapshot.bids().iter().all(|(_, quantity)| *quantity == 0)
+            && snapshot.asks().iter().all(|(_, quantity)| *quantity == 0);
+        match self.books_.get_mut(&inst_id) {
+            Some(book) if book.is_warm() => book.reconcile(snapshot, timestamp),
+            // only trades of orders we never saw so far
+            Some(book) if book.orders_.is_empty() && !empty => book.seed(snapshot, timestamp),
+            Some(_) => {}
+            // before the open there is nothing to start from
+            None if !empty => {
+                let mut book = SnapshotBuilder::new_book(&self.reference_, inst_id);
+                book.seed(snapshot, timestamp);
+                self.books_.insert(inst_id, book);
             }
+            None => {}
         }
-        // no more orders
-        while self.trade_idx_ < self.trades_.len()
-            && self.trades_[self.trade_idx_].clockAtArrival < timestamp
-        {
-            self.process_trade();
+
+        self.snapshot_idx_ += 1;
+        if !self.books_.contains_key(&inst_id) {
+            return None;
         }
+        self.notify(inst_id);
+        return Some(inst_id);
+    }
 
-        // no more trades
-        while self.order_idx_ < self.orders_.len()
-            && self.orders_[self.order_idx_].clockAtArrival < timestamp
-        {
-            self.process_order();
+    // hand events of the last message to observers
+    fn notify(&mut self, inst_id: i32) {
+        let events = match self.books_.get_mut(&inst_id) {
+            Some(book) => std::mem::take(&mut book.events_),
+            None => return,
+        };
+        if self.observers_.is_empty() {
+            return;
+        }
+
+        let book = &self.books_[&inst_id];
+        let mut top_changed = false;
+        for event in events.iter() {
+            for observer in self.observers_.iter_mut() {
+                match event {
+                    BookEvent::OrderAdded(order) => observer.on_order_added(book, order),
+                    BookEvent::OrderCancelled(order, quantity) => {
+                        observer.on_order_cancelled(book, order, *quantity)
+                    }
+                    BookEvent::OrderExecuted(trade) => observer.on_order_executed(book, trade),
+                    BookEvent::LevelChanged(side, price, quantity) => {
+                        observer.on_level_changed(book, *side, *price, *quantity)
+                    }
+                    BookEvent::TopOfBookChanged => top_changed = true,
+                    BookEvent::AuctionUncross(quantity) => {
+                        observer.on_auction_uncross(book, *quantity)
+                    }
+                }
+            }
+        }
+        if top_changed {
+            for observer in self.observers_.iter_mut() {
+                observer.on_top_of_book_changed(book);
+            }
+        }
+    }
+
+    fn snapshot_time(&self, idx: usize) -> i64 {
+        return self.clock_type_.snapshot_time(&self.snapshots_[idx]);
+    }
+
+    // the stream holding the next event and its time,
+    // on ties orders go first, then trades, then exchange snapshots
+    fn next_event(&self) -> Option<(Stream, i64)> {
+        let mut next: Option<(Stream, i64)> = None;
+        let mut consider = |stream: Stream, time: i64| {
+            if next.map_or(true, |(_, t)| time < t) {
+                next = Some((stream, time));
+            }
+        };
+        if self.order_idx_ < self.orders_.len() {
+            consider(Stream::Order, self.order_time(self.order_idx_));
+        }
+        if self.trade_idx_ < self.trades_.len() {
+            consider(Stream::Trade, self.trade_time(self.trade_idx_));
+        }
+        if self.snapshot_idx_ < self.snapshots_.len() {
+            consider(Stream::Snapshot, self.snapshot_time(self.snapshot_idx_));
+        }
+        return next;
+    }
+
+    // time of the next event to process, if any
+    fn next_time(&self) -> Option<i64> {
+        return self.next_event().map(|(_, time)| time);
+    }
+
+    // process the next event, None once every event is processed,
+    // otherwise the instrument of the book it changed if there is one
+    fn process_next(&mut self) -> Option<Option<i32>> {
+        let (stream, time) = self.next_event()?;
+        self.take_rewind_point(time);
+        self.last_time_ = cmp::max(self.last_time_, time);
+
+        return Some(match stream {
+            Stream::Order => Some(self.process_order()),
+            Stream::Trade => Some(self.process_trade()),
+            Stream::Snapshot => self.process_snapshot(),
+        });
+    }
+
+    fn take_rewind_point(&mut self, time: i64) {
+        if self.rewind_interval_ <= 0 || time <= self.last_time_ {
+            // ties with processed events would make the point ambiguous
+            return;
+        }
+        if let Some(last) = self.rewind_points_.last() {
+            if time < last.time + self.rewind_interval_ {
+                return;
+            }
+        }
+        self.rewind_points_.push(RewindPoint {
+            time,
+            order_idx: self.order_idx_,
+            trade_idx: self.trade_idx_,
+            snapshot_idx: self.snapshot_idx_,
+            books: self.books_.clone(),
+        });
+    }
+
+    /// clock of the next event, None once every event is processed
+    pub fn next_event_time(&self) -> Option<i64> {
+        return self.next_time();
+    }
+
+    /// process one event, for callers that drive the replay themselves,
+    /// None once every event is processed, otherwise the instrument of the
+    /// book it changed, None for an exchange snapshot that left no book
+    pub fn step(&mut self) -> Option<Option<i32>> {
+        return self.process_next();
+    }
+
+    /// process event until timestamp
+    pub fn process_until(&mut self, timestamp: i64) {
+        while let Some(next) = self.next_time() {
+            if next >= timestamp {
+                break;
+            }
+            self.process_next();
         }
     }
 
-    // start from these snapshots
-    pub fn init(&mut self, snapshots: &Vec<md::Snapshot>) {
+    /// start from these snapshots
+    pub fn init(&mut self, snapshots: &[md::Snapshot]) {
         for snapshot in snapshots {
-            self.books_
-                .insert(snapshot.StockID, Book::new(snapshot.StockID));
-            let book = &mut self.books_.get_mut(&snapshot.StockID).unwrap();
-            book.timestamp = snapshot.clockAtArrival;
-            book.cum_volume = snapshot.cum_volume;
-            book.cum_amount = (snapshot.cum_amount * Book::PRICE_DIVISOR) as i64;
-            book.num_trades = snapshot.numTrades;
-            book.close = (snapshot.close * Book::PRICE_DIVISOR) as i64;
-            book.open_price = (snapshot.openPrice * Book::PRICE_DIVISOR) as i64;
-            // for bids
-            book.apply_change(
-                md::Side::Bid,
-                (snapshot.bid1p * Book::PRICE_DIVISOR) as i64,
-                snapshot.bid1q,
-            );
-            book.apply_change(
-                md::Side::Bid,
-                (snapshot.bid2p * Book::PRICE_DIVISOR) as i64,
-                snapshot.bid2q,
-            );
-            book.apply_change(
-                md::Side::Bid,
-                (snapshot.bid3p * Book::PRICE_DIVISOR) as i64,
-                snapshot.bid3q,
-            );
-            book.apply_change(
-                md::Side::Bid,
-                (snapshot.bid4p * Book::PRICE_DIVISOR) as i64,
-                snapshot.bid4q,
-            );
-            book.apply_change(
-                md::Side::Bid,
-                (snapshot.bid5p * Book::PRICE_DIVISOR) as i64,
-                snapshot.bid5q,
-            );
-            // for asks
-            book.apply_change(
-                md::Side::Ask,
-                (snapshot.ask1p * Book::PRICE_DIVISOR) as i64,
-                snapshot.ask1q,
-            );
-            book.apply_change(
-                md::Side::Ask,
-                (snapshot.ask2p * Book::PRICE_DIVISOR) as i64,
-                snapshot.ask2q,
-            );
-            book.apply_change(
-                md::Side::Ask,
-                (snapshot.ask3p * Book::PRICE_DIVISOR) as i64,
-                snapshot.ask3q,
-            );
-            book.apply_change(
-                md::Side::Ask,
-                (snapshot.ask4p * Book::PRICE_DIVISOR) as i64,
-                snapshot.ask4q,
-            );
-            book.apply_change(
-                md::Side::Ask,
-                (snapshot.ask5p * Book::PRICE_DIVISOR) as i64,
-                snapshot.ask5q,
-            );
+            let mut book = SnapshotBuilder::new_book(&self.reference_, snapshot.StockID);
+            book.seed(snapshot, snapshot.clockAtArrival);
+            self.books_.insert(snapshot.StockID, book);
         }
+        self.set_start();
+    }
+
+    // reset and rewinding go back to the current state from now on
+    fn set_start(&mut self) {
+        self.rewind_points_.clear();
+        self.start_ = RewindPoint {
+            time: self.last_time_,
+            order_idx: self.order_idx_,
+            trade_idx: self.trade_idx_,
+            snapshot_idx: self.snapshot_idx_,
+            books: self.books_.clone(),
+        };
     }
 
-    pub fn build_snapshot(&mut self, timestamps: &Vec<i64>) -> Vec<md::Snapshot> {
-        let mut snapshots = Vec::with_capacity(timestamps.len());
+    /// every book at each of timestamps, in timestamp then SecurityID order
+    pub fn build_snapshot(&mut self, timestamps: &[i64]) -> Vec<md::Snapshot> {
+        return self
+            .build_snapshot_groups(timestamps)
+            .into_iter()
+            .flatten()
+            .collect();
+    }
+
+    /// one group per timestamp, books in SecurityID order
+    pub fn build_snapshot_groups(&mut self, timestamps: &[i64]) -> Vec<Vec<md::Snapshot>> {
+        return self.build_groups_with(timestamps, Book::to_snapshot);
+    }
+
+    fn build_groups_with<T>(&mut self, timestamps: &[i64], to_row: fn(&Book) -> T) -> Vec<Vec<T>> {
+        let mut groups = Vec::with_capacity(timestamps.len());
         for ts in timestamps {
             self.process_until(*ts);
 
             // turn book into snapshot
+            groups.push(self.books_.values().map(to_row).collect());
+            for book in self.books_.values() {
+                for observer in self.observers_.iter_mut() {
+                    observer.on_snapshot(book);
+                }
+            }
+        }
+        return groups;
+    }
 
-            for (_, book) in self.books_.iter() {
-                snapshots.push(book.to_snapshot());
+    /// books in SecurityID order
+    pub fn books(&self) -> impl Iterator<Item = &Book> {
+        return self.books_.values();
+    }
+
+    /// the book of an instrument as of the last processed event
+    pub fn book(&self, inst_id: i32) -> Option<&Book> {
+        return self.books_.get(&inst_id);
+    }
+
+    /// trading day of the data
+    pub fn date(&self) -> Option<i32> {
+        if let Some(order) = self.orders_.first() {
+            return Some(order.exchange_time().date);
+        }
+        return self.trades_.first().map(|trade| trade.exchange_time().date);
+    }
+
+    /// every book at each time of schedule
+    pub fn build_on_schedule(&mut self, schedule: &Schedule) -> Vec<md::Snapshot> {
+        return self.build_on_schedule_with(schedule, Book::to_snapshot);
+    }
+
+    /// like build_on_schedule, with the statistics of exchange Level-2 snapshots
+    pub fn build_extended_on_schedule(&mut self, schedule: &Schedule) -> Vec<md::ExtendedSnapshot> {
+        return self.build_on_schedule_with(schedule, Book::to_extended_snapshot);
+    }
+
+    fn build_on_schedule_with<T>(&mut self, schedule: &Schedule, to_row: fn(&Book) -> T) -> Vec<T> {
+        let date = match self.date() {
+            Some(date) => date,
+            None => return Vec::new(),
+        };
+        match schedule.timestamps(date) {
+            Some(mut timestamps) => {
+                // the books of times already replayed, e.g. before a restored
+                // checkpoint, are gone
+                let last_time = self.last_time_;
+                timestamps.retain(|ts| *ts > last_time);
+                self.build_groups_with(&timestamps, to_row)
+                    .into_iter()
+                    .flatten()
+                    .collect()
             }
+            None => {
+                // only the book touched by the event changes
+                let mut snapshots = Vec::new();
+                while let Some(changed) = self.process_next() {
+                    let book = match changed {
+                        Some(inst_id) => &self.books_[&inst_id],
+                        None => continue,
+                    };
+                    snapshots.push(to_row(book));
+                    for observer in self.observers_.iter_mut() {
+                        observer.on_snapshot(book);
+                    }
+                }
+                snapshots
+            }
+        }
+    }
+
+    /// e.g. "book as of 10:00:00.000 exchange time"
+    pub fn build_snapshot_at_exchange_time(
+        &mut self,
+        times: &[md::ExchangeTime],
+    ) -> Vec<md::Snapshot> {
+        self.set_clock_type(ClockType::Exchange);
+        let timestamps: Vec<i64> = times.iter().map(|t| t.to_clock()).collect();
+        return self.build_snapshot(&timestamps);
+    }
+
+    /// saves the books and the position in the replay, see restore_checkpoint
+    pub fn save_checkpoint(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let checkpoint = CheckpointRef {
+            clock_type: self.clock_type_,
+            num_orders: self.orders_.len(),
+            num_trades: self.trades_.len(),
+            num_snapshots: self.snapshots_.len(),
+            order_idx: self.order_idx_,
+            trade_idx: self.trade_idx_,
+            snapshot_idx: self.snapshot_idx_,
+            last_time: self.last_time_,
+            books: &self.books_,
+        };
+        let writer = BufWriter::new(File::create(filename)?);
+        bincode::serialize_into(writer, &checkpoint)?;
+        return Ok(());
+    }
+
+    /// the builder must be created from the same orders and trades as the saved one,
+    /// and replay on the same clock since it decides the order of events
+    pub fn restore_checkpoint(&mut self, filename: &str) -> Result<(), Box<dyn Error>> {
+        let reader = BufReader::new(File::open(filename)?);
+        let checkpoint: Checkpoint = bincode::deserialize_from(reader)?;
+        if checkpoint.clock_type != self.clock_type_ {
+            return Err(format!(
+                "checkpoint was saved on {:?} clock, the builder replays on {:?} clock",
+                checkpoint.clock_type, self.clock_type_
+            )
+            .into());
+        }
+        if checkpoint.num_orders != self.orders_.len()
+            || checkpoint.num_trades != self.trades_.len()
+            || checkpoint.num_snapshots != self.snapshots_.len()
+        {
+            return Err(format!(
+                "checkpoint was saved with {} orders, {} trades and {} snapshots, got {}, {} and {}",
+                checkpoint.num_orders,
+                checkpoint.num_trades,
+                checkpoint.num_snapshots,
+                self.orders_.len(),
+                self.trades_.len(),
+                self.snapshots_.len()
+            )
+            .into());
         }
-        return snapshots;
+
+        self.order_idx_ = checkpoint.order_idx;
+        self.trade_idx_ = checkpoint.trade_idx;
+        self.snapshot_idx_ = checkpoint.snapshot_idx;
+        self.last_time_ = checkpoint.last_time;
+        self.books_ = checkpoint.books;
+        self.set_start();
+        return Ok(());
     }
 
+    /// back to the start of the day with the books from init, or to the
+    /// restored checkpoint, rewind points are kept since they are still valid
     pub fn reset(&mut self) {
-        self.order_idx_ = 0;
-        self.trade_idx_ = 0;
+        self.order_idx_ = self.start_.order_idx;
+        self.trade_idx_ = self.start_.trade_idx;
+        self.snapshot_idx_ = self.start_.snapshot_idx;
+        self.last_time_ = self.start_.time;
+        self.books_ = self.start_.books.clone();
+    }
+
+    /// state as if process_until(timestamp) was called on a fresh builder,
+    /// jumps back to the nearest rewind point instead of replaying from the start,
+    /// fails for a time before a restored checkpoint since that is gone
+    pub fn rewind_to(&mut self, timestamp: i64) -> Result<(), Box<dyn Error>> {
+        if self.last_time_ < timestamp {
+            self.process_until(timestamp);
+            return Ok(());
+        }
+        let started = self.start_.order_idx + self.start_.trade_idx + self.start_.snapshot_idx > 0;
+        if started && timestamp <= self.start_.time {
+            return Err(format!(
+                "can not rewind to {}, the replay starts after {}",
+                timestamp, self.start_.time
+            )
+            .into());
+        }
+
+        let idx = self
+            .rewind_points_
+            .partition_point(|point| point.time <= timestamp);
+        if idx == 0 {
+            self.reset();
+        } else {
+            let point = &self.rewind_points_[idx - 1];
+            self.order_idx_ = point.order_idx;
+            self.trade_idx_ = point.trade_idx;
+            self.snapshot_idx_ = point.snapshot_idx;
+            self.books_ = point.books.clone();
+            // everything before the point is processed
+            self.last_time_ = point.time - 1;
+        }
+        self.process_until(timestamp);
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md::testing::{clock, order, trade};
+    use crate::md::Side;
+
+    fn arc<T>(rows: Vec<T>) -> Vec<Arc<T>> {
+        return rows.into_iter().map(Arc::new).collect();
+    }
+
+    // an exchange snapshot with these (price, quantity) levels
+    fn snapshot(inst: i32, time: &str, bids: &[(i64, i64)], asks: &[(i64, i64)]) -> md::Snapshot {
+        let mut book = Book::new(inst);
+        book.timestamp = clock(time) + 500;
+        book.exchange_time = md::ExchangeTime::parse(md::testing::DATE, time).unwrap();
+        for (price, quantity) in bids {
+            book.apply_change(Side::Bid, *price, *quantity);
+        }
+        for (price, quantity) in asks {
+            book.apply_change(Side::Ask, *price, *quantity);
+        }
+        return book.to_snapshot();
+    }
+
+    #[test]
+    fn rewind_points_are_off_by_default() {
+        let orders = vec![
+            order(2290, 1, "09:31:00.000", Side::Bid, 51000, 100),
+            order(2290, 2, "09:35:00.000", Side::Bid, 51000, 100),
+        ];
+        let mut builder = SnapshotBuilder::new(arc(orders), Vec::new());
+        builder.process_until(i64::MAX);
+        assert!(builder.rewind_points_.is_empty());
+    }
+
+    #[test]
+    fn rewind_keeps_books_from_init() {
+        let orders = vec![
+            order(2290, 1, "09:31:00.000", Side::Bid, 51000, 100),
+            order(2290, 2, "09:35:00.000", Side::Bid, 51000, 100),
+        ];
+        let mut builder = SnapshotBuilder::new(arc(orders), Vec::new());
+        builder.set_clock_type(ClockType::Exchange);
+        builder.set_rewind_interval(60 * 1000000);
+        builder.init(&[snapshot(2290, "09:30:00.000", &[(51000, 500)], &[])]);
+        builder.process_until(i64::MAX);
+        assert_eq!(builder.book(2290).unwrap().best_bid(), Some((51000, 700)));
+
+        // before the first rewind point, back to the books from init
+        builder.rewind_to(clock("09:30:30.000")).unwrap();
+        assert_eq!(builder.book(2290).unwrap().best_bid(), Some((51000, 500)));
+        builder.rewind_to(clock("09:33:00.000")).unwrap();
+        assert_eq!(builder.book(2290).unwrap().best_bid(), Some((51000, 600)));
+
+        builder.set_reference_data(HashMap::new());
+        assert!(builder.rewind_points_.is_empty());
+    }
+
+    // a day of two instruments with crosses and cancels
+    fn day() -> (Vec<Arc<md::Order>>, Vec<Arc<md::Trade>>) {
+        let orders = vec![
+            order(2290, 1, "09:31:00.000", Side::Bid, 51000, 300),
+            order(2385, 2, "09:32:00.000", Side::Ask, 95000, 200),
+            order(2290, 3, "09:33:00.000", Side::Ask, 51000, 100),
+            order(2290, 5, "09:35:00.000", Side::Bid, 50900, 100),
+            order(2385, 6, "09:36:00.000", Side::Bid, 95000, 50),
+            order(2290, 9, "09:38:00.000", Side::Ask, 51100, 400),
+        ];
+        let trades = vec![
+            trade(2290, 4, "09:33:00.000", 51000, 100, 1, 3),
+            trade(2385, 7, "09:36:00.000", 95000, 50, 6, 2),
+            trade(2290, 8, "09:37:00.000", 0, 100, 5, 0),
+        ];
+        return (arc(orders), arc(trades));
+    }
+
+    fn checkpoint_file(name: &str) -> String {
+        let filename = std::env::temp_dir().join(format!("reconstruct-{}.checkpoint", name));
+        return filename.to_str().unwrap().to_string();
+    }
+
+    #[test]
+    fn restored_checkpoint_resumes_the_replay() {
+        let later: Vec<i64> = ["09:34:30.000", "09:36:00.000", "09:40:00.000"]
+            .iter()
+            .map(|time| clock(time))
+            .collect();
+        let (orders, trades) = day();
+        let mut builder = SnapshotBuilder::new(orders.clone(), trades.clone());
+        let expected = format!("{:?}", builder.build_snapshot(&later));
+
+        let filename = checkpoint_file("round-trip");
+        let mut builder = SnapshotBuilder::new(orders.clone(), trades.clone());
+        builder.build_snapshot(&[clock("09:34:00.000")]);
+        builder.save_checkpoint(&filename).unwrap();
+
+        let mut builder = SnapshotBuilder::new(orders, trades);
+        builder.restore_checkpoint(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        assert_eq!(builder.book(2290).unwrap().best_bid(), Some((51000, 200)));
+        assert_eq!(format!("{:?}", builder.build_snapshot(&later)), expected);
+    }
+
+    #[test]
+    fn checkpoint_of_other_input_is_rejected() {
+        let filename = checkpoint_file("mismatch");
+        let (orders, trades) = day();
+        let mut builder = SnapshotBuilder::new(orders.clone(), trades.clone());
+        builder.process_until(clock("09:34:00.000"));
+        builder.save_checkpoint(&filename).unwrap();
+
+        let mut exchange = SnapshotBuilder::new(orders.clone(), trades.clone());
+        exchange.set_clock_type(ClockType::Exchange);
+        let error = exchange.restore_checkpoint(&filename).unwrap_err();
+        assert!(error.to_string().contains("clock"));
+
+        let mut shorter = SnapshotBuilder::new(orders[1..].to_vec(), trades);
+        let error = shorter.restore_checkpoint(&filename).unwrap_err();
+        assert!(error.to_string().contains("orders"));
+        std::fs::remove_file(&filename).unwrap();
+        // nothing was replayed
+        assert!(shorter.book(2290).is_none());
+    }
+
+    #[test]
+    fn rewind_stops_at_a_restored_checkpoint() {
+        let orders = vec![
+            order(2290, 1, "09:31:00.000", Side::Bid, 51000, 100),
+            order(2290, 2, "09:35:00.000", Side::Bid, 51000, 100),
+            order(2290, 3, "09:37:00.000", Side::Bid, 51000, 100),
+        ];
+        let filename = &checkpoint_file("rewind");
+        let mut builder = SnapshotBuilder::new(arc(orders.clone()), Vec::new());
+        builder.process_until(clock("09:36:00.000"));
+        builder.save_checkpoint(filename).unwrap();
+
+        let mut builder = SnapshotBuilder::new(arc(orders), Vec::new());
+        builder.restore_checkpoint(filename).unwrap();
+        std::fs::remove_file(filename).unwrap();
+        builder.set_rewind_interval(60 * 1000000);
+        builder.process_until(i64::MAX);
+        assert!(builder.rewind_to(clock("09:34:00.000")).is_err());
+        // the books are left alone
+        assert_eq!(builder.book(2290).unwrap().best_bid(), Some((51000, 300)));
+        builder.rewind_to(clock("09:36:00.000")).unwrap();
+        assert_eq!(builder.book(2290).unwrap().best_bid(), Some((51000, 200)));
+    }
+
+    #[test]
+    fn exchange_clock_replays_in_transact_time_order() {
+        // 2290 happened first but arrived after 2385
+        let early = order(2290, 2, "10:00:00.000", Side::Bid, 51000, 100);
+        let mut late = order(2385, 1, "10:00:00.010", Side::Bid, 95000, 100);
+        late.clockAtArrival = early.clockAtArrival - 100;
+        let mut builder = SnapshotBuilder::new(arc(vec![late, early]), Vec::new());
+        builder.set_clock_type(ClockType::Exchange);
+
+        builder.process_until(clock("10:00:00.005"));
+        assert_eq!(builder.book(2290).unwrap().best_bid(), Some((51000, 100)));
+        assert!(builder.book(2385).is_none());
+        builder.process_until(i64::MAX);
+        assert!(builder.book(2385).is_some());
+    }
+
+    #[test]
+    fn closing_auction_matches_at_the_close() {
+        let orders = vec![
+            order(2290, 1, "14:58:00.000", Side::Bid, 51000, 300),
+            order(2290, 2, "14:58:01.000", Side::Ask, 50900, 200),
+        ];
+        let trades = vec![trade(2290, 3, "15:00:00.000", 50950, 200, 1, 2)];
+        let mut builder = SnapshotBuilder::new(arc(orders), arc(trades));
+        builder.set_clock_type(ClockType::Exchange);
+
+        // both sides rest crossed until the auction is matched
+        builder.process_until(clock("14:59:59.000"));
+        let book = builder.book(2290).unwrap();
+        assert_eq!(book.best_bid(), Some((51000, 300)));
+        assert_eq!(book.best_ask(), Some((50900, 200)));
+
+        builder.process_until(i64::MAX);
+        let book = builder.book(2290).unwrap();
+        assert_eq!(book.best_bid(), Some((51000, 100)));
+        assert_eq!(book.best_ask(), None);
+    }
+
+    // (ApplSeqNum, remaining) of the orders we know at a level
+    fn queue(book: &Book, side: Side, price: i64) -> Vec<(i64, i64)> {
+        return book
+            .orders_at(side, price)
+            .map(|(order, remaining)| (order.ApplSeqNum, remaining))
+            .collect();
+    }
+
+    #[test]
+    fn crosses_fill_the_queue_in_time_priority() {
+        let orders = vec![
+            order(2290, 1, "10:00:00.000", Side::Bid, 51000, 200),
+            order(2290, 2, "10:00:01.000", Side::Bid, 51000, 300),
+            order(2290, 3, "10:00:02.000", Side::Bid, 51000, 100),
+            order(2290, 4, "10:00:03.000", Side::Bid, 50900, 500),
+            order(2290, 5, "10:00:04.000", Side::Ask, 51000, 350),
+        ];
+        let mut builder = SnapshotBuilder::new(arc(orders), Vec::new());
+        builder.set_clock_type(ClockType::Exchange);
+        builder.process_until(i64::MAX);
+
+        let book = builder.book(2290).unwrap();
+        assert_eq!(queue(book, Side::Bid, 51000), vec![(2, 150), (3, 100)]);
+        assert_eq!(queue(book, Side::Bid, 50900), vec![(4, 500)]);
+        assert_eq!(book.depth_at(Side::Bid, 51000), 250);
+        assert_eq!(book.cumulative_depth(Side::Bid, 2), 750);
+        assert_eq!(book.volume_to_move(Side::Bid, 1), Some(250));
+        // filled orders can still be looked up
+        assert_eq!(book.remaining_quantity(1), 0);
+        assert_eq!(book.order(1).unwrap().OrderQty, 200);
+        assert_eq!(book.remaining_quantity(5), 0);
+        assert!(queue(book, Side::Ask, 51000).is_empty());
+    }
+
+    #[test]
+    fn cancels_leave_the_queue() {
+        let orders = vec![
+            order(2290, 1, "10:00:00.000", Side::Bid, 51000, 200),
+            order(2290, 2, "10:00:01.000", Side::Bid, 51000, 300),
+            order(2290, 3, "10:00:02.000", Side::Bid, 51000, 100),
+            order(2290, 4, "10:00:03.000", Side::Ask, 51000, 100),
+        ];
+        let trades = vec![
+            trade(2290, 5, "10:00:03.000", 51000, 100, 1, 4),
+            trade(2290, 6, "10:00:05.000", 0, 300, 2, 0),
+            trade(2290, 7, "10:00:06.000", 0, 100, 1, 0),
+        ];
+        let mut builder = SnapshotBuilder::new(arc(orders), arc(trades));
+        builder.set_clock_type(ClockType::Exchange);
+
+        builder.process_until(clock("10:00:05.500"));
+        let book = builder.book(2290).unwrap();
+        assert_eq!(queue(book, Side::Bid, 51000), vec![(1, 100), (3, 100)]);
+        assert_eq!(book.depth_at(Side::Bid, 51000), 200);
+
+        // the rest of the partly filled order
+        builder.process_until(i64::MAX);
+        let book = builder.book(2290).unwrap();
+        assert_eq!(queue(book, Side::Bid, 51000), vec![(3, 100)]);
+        assert_eq!(book.best_bid(), Some((51000, 100)));
+        assert_eq!(book.remaining_quantity(1), 0);
+    }
+
+    #[test]
+    fn warm_start_seeds_instruments_first_seen_in_a_snapshot() {
+        let mut seed = snapshot(2290, "10:00:00.000", &[(51000, 500)], &[(51100, 300)]);
+        seed.cum_volume = 1000;
+        let mut builder = SnapshotBuilder::new(Vec::new(), Vec::new());
+        builder.set_clock_type(ClockType::Exchange);
+        builder.set_exchange_snapshots(arc(vec![seed]));
+        builder.process_until(i64::MAX);
+
+        let book = builder.book(2290).unwrap();
+        assert!(book.is_warm());
+        assert_eq!(book.best_bid(), Some((51000, 500)));
+        assert_eq!(book.best_ask(), Some((51100, 300)));
+        assert_eq!(book.cum_volume, 1000);
+        // none of it belongs to an order we know
+        assert_eq!(book.unattributed_quantity(), 800);
+        assert_eq!(book.orders_at(Side::Bid, 51000).count(), 0);
+    }
+
+    #[test]
+    fn warm_start_from_an_empty_snapshot_leaves_no_book() {
+        // the recorder sends all-zero snapshots before the open
+        let empty = snapshot(2290, "08:24:03.000", &[], &[]);
+        let orders = vec![order(2290, 1, "09:15:00.000", Side::Bid, 51000, 100)];
+        let mut builder = SnapshotBuilder::new(arc(orders), Vec::new());
+        builder.set_clock_type(ClockType::Exchange);
+        builder.set_exchange_snapshots(arc(vec![empty.clone()]));
+        assert_eq!(builder.step(), Some(None));
+        assert!(builder.book(2290).is_none());
+        assert_eq!(builder.step(), Some(Some(2290)));
+        assert_eq!(builder.step(), None);
+
+        let mut builder = SnapshotBuilder::new(Vec::new(), Vec::new());
+        builder.set_clock_type(ClockType::Exchange);
+        builder.set_exchange_snapshots(arc(vec![empty]));
+        assert!(builder.build_on_schedule(&Schedule::EveryEvent).is_empty());
+    }
+
+    #[test]
+    fn reconcile_only_adjusts_unattributed_quantity() {
+        let snapshots = vec![
+            snapshot(2290, "10:00:00.000", &[(51000, 500)], &[(51100, 300)]),
+            // 100 of the seeded bid and the whole 51100 level went away unseen
+            snapshot(2290, "10:00:03.000", &[(51000, 600)], &[(51200, 100)]),
+        ];
+        let orders = vec![order(2290, 1, "10:00:01.000", Side::Bid, 51000, 200)];
+        let mut builder = SnapshotBuilder::new(arc(orders), Vec::new());
+        builder.set_clock_type(ClockType::Exchange);
+        builder.set_exchange_snapshots(arc(snapshots));
+
+        builder.process_until(clock("10:00:02.000"));
+        let book = builder.book(2290).unwrap();
+        assert_eq!(book.best_bid(), Some((51000, 700)));
+        assert_eq!(book.unattributed_quantity(), 800);
+
+        builder.process_until(i64::MAX);
+        let book = builder.book(2290).unwrap();
+        assert_eq!(book.best_bid(), Some((51000, 600)));
+        assert_eq!(book.best_ask(), Some((51200, 100)));
+        assert_eq!(book.unattributed_quantity(), 500);
+        assert_eq!(queue(book, Side::Bid, 51000), vec![(1, 200)]);
+    }
+
+    #[test]
+    fn unattributed_quantity_is_ahead_of_known_orders() {
+        let snapshots = vec![snapshot(2290, "10:00:00.000", &[(51000, 500)], &[])];
+        let orders = vec![
+            order(2290, 1, "10:00:01.000", Side::Bid, 51000, 200),
+            order(2290, 2, "10:00:02.000", Side::Ask, 51000, 600),
+        ];
+        // 99 rested before our capture started
+        let trades = vec![
+            trade(2290, 3, "10:00:02.000", 51000, 500, 99, 2),
+            trade(2290, 4, "10:00:02.000", 51000, 100, 1, 2),
+        ];
+        let mut builder = SnapshotBuilder::new(arc(orders), arc(trades));
+        builder.set_clock_type(ClockType::Exchange);
+        builder.set_exchange_snapshots(arc(snapshots));
+        builder.process_until(i64::MAX);
+
+        let book = builder.book(2290).unwrap();
+        assert_eq!(book.best_bid(), Some((51000, 100)));
+        assert_eq!(book.best_ask(), None);
+        assert_eq!(book.unattributed_quantity(), 0);
+        assert_eq!(queue(book, Side::Bid, 51000), vec![(1, 100)]);
+    }
+
+    #[test]
+    fn warm_start_reconciles_at_the_exchange_time_of_a_snapshot() {
+        // the snapshot arrives after a trade that happened after it
+        let mut late = snapshot(2290, "10:00:03.000", &[(51000, 500)], &[]);
+        late.cum_volume = 1000;
+        late.clockAtArrival = clock("10:00:03.900");
+        let orders = vec![order(2290, 1, "10:00:03.100", Side::Ask, 51000, 100)];
+        let trades = vec![trade(2290, 2, "10:00:03.100", 51000, 100, 99, 1)];
+        let mut builder = SnapshotBuilder::new(arc(orders), arc(trades));
+        builder.set_clock_type(ClockType::Exchange);
+        builder.set_exchange_snapshots(arc(vec![late]));
+        builder.process_until(i64::MAX);
+
+        let book = builder.book(2290).unwrap();
+        assert_eq!(book.cum_volume, 1100);
+        assert_eq!(book.best_bid(), Some((51000, 400)));
+    }
+
+    #[test]
+    fn continuous_orders_match_on_arrival() {
+        let orders = vec![
+            order(2290, 1, "14:50:00.000", Side::Bid, 51000, 300),
+            order(2290, 2, "14:50:01.000", Side::Ask, 50900, 200),
+        ];
+        let mut builder = SnapshotBuilder::new(arc(orders), Vec::new());
+        builder.set_clock_type(ClockType::Exchange);
+        builder.process_until(i64::MAX);
+        let book = builder.book(2290).unwrap();
+        assert_eq!(book.best_bid(), Some((51000, 100)));
+        assert_eq!(book.best_ask(), None);
     }
 }
@@ -0,0 +1,424 @@
+use crate::md;
+use crate::observer::BookObserver;
+use crate::snapshot_builder::Book;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+/// when the monitor raises alerts, SZSE data has no trader ids so every
+/// pattern is about orders of one instrument and side
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Thresholds {
+    /// a cancel is large at this many times the average limit order of the instrument
+    pub large_multiple: f64,
+    /// ticks from the best price of its side that count as near the touch
+    pub near_touch_ticks: i64,
+    /// only orders cancelled within this many milliseconds of exchange time
+    /// count for large cancels and layering
+    pub max_lifetime_millis: i64,
+    /// distinct prices one side must be layered on
+    pub layering_levels: usize,
+    /// ticks from the best price the layered orders may rest at
+    pub layering_ticks: i64,
+    /// milliseconds that placing and cancelling the layers must fit in
+    pub layering_window_millis: i64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            large_multiple: 10.0,
+            near_touch_ticks: 2,
+            max_lifetime_millis: 10_000,
+            layering_levels: 3,
+            layering_ticks: 5,
+            layering_window_millis: 5_000,
+        }
+    }
+}
+
+/// the pattern an alert was raised for
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum AlertKind {
+    /// a large order near the touch cancelled shortly after it was placed
+    LargeCancelNearTouch,
+    /// orders on several prices of one side placed and cancelled together
+    /// while the instrument traded
+    Layering,
+}
+
+/// prices in book units
+#[derive(Debug, Clone)]
+pub struct Alert {
+    /// SecurityID of the instrument
+    pub inst_id: i32,
+    /// exchange time of the cancel that raised it
+    pub exchange_time: md::ExchangeTime,
+    /// the pattern that was seen
+    pub kind: AlertKind,
+    /// side of the orders involved
+    pub side: md::Side,
+    /// the cancelled order, or the best price among the layers
+    pub price: i64,
+    /// cancelled quantity of every order involved
+    pub quantity: i64,
+    /// ApplSeqNums of the orders involved
+    pub seqs: Vec<i64>,
+}
+
+/// per instrument statistics of the whole replay
+#[derive(Debug, Clone)]
+pub struct CancelSummary {
+    /// SecurityID of the instrument
+    pub inst_id: i32,
+    /// orders placed
+    pub orders: i64,
+    /// executions
+    pub executions: i64,
+    /// orders cancelled
+    pub cancels: i64,
+    /// quantity cancelled
+    pub cancelled_quantity: i64,
+    /// orders per execution, the number of orders when nothing traded
+    pub order_to_trade: f64,
+    /// cancels per order placed
+    pub cancel_to_order: f64,
+    /// milliseconds of exchange time from placing to cancelling an order
+    pub latency_mean: f64,
+    /// 10th percentile of the cancel latency
+    pub latency_p10: i64,
+    /// median of the cancel latency
+    pub latency_p50: i64,
+    /// 90th percentile of the cancel latency
+    pub latency_p90: i64,
+    /// 99th percentile of the cancel latency
+    pub latency_p99: i64,
+    /// share of cancels within 100 milliseconds and within a second
+    pub within_100ms: f64,
+    /// share of cancels within a second
+    pub within_1s: f64,
+    /// alerts raised
+    pub alerts: usize,
+}
+
+// a cancelled order kept for the layering window
+struct Cancel {
+    seq: i64,
+    added_millis: i64,
+    cancelled_millis: i64,
+    price: i64,
+    quantity: i64,
+}
+
+#[derive(Default)]
+struct Instrument {
+    orders: i64,
+    executions: i64,
+    limit_quantity: i64,
+    limit_orders: i64,
+    cancelled_quantity: i64,
+    latencies: Vec<i64>,
+    last_execution_millis: Option<i64>,
+    bid_cancels: VecDeque<Cancel>,
+    ask_cancels: VecDeque<Cancel>,
+    alerts: usize,
+}
+
+/// p in [0, 100], nearest rank of sorted values
+pub(crate) fn percentile(sorted: &[i64], p: usize) -> i64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (sorted.len() * p).div_ceil(100);
+    return sorted[rank.saturating_sub(1)];
+}
+
+// ticks the price is behind the best price of its side, 0 at or ahead of it
+fn ticks_from_touch(book: &Book, side: md::Side, price: i64) -> i64 {
+    let best = match side {
+        md::Side::Bid => book.best_bid(),
+        _ => book.best_ask(),
+    };
+    let behind = match (side, best) {
+        (_, None) => 0,
+        (md::Side::Bid, Some((best, _))) => best - price,
+        (_, Some((best, _))) => price - best,
+    };
+    return behind.max(0) / Book::TICK_SIZE;
+}
+
+/// tracks cancels of every instrument and raises alerts, usable as an observer
+pub struct SurveillanceMonitor {
+    thresholds: Thresholds,
+    // key: SecurityID
+    instruments_: BTreeMap<i32, Instrument>,
+    alerts_: Vec<Alert>,
+}
+
+impl SurveillanceMonitor {
+    /// raises alerts on the given thresholds
+    pub fn new(thresholds: Thresholds) -> SurveillanceMonitor {
+        SurveillanceMonitor {
+            thresholds,
+            instruments_: BTreeMap::new(),
+            alerts_: Vec::new(),
+        }
+    }
+
+    fn check_large_cancel(&mut self, book: &Book, order: &md::Order, quantity: i64, lifetime: i64) {
+        let thresholds = &self.thresholds;
+        let instrument = self.instruments_.get_mut(&book.inst_id()).unwrap();
+        if instrument.limit_orders == 0 || lifetime > thresholds.max_lifetime_millis {
+            return;
+        }
+        let average = instrument.limit_quantity as f64 / instrument.limit_orders as f64;
+        if (quantity as f64) < thresholds.large_multiple * average
+            || ticks_from_touch(book, order.Side, order.Price) > thresholds.near_touch_ticks
+        {
+            return;
+        }
+        instrument.alerts += 1;
+        self.alerts_.push(Alert {
+            inst_id: book.inst_id(),
+            exchange_time: book.exchange_time,
+            kind: AlertKind::LargeCancelNearTouch,
+            side: order.Side,
+            price: order.Price,
+            quantity,
+            seqs: vec![order.ApplSeqNum],
+        });
+    }
+
+    fn check_layering(&mut self, book: &Book, order: &md::Order, quantity: i64) {
+        let thresholds = &self.thresholds;
+        let instrument = self.instruments_.get_mut(&book.inst_id()).unwrap();
+        let now = book.exchange_time.millis;
+        let last_execution = instrument.last_execution_millis;
+        let cancels = match order.Side {
+            md::Side::Bid => &mut instrument.bid_cancels,
+            md::Side::Ask => &mut instrument.ask_cancels,
+            md::Side::Unknown => return,
+        };
+        if ticks_from_touch(book, order.Side, order.Price) <= thresholds.layering_ticks {
+            cancels.push_back(Cancel {
+                seq: order.ApplSeqNum,
+                added_millis: order.exchange_time().millis,
+                cancelled_millis: now,
+                price: order.Price,
+                quantity,
+            });
+        }
+        let start = now - thresholds.layering_window_millis;
+        while cancels
+            .front()
+            .is_some_and(|cancel| cancel.cancelled_millis < start)
+        {
+            cancels.pop_front();
+        }
+
+        // layers placed and cancelled within the window, with a trade in between
+        let layers: Vec<&Cancel> = cancels
+            .iter()
+            .filter(|cancel| cancel.added_millis >= start)
+            .collect();
+        let mut prices: Vec<i64> = layers.iter().map(|cancel| cancel.price).collect();
+        prices.sort_unstable();
+        prices.dedup();
+        let first_added = layers.iter().map(|cancel| cancel.added_millis).min();
+        let traded = match (first_added, last_execution) {
+            (Some(first), Some(execution)) => first <= execution,
+            _ => false,
+        };
+        if prices.len() < thresholds.layering_levels || !traded {
+            return;
+        }
+        let price = match order.Side {
+            md::Side::Bid => *prices.last().unwrap(),
+            _ => prices[0],
+        };
+        let alert = Alert {
+            inst_id: book.inst_id(),
+            exchange_time: book.exchange_time,
+            kind: AlertKind::Layering,
+            side: order.Side,
+            price,
+            quantity: layers.iter().map(|cancel| cancel.quantity).sum(),
+            seqs: layers.iter().map(|cancel| cancel.seq).collect(),
+        };
+        // one alert per burst
+        cancels.clear();
+        instrument.alerts += 1;
+        self.alerts_.push(alert);
+    }
+
+    /// alerts in the order they were raised
+    pub fn take_alerts(&mut self) -> Vec<Alert> {
+        return std::mem::take(&mut self.alerts_);
+    }
+
+    /// statistics of every instrument in SecurityID order
+    pub fn summary(&self) -> Vec<CancelSummary> {
+        let mut rows = Vec::with_capacity(self.instruments_.len());
+        for (inst_id, instrument) in self.instruments_.iter() {
+            let mut latencies = instrument.latencies.clone();
+            latencies.sort_unstable();
+            let cancels = latencies.len() as i64;
+            let share = |millis: i64| {
+                if cancels == 0 {
+                    return 0.0;
+                }
+                let within = latencies
+                    .iter()
+                    .filter(|latency| **latency <= millis)
+                    .count();
+                return within as f64 / cancels as f64;
+            };
+            rows.push(CancelSummary {
+                inst_id: *inst_id,
+                orders: instrument.orders,
+                executions: instrument.executions,
+                cancels,
+                cancelled_quantity: instrument.cancelled_quantity,
+                order_to_trade: instrument.orders as f64 / instrument.executions.max(1) as f64,
+                cancel_to_order: cancels as f64 / instrument.orders.max(1) as f64,
+                latency_mean: latencies.iter().sum::<i64>() as f64 / cancels.max(1) as f64,
+                latency_p10: percentile(&latencies, 10),
+                latency_p50: percentile(&latencies, 50),
+                latency_p90: percentile(&latencies, 90),
+                latency_p99: percentile(&latencies, 99),
+                within_100ms: share(100),
+                within_1s: share(1000),
+                alerts: instrument.alerts,
+            });
+        }
+        return rows;
+    }
+}
+
+impl BookObserver for SurveillanceMonitor {
+    fn on_order_added(&mut self, book: &Book, order: &md::Order) {
+        let instrument = self.instruments_.entry(book.inst_id()).or_default();
+        instrument.orders += 1;
+        if order.OrderType == md::OrderType::LimitOrder {
+            instrument.limit_orders += 1;
+            instrument.limit_quantity += order.OrderQty;
+        }
+    }
+
+    fn on_order_cancelled(&mut self, book: &Book, order: &md::Order, quantity: i64) {
+        let lifetime = book.exchange_time.millis - order.exchange_time().millis;
+        let instrument = self.instruments_.entry(book.inst_id()).or_default();
+        instrument.cancelled_quantity += quantity;
+        instrument.latencies.push(lifetime);
+        if order.OrderType != md::OrderType::LimitOrder {
+            return;
+        }
+        self.check_large_cancel(book, order, quantity, lifetime);
+        if lifetime <= self.thresholds.max_lifetime_millis {
+            self.check_layering(book, order, quantity);
+        }
+    }
+
+    fn on_order_executed(&mut self, book: &Book, _trade: &md::Trade) {
+        let instrument = self.instruments_.entry(book.inst_id()).or_default();
+        instrument.executions += 1;
+        instrument.last_execution_millis = Some(book.exchange_time.millis);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md::testing::{order, trade};
+    use crate::md::Side;
+    use crate::snapshot_builder::{ClockType, SnapshotBuilder};
+    use std::sync::{Arc, Mutex};
+
+    fn alerts(
+        thresholds: Thresholds,
+        orders: Vec<md::Order>,
+        trades: Vec<md::Trade>,
+    ) -> Vec<Alert> {
+        let orders = orders.into_iter().map(Arc::new).collect();
+        let trades = trades.into_iter().map(Arc::new).collect();
+        let mut builder = SnapshotBuilder::new(orders, trades);
+        builder.set_clock_type(ClockType::Exchange);
+        let monitor = Arc::new(Mutex::new(SurveillanceMonitor::new(thresholds)));
+        builder.add_observer(Box::new(monitor.clone()));
+        builder.process_until(i64::MAX);
+        let alerts = monitor.lock().unwrap().take_alerts();
+        return alerts;
+    }
+
+    // nine bids of 100 at 50000, then a bid of 10000 at price, cancelled at time
+    fn large_cancel(price: i64, time: &str) -> Vec<Alert> {
+        let mut orders: Vec<md::Order> = (1..10)
+            .map(|seq| order(2290, seq, "10:00:00.000", Side::Bid, 50000, 100))
+            .collect();
+        orders.push(order(2290, 10, "10:00:00.500", Side::Bid, price, 10000));
+        let trades = vec![trade(2290, 11, time, 0, 10000, 10, 0)];
+        let thresholds = Thresholds {
+            large_multiple: 5.0,
+            ..Thresholds::default()
+        };
+        return alerts(thresholds, orders, trades);
+    }
+
+    #[test]
+    fn large_cancel_near_the_touch_raises_an_alert() {
+        let alerts = large_cancel(51000, "10:00:01.000");
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].kind, AlertKind::LargeCancelNearTouch);
+        assert_eq!(alerts[0].price, 51000);
+        assert_eq!(alerts[0].quantity, 10000);
+        assert_eq!(alerts[0].seqs, vec![10]);
+
+        // two ticks behind the best bid is still near
+        assert_eq!(large_cancel(49800, "10:00:01.000").len(), 1);
+    }
+
+    #[test]
+    fn large_cancel_far_from_the_touch_or_late_raises_nothing() {
+        // three ticks behind the best bid
+        assert!(large_cancel(49700, "10:00:01.000").is_empty());
+        // rested longer than max_lifetime_millis
+        assert!(large_cancel(51000, "10:00:10.501").is_empty());
+        assert_eq!(large_cancel(51000, "10:00:10.500").len(), 1);
+    }
+
+    // bids layered on four prices behind the best bid and cancelled one by
+    // one, with an execution at the best bid in between when traded
+    fn layering(traded: bool) -> Vec<Alert> {
+        let mut orders = vec![
+            order(2290, 1, "10:00:00.000", Side::Bid, 50000, 100),
+            order(2290, 2, "10:00:00.100", Side::Bid, 49900, 100),
+            order(2290, 3, "10:00:00.200", Side::Bid, 49800, 100),
+            order(2290, 4, "10:00:00.300", Side::Bid, 49700, 100),
+            order(2290, 5, "10:00:00.400", Side::Bid, 49600, 100),
+        ];
+        let mut trades = Vec::new();
+        if traded {
+            orders.push(order(2290, 6, "10:00:01.000", Side::Ask, 50000, 50));
+            trades.push(trade(2290, 7, "10:00:01.000", 50000, 50, 1, 6));
+        }
+        for seq in 2..6 {
+            let time = format!("10:00:02.{}00", seq);
+            trades.push(trade(2290, 10 + seq, &time, 0, 100, seq, 0));
+        }
+        return alerts(Thresholds::default(), orders, trades);
+    }
+
+    #[test]
+    fn layering_with_an_execution_in_between_raises_one_alert() {
+        let alerts = layering(true);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].kind, AlertKind::Layering);
+        assert!(matches!(alerts[0].side, Side::Bid));
+        // raised on the third level, the best price among the layers
+        assert_eq!(alerts[0].price, 49900);
+        assert_eq!(alerts[0].quantity, 300);
+        assert_eq!(alerts[0].seqs, vec![2, 3, 4]);
+
+        assert!(layering(false).is_empty());
+    }
+}
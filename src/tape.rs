@@ -0,0 +1,288 @@
+use crate::md;
+use crate::observer::BookObserver;
+use crate::schedule;
+use crate::snapshot_builder::Book;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+// aggressive orders of a book whose executions may still come, with the
+// arrival clock later orders can be replayed before the trades of earlier ones
+const PENDING_PER_BOOK: usize = 32;
+
+/// one execution with the book as the aggressive order found it, prices in book units
+#[derive(Debug, Clone)]
+pub struct TapeEntry {
+    /// SecurityID of the instrument
+    pub inst_id: i32,
+    /// clock of the trade
+    pub timestamp: i64,
+    /// exchange time of the trade
+    pub exchange_time: md::ExchangeTime,
+    /// ApplSeqNum of the trade
+    pub seq: i64,
+    /// execution price
+    pub price: i64,
+    /// shares executed
+    pub quantity: i64,
+    /// who initiated the execution
+    pub aggressor: md::Aggressor,
+    /// ApplSeqNum of the buy order
+    pub bid_seq: i64,
+    /// ApplSeqNum of the sell order
+    pub offer_seq: i64,
+    /// (price, quantity) before the aggressive order, None in call auctions
+    pub best_bid: Option<(i64, i64)>,
+    /// like best_bid for the ask side
+    pub best_ask: Option<(i64, i64)>,
+    /// ApplSeqNum of the passive order, None in call auctions
+    pub resting_seq: Option<i64>,
+    /// quantity ahead of the passive order in its queue before the aggressive
+    /// order, None if we never saw the passive order rest in the book
+    pub queue_ahead: Option<i64>,
+    /// milliseconds the passive order rested before the execution
+    pub resting_age_millis: Option<i64>,
+    /// quantity of the aggressive order, None if we never saw it
+    pub aggressor_quantity: Option<i64>,
+    /// type of the aggressive order, None if we never saw it
+    pub aggressor_type: Option<md::OrderType>,
+}
+
+impl TapeEntry {
+    /// in book units, None unless both sides had a level
+    pub fn spread(&self) -> Option<i64> {
+        return match (self.best_bid, self.best_ask) {
+            (Some((bid, _)), Some((ask, _))) => Some(ask - bid),
+            _ => None,
+        };
+    }
+}
+
+// what an aggressive order found in the book
+struct Pending {
+    seq: i64,
+    best_bid: Option<(i64, i64)>,
+    best_ask: Option<(i64, i64)>,
+    // key: ApplSeqNum of a passive order it could reach
+    // value: quantity ahead of it
+    queue_ahead: HashMap<i64, i64>,
+}
+
+/// executions enriched with the book before each aggressive order, usable as an observer
+pub struct TradeTape {
+    // key: SecurityID
+    pending_: BTreeMap<i32, VecDeque<Pending>>,
+    entries_: Vec<TapeEntry>,
+}
+
+impl Default for TradeTape {
+    fn default() -> Self {
+        TradeTape::new()
+    }
+}
+
+// an order that takes liquidity the moment it arrives
+fn is_aggressive(book: &Book, order: &md::Order) -> bool {
+    if schedule::is_call_auction(order.exchange_time().millis) {
+        return false;
+    }
+    return match (order.OrderType, order.Side) {
+        (md::OrderType::MarketOrder, _) => true,
+        (md::OrderType::LimitOrder, md::Side::Bid) => {
+            book.best_ask().is_some_and(|(ask, _)| ask <= order.Price)
+        }
+        (md::OrderType::LimitOrder, md::Side::Ask) => {
+            book.best_bid().is_some_and(|(bid, _)| bid >= order.Price)
+        }
+        _ => false,
+    };
+}
+
+impl TradeTape {
+    /// an empty tape, entries are added as an observer or with add_trade
+    pub fn new() -> TradeTape {
+        TradeTape {
+            pending_: BTreeMap::new(),
+            entries_: Vec::new(),
+        }
+    }
+
+    // queue positions of the passive orders the order can reach, level by
+    // level until the levels hold its whole quantity
+    fn reachable(book: &Book, order: &md::Order) -> HashMap<i64, i64> {
+        let side = order.Side.opposite();
+        let mut queue_ahead = HashMap::new();
+        let mut reached = 0;
+        for (price, quantity) in book.depth(side) {
+            let within = match (order.OrderType, side) {
+                (md::OrderType::MarketOrder, _) => true,
+                (_, md::Side::Ask) => price <= order.Price,
+                _ => price >= order.Price,
+            };
+            if !within || reached >= order.OrderQty {
+                break;
+            }
+            // the unattributed part of a level is ahead of every order
+            let attributed: i64 = book.orders_at(side, price).map(|(_, qty)| qty).sum();
+            let mut ahead = quantity - attributed;
+            for (resting, remaining) in book.orders_at(side, price) {
+                queue_ahead.insert(resting.ApplSeqNum, ahead);
+                ahead += remaining;
+            }
+            reached += quantity;
+        }
+        return queue_ahead;
+    }
+
+    /// records an execution against book, cancels are ignored
+    pub fn add_trade(&mut self, book: &Book, trade: &md::Trade) {
+        match trade.ExecType {
+            md::ExecuteType::Traded => {}
+            _ => return,
+        }
+        let aggressor = trade.aggressor();
+        let mut entry = TapeEntry {
+            inst_id: trade.SecurityID,
+            timestamp: book.timestamp,
+            exchange_time: trade.exchange_time(),
+            seq: trade.ApplSeqNum,
+            price: trade.TradePrice,
+            quantity: trade.TradeQty,
+            aggressor,
+            bid_seq: trade.BidApplSeqNum,
+            offer_seq: trade.OfferApplSeqNum,
+            best_bid: None,
+            best_ask: None,
+            resting_seq: None,
+            queue_ahead: None,
+            resting_age_millis: None,
+            aggressor_quantity: None,
+            aggressor_type: None,
+        };
+
+        let (aggressor_seq, resting_seq) = match aggressor {
+            md::Aggressor::Buy => (trade.BidApplSeqNum, trade.OfferApplSeqNum),
+            md::Aggressor::Sell => (trade.OfferApplSeqNum, trade.BidApplSeqNum),
+            md::Aggressor::Auction | md::Aggressor::Unknown => {
+                self.entries_.push(entry);
+                return;
+            }
+        };
+        entry.resting_seq = Some(resting_seq);
+        if let Some(order) = book.order(aggressor_seq) {
+            entry.aggressor_quantity = Some(order.OrderQty);
+            entry.aggressor_type = Some(order.OrderType);
+        }
+        if let Some(order) = book.order(resting_seq) {
+            let age = entry.exchange_time.millis - order.exchange_time().millis;
+            entry.resting_age_millis = Some(age);
+        }
+        let pending = self
+            .pending_
+            .get(&trade.SecurityID)
+            .and_then(|pending| pending.iter().find(|p| p.seq == aggressor_seq));
+        if let Some(pending) = pending {
+            entry.best_bid = pending.best_bid;
+            entry.best_ask = pending.best_ask;
+            entry.queue_ahead = pending.queue_ahead.get(&resting_seq).copied();
+        }
+        self.entries_.push(entry);
+    }
+
+    /// executions in the order they were replayed
+    pub fn take_entries(&mut self) -> Vec<TapeEntry> {
+        return std::mem::take(&mut self.entries_);
+    }
+}
+
+impl BookObserver for TradeTape {
+    fn on_before_order(&mut self, book: &Book, order: &md::Order) {
+        if !is_aggressive(book, order) {
+            return;
+        }
+        let pending = self.pending_.entry(order.SecurityID).or_default();
+        if pending.len() == PENDING_PER_BOOK {
+            pending.pop_front();
+        }
+        pending.push_back(Pending {
+            seq: order.ApplSeqNum,
+            best_bid: book.best_bid(),
+            best_ask: book.best_ask(),
+            queue_ahead: TradeTape::reachable(book, order),
+        });
+    }
+
+    fn on_order_executed(&mut self, book: &Book, trade: &md::Trade) {
+        self.add_trade(book, trade);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md::testing::{clock, order, trade};
+    use crate::md::Side;
+    use crate::snapshot_builder::{ClockType, SnapshotBuilder};
+    use std::sync::{Arc, Mutex};
+
+    // 500 at 51000 from before our capture, an ask of 200 behind it and a
+    // bid of 100 at 50800, then a bid taking 600 at 51000 and an ask taking
+    // 50 at 50800 that are both replayed before the first execution
+    fn entries() -> BTreeMap<i64, TapeEntry> {
+        let mut before = Book::new(2290);
+        before.timestamp = clock("09:59:00.000") + 500;
+        before.exchange_time = md::ExchangeTime::from_clock(clock("09:59:00.000"));
+        before.apply_change(Side::Ask, 51000, 500);
+
+        let orders = vec![
+            order(2290, 4, "10:00:00.000", Side::Bid, 50800, 100),
+            order(2290, 5, "10:00:00.000", Side::Ask, 51000, 200),
+            order(2290, 6, "10:00:01.000", Side::Bid, 51000, 600),
+            order(2290, 9, "10:00:01.000", Side::Ask, 50800, 50),
+        ];
+        let trades = vec![
+            trade(2290, 7, "10:00:01.000", 51000, 500, 6, 1),
+            trade(2290, 8, "10:00:01.000", 51000, 100, 6, 5),
+            trade(2290, 10, "10:00:01.000", 50800, 50, 4, 9),
+        ];
+        let orders = orders.into_iter().map(Arc::new).collect();
+        let trades = trades.into_iter().map(Arc::new).collect();
+        let mut builder = SnapshotBuilder::new(orders, trades);
+        builder.set_clock_type(ClockType::Exchange);
+        builder.set_exchange_snapshots(vec![Arc::new(before.to_snapshot())]);
+        let tape = Arc::new(Mutex::new(TradeTape::new()));
+        builder.add_observer(Box::new(tape.clone()));
+        builder.process_until(i64::MAX);
+        let entries = tape.lock().unwrap().take_entries();
+        return entries
+            .into_iter()
+            .map(|entry| (entry.seq, entry))
+            .collect();
+    }
+
+    #[test]
+    fn queue_ahead_includes_unattributed_quantity() {
+        let entries = entries();
+        // never saw the order from before the capture rest
+        assert_eq!(entries[&7].resting_seq, Some(1));
+        assert_eq!(entries[&7].queue_ahead, None);
+        assert_eq!(entries[&7].resting_age_millis, None);
+        assert_eq!(entries[&8].queue_ahead, Some(500));
+        assert_eq!(entries[&8].resting_age_millis, Some(1000));
+        assert_eq!(entries[&10].queue_ahead, Some(0));
+    }
+
+    #[test]
+    fn executions_find_the_book_of_their_aggressive_order() {
+        let entries = entries();
+        for seq in [7, 8] {
+            assert_eq!(entries[&seq].aggressor, md::Aggressor::Buy);
+            assert_eq!(entries[&seq].best_ask, Some((51000, 700)));
+            assert_eq!(entries[&seq].best_bid, Some((50800, 100)));
+            assert_eq!(entries[&seq].spread(), Some(200));
+            assert_eq!(entries[&seq].aggressor_quantity, Some(600));
+        }
+        // the ask came after the bid took its part, not before
+        assert_eq!(entries[&10].aggressor, md::Aggressor::Sell);
+        assert_eq!(entries[&10].best_ask, Some((51000, 100)));
+        assert_eq!(entries[&10].aggressor_quantity, Some(50));
+    }
+}